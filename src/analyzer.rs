@@ -1,23 +1,541 @@
 //! Code analysis module for call graph generation
 
 use crate::config::Config;
-use crate::lsp::LspClient;
-use crate::Result;
+use crate::filter::ExcludeFilter;
+use crate::lsp::{language_id, KnownLanguage};
+use crate::source::{CallGraphSource, Calls, FunctionSymbol};
+use crate::{CallGraphError, Result};
+use futures::stream::{self, StreamExt};
+use petgraph::stable_graph::StableDiGraph;
+use petgraph::Direction;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::{CallHierarchyItem, Position, Range, SymbolKind, Url};
+use tracing::{debug, info, warn};
+use walkdir::WalkDir;
+
+pub use petgraph::stable_graph::NodeIndex;
 
 // ============================================================================
 // Call Graph
 // ============================================================================
 
 /// Call graph representation
+///
+/// Functions are nodes and caller -> callee relations are edges. Node ids
+/// stay valid for the lifetime of the graph.
+#[derive(Debug, Default)]
 pub struct CallGraph {
-    // TODO: Add actual call graph data structures
+    graph: StableDiGraph<FunctionNode, CallEdge>,
+    index: HashMap<SymbolId, NodeIndex>,
+    roots: Vec<NodeIndex>,
+    workspace_root: Option<PathBuf>,
+    failures: Vec<(NodeIndex, String)>,
+}
+
+/// A function or method in the call graph
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionNode {
+    /// The function name as reported by the language server
+    pub name: String,
+    /// Module path of the defining file relative to the workspace, e.g. `api::handlers`
+    pub container: Option<String>,
+    /// The kind of symbol (function, method, constructor, ...)
+    pub kind: SymbolKind,
+    /// Document containing the definition
+    pub uri: Url,
+    /// Range of the whole definition
+    pub range: Range,
+    /// Range of the function name
+    pub selection_range: Range,
+    /// Extra detail from the server, usually the signature
+    pub detail: Option<String>,
+    /// Whether the node stands for a whole external crate or package
+    pub external: bool,
+    /// Opaque server data that must be echoed back in call hierarchy requests
+    pub(crate) data: Option<serde_json::Value>,
+}
+
+/// A caller -> callee relation
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CallEdge {
+    /// Ranges of the individual call expressions inside the caller
+    ///
+    /// Columns count Unicode characters, whatever encoding the server used.
+    pub call_sites: Vec<Range>,
+}
+
+/// Identity of a function across separate LSP responses
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SymbolId {
+    uri: Url,
+    line: u32,
+    character: u32,
+    name: String,
+}
+
+impl FunctionNode {
+    /// Build a node from a call hierarchy item
+    ///
+    /// The container is derived from the file path relative to `workspace_root`.
+    pub fn from_item(item: CallHierarchyItem, workspace_root: Option<&Path>) -> Self {
+        Self {
+            container: module_path(&item.uri, workspace_root),
+            name: item.name,
+            kind: item.kind,
+            uri: item.uri,
+            range: item.range,
+            selection_range: item.selection_range,
+            detail: item.detail,
+            external: false,
+            data: item.data,
+        }
+    }
+
+    /// Build the leaf that represents every function of an external package
+    pub fn external_package(uri: &Url) -> Self {
+        let (name, root) = external_package(uri);
+        Self {
+            name,
+            container: None,
+            kind: SymbolKind::PACKAGE,
+            uri: root,
+            range: Range::default(),
+            selection_range: Range::default(),
+            detail: None,
+            external: true,
+            data: None,
+        }
+    }
+
+    /// Identity used to deduplicate nodes
+    pub fn symbol_id(&self) -> SymbolId {
+        SymbolId {
+            uri: self.uri.clone(),
+            line: self.selection_range.start.line,
+            character: self.selection_range.start.character,
+            name: self.name.clone(),
+        }
+    }
+
+    /// Name prefixed with its container, e.g. `api::handlers::process`
+    pub fn qualified_name(&self) -> String {
+        match &self.container {
+            Some(container) if !container.is_empty() => format!("{}::{}", container, self.name),
+            _ => self.name.clone(),
+        }
+    }
+
+    /// Name of the language the function is written in, if known
+    pub fn language(&self) -> Option<&'static str> {
+        if self.external {
+            return None;
+        }
+        KnownLanguage::for_path(Path::new(self.uri.path())).map(|language| language.name)
+    }
+
+    /// Rebuild the call hierarchy item for follow-up requests
+    pub fn to_item(&self) -> CallHierarchyItem {
+        CallHierarchyItem {
+            name: self.name.clone(),
+            kind: self.kind,
+            tags: None,
+            detail: self.detail.clone(),
+            uri: self.uri.clone(),
+            range: self.range,
+            selection_range: self.selection_range,
+            data: self.data.clone(),
+        }
+    }
+}
+
+impl CallEdge {
+    /// Number of call sites
+    pub fn count(&self) -> usize {
+        self.call_sites.len()
+    }
 }
 
 impl CallGraph {
+    /// Create an empty call graph
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty call graph whose module paths are relative to `root`
+    pub fn with_workspace_root(root: PathBuf) -> Self {
+        Self {
+            workspace_root: Some(root),
+            ..Self::default()
+        }
+    }
+
     /// Analyze code and build call graph
-    pub async fn analyze(_lsp_client: &mut LspClient, _config: &Config) -> Result<Self> {
-        // TODO: Implement call graph analysis
-        Ok(Self {})
+    ///
+    /// Functions and calls come from `source`: language servers, a
+    /// precomputed index, or any other `CallGraphSource`.
+    pub async fn analyze<S>(source: &mut S, config: &Config) -> Result<Self>
+    where
+        S: CallGraphSource + ?Sized,
+    {
+        source.prepare(config).await?;
+
+        let options = TraversalOptions {
+            max_depth: config.max_depth,
+            exclude: ExcludeFilter::new(&config.exclude_patterns)?,
+            include_external: config.analysis.include_external,
+            max_concurrent_requests: config.lsp.max_concurrent_requests.max(1),
+        };
+        let workspace_root = config.workspace_root()?;
+        let mut graph = Self::with_workspace_root(workspace_root.clone());
+        let mut restarts = 0;
+        let roots: Vec<_> = match &config.root_function {
+            // An explicitly requested root is kept even if a pattern matches it
+            Some(name) => resolve_root(&*source, config, name)
+                .await?
+                .into_iter()
+                .map(|root| {
+                    let id = graph.add_item(root);
+                    graph.mark_root(id);
+                    id
+                })
+                .collect(),
+            // Without a root every function in the file or project is a seed
+            None => {
+                let files = analyzed_files(config)?;
+                let items = loop {
+                    match all_functions(&*source, &files).await {
+                        Ok(items) => break items,
+                        Err(e) => recover(source, config, e, &mut restarts).await?,
+                    }
+                };
+                items
+                    .into_iter()
+                    .map(|item| FunctionNode::from_item(item, Some(&workspace_root)))
+                    .filter(|node| !is_external(&node.uri, Some(&workspace_root)))
+                    .filter(|node| !options.exclude.is_excluded(node, Some(&workspace_root)))
+                    .map(|node| graph.add_function(node))
+                    .collect()
+            }
+        };
+
+        let traversals = [
+            (config.direction.includes_callees(), Traversal::Outgoing),
+            (config.direction.includes_callers(), Traversal::Incoming),
+        ];
+        for (_, traversal) in traversals.into_iter().filter(|(enabled, _)| *enabled) {
+            let mut frontier = Frontier::new(&roots);
+            // A crashed server is restarted and the walk resumes where it stopped
+            while let Err(e) =
+                traverse(&*source, &mut graph, &mut frontier, traversal, &options).await
+            {
+                recover(source, config, e, &mut restarts).await?;
+            }
+        }
+
+        info!(
+            "Discovered {} functions and {} calls",
+            graph.node_count(),
+            graph.edge_count()
+        );
+        if !graph.failures.is_empty() {
+            warn!(
+                "Calls of {} functions could not be retrieved; the graph is incomplete",
+                graph.failures.len()
+            );
+        }
+        Ok(graph)
+    }
+
+    // ------------------------------------------------------------------------
+    // Queries
+    // ------------------------------------------------------------------------
+
+    /// Number of functions in the graph
+    pub fn node_count(&self) -> usize {
+        self.graph.node_count()
+    }
+
+    /// Number of distinct caller -> callee relations in the graph
+    pub fn edge_count(&self) -> usize {
+        self.graph.edge_count()
+    }
+
+    /// All functions in insertion order
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeIndex, &FunctionNode)> {
+        self.graph
+            .node_indices()
+            .map(move |id| (id, &self.graph[id]))
+    }
+
+    /// All calls as `(caller, callee, edge)` in insertion order
+    pub fn edges(&self) -> impl Iterator<Item = (NodeIndex, NodeIndex, &CallEdge)> {
+        self.graph.edge_indices().map(move |edge| {
+            let (caller, callee) = self.graph.edge_endpoints(edge).unwrap();
+            (caller, callee, &self.graph[edge])
+        })
+    }
+
+    /// The function with the given id
+    pub fn node(&self, id: NodeIndex) -> Option<&FunctionNode> {
+        self.graph.node_weight(id)
+    }
+
+    /// The call from `caller` to `callee`, if any
+    pub fn edge(&self, caller: NodeIndex, callee: NodeIndex) -> Option<&CallEdge> {
+        self.graph
+            .find_edge(caller, callee)
+            .map(|edge| &self.graph[edge])
+    }
+
+    /// Look up a function by its symbol identity
+    pub fn find(&self, symbol: &SymbolId) -> Option<NodeIndex> {
+        self.index.get(symbol).copied()
+    }
+
+    /// All functions with the given plain or qualified name
+    pub fn find_by_name<'a>(&'a self, name: &'a str) -> impl Iterator<Item = NodeIndex> + 'a {
+        self.nodes()
+            .filter(move |(_, node)| node.name == name || node.qualified_name() == name)
+            .map(|(id, _)| id)
+    }
+
+    /// Functions the analysis started from
+    pub fn roots(&self) -> &[NodeIndex] {
+        &self.roots
+    }
+
+    /// Directory module paths are relative to, if any
+    pub fn workspace_root(&self) -> Option<&Path> {
+        self.workspace_root.as_deref()
+    }
+
+    /// Functions whose calls could not be retrieved, with the reason
+    ///
+    /// Their neighbours are missing from the graph.
+    pub fn failures(&self) -> &[(NodeIndex, String)] {
+        &self.failures
+    }
+
+    /// Whether the function is one of the analysis roots
+    pub fn is_root(&self, id: NodeIndex) -> bool {
+        self.roots.contains(&id)
+    }
+
+    /// Functions calling `id`
+    pub fn callers(&self, id: NodeIndex) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.neighbors(id, Direction::Incoming)
+    }
+
+    /// Functions called by `id`
+    pub fn callees(&self, id: NodeIndex) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.neighbors(id, Direction::Outgoing)
+    }
+
+    /// Underlying petgraph structure for custom algorithms
+    pub fn graph(&self) -> &StableDiGraph<FunctionNode, CallEdge> {
+        &self.graph
+    }
+
+    /// Neighbours sorted by id so iteration order does not depend on petgraph internals
+    fn neighbors(&self, id: NodeIndex, direction: Direction) -> std::vec::IntoIter<NodeIndex> {
+        let mut neighbors: Vec<_> = self.graph.neighbors_directed(id, direction).collect();
+        neighbors.sort();
+        neighbors.dedup();
+        neighbors.into_iter()
+    }
+
+    // ------------------------------------------------------------------------
+    // Construction
+    // ------------------------------------------------------------------------
+
+    /// Insert a function, returning the existing id if it is already present
+    pub fn add_function(&mut self, node: FunctionNode) -> NodeIndex {
+        let symbol = node.symbol_id();
+        if let Some(&id) = self.index.get(&symbol) {
+            return id;
+        }
+        let id = self.graph.add_node(node);
+        self.index.insert(symbol, id);
+        id
+    }
+
+    /// Insert a call hierarchy item as a function node
+    pub fn add_item(&mut self, item: CallHierarchyItem) -> NodeIndex {
+        let node = FunctionNode::from_item(item, self.workspace_root.as_deref());
+        self.add_function(node)
+    }
+
+    /// Mark a function as an analysis root
+    pub fn mark_root(&mut self, id: NodeIndex) {
+        if !self.roots.contains(&id) {
+            self.roots.push(id);
+        }
+    }
+
+    /// Record that the calls of a function could not be retrieved
+    pub fn record_failure(&mut self, id: NodeIndex, reason: impl Into<String>) {
+        self.failures.push((id, reason.into()));
+    }
+
+    /// Record a call, merging call sites if the relation is already known
+    pub fn add_call(&mut self, caller: NodeIndex, callee: NodeIndex, call_sites: Vec<Range>) {
+        match self.graph.find_edge(caller, callee) {
+            Some(edge) => {
+                let edge = &mut self.graph[edge];
+                for range in call_sites {
+                    if !edge.call_sites.contains(&range) {
+                        edge.call_sites.push(range);
+                    }
+                }
+            }
+            None => {
+                self.graph.add_edge(caller, callee, CallEdge { call_sites });
+            }
+        }
+    }
+}
+
+/// Derive a module path such as `api::handlers` from a document URI
+///
+/// The path is taken relative to the workspace root with a leading `src`
+/// directory, the extension and module index files (`mod.rs`, `__init__.py`,
+/// `index.ts`) removed. Without a root only the file stem is used.
+pub(crate) fn module_path(uri: &Url, workspace_root: Option<&Path>) -> Option<String> {
+    let path = uri.to_file_path().ok()?;
+    let relative = workspace_root
+        .and_then(|root| path.strip_prefix(root).ok())
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from(path.file_name().unwrap_or_default()));
+
+    let mut segments: Vec<String> = relative
+        .with_extension("")
+        .components()
+        .filter_map(|component| component.as_os_str().to_str().map(str::to_string))
+        .collect();
+    if segments.len() > 1 && segments.first().is_some_and(|first| first == "src") {
+        segments.remove(0);
+    }
+    if segments.len() > 1
+        && segments
+            .last()
+            .is_some_and(|last| matches!(last.as_str(), "mod" | "__init__" | "index"))
+    {
+        segments.pop();
+    }
+
+    let module = segments.join("::");
+    (!module.is_empty()).then_some(module)
+}
+
+// ============================================================================
+// External Packages
+// ============================================================================
+
+/// Directories that hold installed dependencies even inside the workspace
+const DEPENDENCY_DIRECTORIES: &[&str] = &["node_modules", "site-packages", "dist-packages"];
+
+/// Whether a document lies outside the project's own code
+///
+/// Without a workspace root nothing is considered external.
+fn is_external(uri: &Url, workspace_root: Option<&Path>) -> bool {
+    let Some(root) = workspace_root else {
+        return false;
+    };
+    match uri.to_file_path() {
+        Ok(path) => match path.strip_prefix(root) {
+            Ok(relative) => relative.components().any(|component| {
+                DEPENDENCY_DIRECTORIES.contains(&component.as_os_str().to_string_lossy().as_ref())
+            }),
+            Err(_) => true,
+        },
+        // Virtual documents such as `jdt://` class files
+        Err(_) => true,
+    }
+}
+
+/// Name and root URI of the crate or package containing a document
+///
+/// Recognizes the cargo registry and git checkouts, the Rust standard library,
+/// `node_modules`, Python `site-packages`, the Go module cache and system
+/// include directories. Anything else is named after its parent directory.
+fn external_package(uri: &Url) -> (String, Url) {
+    let Ok(path) = uri.to_file_path() else {
+        let name = uri.host_str().unwrap_or(uri.scheme()).to_string();
+        let root = Url::parse(&format!("{}://{}/", uri.scheme(), name)).unwrap_or(uri.clone());
+        return (name, root);
+    };
+
+    let parts: Vec<String> = path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    let position = |marker: &str| parts.iter().rposition(|part| part == marker);
+    let next = |index: usize| parts.get(index).map(String::as_str);
+
+    let (name, depth) = if let Some(i) = position("node_modules").filter(|&i| next(i + 1).is_some())
+    {
+        match next(i + 1) {
+            Some(scope) if scope.starts_with('@') && next(i + 2).is_some() => {
+                (format!("{}/{}", scope, parts[i + 2]), i + 3)
+            }
+            _ => (parts[i + 1].clone(), i + 2),
+        }
+    } else if let Some(i) = position("site-packages")
+        .or_else(|| position("dist-packages"))
+        .filter(|&i| next(i + 1).is_some())
+    {
+        let package = parts[i + 1].trim_end_matches(".py").to_string();
+        (package, i + 2)
+    } else if let Some(i) = position("registry").filter(|&i| next(i + 1) == Some("src")) {
+        match next(i + 3) {
+            Some(crate_dir) => (strip_version(crate_dir).to_string(), i + 4),
+            None => (parts[i].clone(), i + 1),
+        }
+    } else if let Some(i) = position("checkouts").filter(|&i| next(i + 2).is_some()) {
+        (strip_version(&parts[i + 1]).to_string(), i + 3)
+    } else if let Some(i) = position("rustlib").and_then(|_| position("library")) {
+        match next(i + 1) {
+            Some(crate_name) => (crate_name.to_string(), i + 2),
+            None => (parts[i].clone(), i + 1),
+        }
+    } else if let Some(i) = parts
+        .windows(2)
+        .rposition(|pair| pair[0] == "pkg" && pair[1] == "mod")
+    {
+        // Module paths like `github.com/user/repo@v1.2.3`
+        let start = i + 2;
+        let end = parts[start..]
+            .iter()
+            .position(|part| part.contains('@'))
+            .map_or(parts.len() - 1, |offset| start + offset + 1);
+        let module = parts[start..end].join("/");
+        let module = module.split('@').next().unwrap_or(&module).to_string();
+        (module, end)
+    } else if let Some(i) = position("include").filter(|&i| next(i + 1).is_some()) {
+        let header = Path::new(&parts[i + 1]);
+        let name = header.file_stem().unwrap_or(header.as_os_str());
+        (name.to_string_lossy().into_owned(), i + 2)
+    } else {
+        let depth = parts.len().saturating_sub(1).max(1);
+        (parts[depth - 1].clone(), depth)
+    };
+
+    let root: PathBuf = parts.iter().take(depth).collect();
+    let root = Url::from_directory_path(&root).unwrap_or_else(|_| uri.clone());
+    (name, root)
+}
+
+/// Strip a trailing `-1.2.3` version or `-a1b2c3` checkout hash
+fn strip_version(name: &str) -> &str {
+    match name.rsplit_once('-') {
+        Some((base, suffix))
+            if suffix.starts_with(|c: char| c.is_ascii_digit())
+                || (suffix.len() >= 8 && suffix.chars().all(|c| c.is_ascii_hexdigit())) =>
+        {
+            base
+        }
+        _ => name,
     }
 }
 
@@ -25,13 +543,475 @@ impl CallGraph {
 // Symbol Resolution
 // ============================================================================
 
-// TODO: Implement symbol resolution
+/// How the user identified the root function on the command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RootQuery {
+    /// `name`, `Type::name`, `crate::module::name` or `module.Class.name`
+    Name {
+        qualifier: Vec<String>,
+        name: String,
+    },
+    /// `path/to/file.rs:LINE` or `path/to/file.rs:LINE:COLUMN`, both 1-based
+    Location {
+        path: PathBuf,
+        line: u32,
+        column: Option<u32>,
+    },
+}
+
+impl RootQuery {
+    fn parse(spec: &str) -> Self {
+        // Peel off up to two numeric `:N` suffixes; `::` never produces one
+        let mut parts: Vec<&str> = spec.split(':').collect();
+        let mut numbers = Vec::new();
+        while numbers.len() < 2 && parts.len() > 1 {
+            match parts.last().and_then(|part| part.parse::<u32>().ok()) {
+                Some(number) if number > 0 => {
+                    numbers.insert(0, number);
+                    parts.pop();
+                }
+                _ => break,
+            }
+        }
+        let path = parts.join(":");
+        if !numbers.is_empty() && !path.is_empty() && !path.ends_with(':') {
+            return Self::Location {
+                path: PathBuf::from(path),
+                line: numbers[0],
+                column: numbers.get(1).copied(),
+            };
+        }
+
+        let mut segments: Vec<String> = spec
+            .split("::")
+            .flat_map(|segment| segment.split('.'))
+            .filter(|segment| !segment.is_empty())
+            .map(str::to_string)
+            .collect();
+        if segments
+            .first()
+            .is_some_and(|first| matches!(first.as_str(), "crate" | "self"))
+        {
+            segments.remove(0);
+        }
+        let name = segments.pop().unwrap_or_default();
+        Self::Name {
+            qualifier: segments,
+            name,
+        }
+    }
+}
+
+/// Find the call hierarchy item for the configured root function
+///
+/// Several matching functions are reported as an error rather than guessed.
+async fn resolve_root<S>(source: &S, config: &Config, spec: &str) -> Result<Vec<CallHierarchyItem>>
+where
+    S: CallGraphSource + ?Sized,
+{
+    let workspace_root = config.workspace_root()?;
+    let candidate = match RootQuery::parse(spec) {
+        RootQuery::Location { path, line, column } => {
+            let path = if path.exists() {
+                path
+            } else {
+                workspace_root.join(path)
+            };
+            resolve_location(source, &path, line, column).await?
+        }
+        RootQuery::Name { qualifier, name } => {
+            let candidates = match &config.file_path {
+                Some(file) => source.document_symbols(file).await?,
+                None => match source.workspace_symbols(&name).await? {
+                    Some(candidates) => candidates,
+                    None => {
+                        let mut candidates = Vec::new();
+                        for file in source_files(&workspace_root) {
+                            candidates.extend(source.document_symbols(&file).await?);
+                        }
+                        candidates
+                    }
+                },
+            };
+
+            select_candidate(candidates, &qualifier, &name, spec, &workspace_root)?
+        }
+    };
+
+    debug!(
+        "Resolved root '{}' to {}",
+        spec,
+        candidate.describe(&workspace_root)
+    );
+    let items = source.prepare_item(&candidate).await?;
+    if items.is_empty() {
+        return Err(CallGraphError::AnalysisError(format!(
+            "Language server returned no call hierarchy item for '{spec}'"
+        ))
+        .into());
+    }
+    Ok(items)
+}
+
+/// The innermost function whose declaration encloses a 1-based line and column
+async fn resolve_location<S>(
+    source: &S,
+    path: &Path,
+    line: u32,
+    column: Option<u32>,
+) -> Result<FunctionSymbol>
+where
+    S: CallGraphSource + ?Sized,
+{
+    let line = line - 1;
+    // The column counts characters; the source's ranges may not
+    let position =
+        column.map(|column| source.to_source_position(path, Position::new(line, column - 1)));
+    innermost_candidate(source.document_symbols(path).await?, path, line, position)
+}
+
+/// The one candidate matching a root function name
+///
+/// Several matching functions are reported as an error rather than guessed.
+fn select_candidate(
+    candidates: Vec<FunctionSymbol>,
+    qualifier: &[String],
+    name: &str,
+    spec: &str,
+    workspace_root: &Path,
+) -> Result<FunctionSymbol> {
+    let mut matches: Vec<_> = candidates
+        .into_iter()
+        .filter(|candidate| candidate.matches(qualifier, name, workspace_root))
+        .collect();
+    matches.dedup_by(|a, b| a.uri == b.uri && a.position == b.position);
+
+    match matches.len() {
+        0 => Err(CallGraphError::AnalysisError(format!("Root function '{spec}' not found")).into()),
+        1 => Ok(matches.remove(0)),
+        count => {
+            let listing: Vec<_> = matches
+                .iter()
+                .map(|candidate| format!("  {}", candidate.describe(workspace_root)))
+                .collect();
+            Err(CallGraphError::AnalysisError(format!(
+                "Root function '{spec}' is ambiguous; {count} candidates match:\n{}\n\
+                 Qualify the name (e.g. Type::{name}) or use path/to/file:LINE",
+                listing.join("\n")
+            ))
+            .into())
+        }
+    }
+}
+
+/// The innermost candidate whose declaration encloses a zero-based line, or
+/// a position if a column was given
+fn innermost_candidate(
+    candidates: Vec<FunctionSymbol>,
+    path: &Path,
+    line: u32,
+    position: Option<Position>,
+) -> Result<FunctionSymbol> {
+    let encloses = |range: &Range| match position {
+        Some(position) => range.start <= position && position <= range.end,
+        None => range.start.line <= line && line <= range.end.line,
+    };
+
+    candidates
+        .into_iter()
+        .filter(|candidate| encloses(&candidate.range))
+        .min_by_key(|candidate| {
+            let range = candidate.range;
+            (
+                range.end.line - range.start.line,
+                range.end.character.saturating_sub(range.start.character),
+            )
+        })
+        .ok_or_else(|| {
+            CallGraphError::AnalysisError(format!(
+                "No function found at {}:{}",
+                path.display(),
+                line + 1
+            ))
+            .into()
+        })
+}
+
+/// Restart a source whose server exited, or give up with the error
+///
+/// `restarts` counts the restarts of the whole analysis.
+async fn recover<S>(
+    source: &mut S,
+    config: &Config,
+    error: Box<dyn std::error::Error + Send + Sync>,
+    restarts: &mut u32,
+) -> Result<()>
+where
+    S: CallGraphSource + ?Sized,
+{
+    if !is_server_exit(&*error) || *restarts >= config.lsp.max_restarts {
+        return Err(error);
+    }
+    *restarts += 1;
+    warn!(
+        "{}; restarting ({}/{})",
+        error, restarts, config.lsp.max_restarts
+    );
+    source.restart(config).await
+}
+
+fn is_server_exit(error: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    matches!(error.downcast_ref(), Some(CallGraphError::ServerExited(_)))
+}
+
+fn is_timeout(error: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    matches!(error.downcast_ref(), Some(CallGraphError::Timeout(_)))
+}
+
+/// Call hierarchy items for every function declared in the given files
+///
+/// Files the source cannot handle and functions whose items time out are
+/// skipped with a warning; a server exit is left to the caller to recover.
+async fn all_functions<S>(source: &S, files: &[PathBuf]) -> Result<Vec<CallHierarchyItem>>
+where
+    S: CallGraphSource + ?Sized,
+{
+    let mut items = Vec::new();
+    for file in files {
+        let candidates = match source.document_symbols(file).await {
+            Ok(candidates) => candidates,
+            Err(e) if is_server_exit(&*e) => return Err(e),
+            Err(e) => {
+                warn!("Skipping {}: {}", file.display(), e);
+                continue;
+            }
+        };
+        for candidate in candidates {
+            match source.prepare_item(&candidate).await {
+                Ok(prepared) => items.extend(prepared),
+                Err(e) if is_timeout(&*e) => warn!("Skipping {}: {}", candidate.name, e),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    info!("Found {} functions in {} files", items.len(), files.len());
+    Ok(items)
+}
 
 // ============================================================================
 // Code Traversal
 // ============================================================================
 
-// TODO: Implement code traversal
+/// Directories that never contain project sources worth analyzing
+const IGNORED_DIRECTORIES: &[&str] = &["target", "node_modules", "__pycache__", "build", "dist"];
+
+/// Source files below `root` in a stable order
+///
+/// Hidden and build output directories are skipped, as are files whose
+/// language is unknown.
+pub(crate) fn source_files(root: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<_> = WalkDir::new(root)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            let name = entry.file_name().to_string_lossy();
+            let ignored = name.starts_with('.')
+                || (entry.file_type().is_dir() && IGNORED_DIRECTORIES.contains(&name.as_ref()));
+            entry.depth() == 0 || !ignored
+        })
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                warn!("Failed to read directory entry: {}", e);
+                None
+            }
+        })
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .filter(|path| language_id(path) != "plaintext")
+        .collect();
+    files.sort();
+    files
+}
+
+/// The configured file, or every source file of the workspace
+pub(crate) fn analyzed_files(config: &Config) -> Result<Vec<PathBuf>> {
+    Ok(match &config.file_path {
+        Some(file) => vec![file.clone()],
+        None => source_files(&config.workspace_root()?),
+    })
+}
+
+/// Which side of the call hierarchy a traversal follows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Traversal {
+    Outgoing,
+    Incoming,
+}
+
+impl Traversal {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Outgoing => "outgoing",
+            Self::Incoming => "incoming",
+        }
+    }
+}
+
+/// Settings shared by the traversals of one analysis
+struct TraversalOptions {
+    max_depth: Option<usize>,
+    exclude: ExcludeFilter,
+    /// Expand code outside the workspace instead of collapsing it per package
+    include_external: bool,
+    /// Call requests in flight at once
+    max_concurrent_requests: usize,
+}
+
+/// Functions a traversal has yet to expand, with their distance from a root
+struct Frontier {
+    queue: VecDeque<(NodeIndex, usize)>,
+    expanded: HashSet<NodeIndex>,
+}
+
+impl Frontier {
+    fn new(roots: &[NodeIndex]) -> Self {
+        Self {
+            queue: roots.iter().map(|&root| (root, 0)).collect(),
+            expanded: HashSet::new(),
+        }
+    }
+
+    /// Next function to expand, skipping expanded ones and those at `max_depth`
+    fn pop(&mut self, max_depth: Option<usize>) -> Option<(NodeIndex, usize)> {
+        while let Some((id, depth)) = self.queue.pop_front() {
+            if max_depth.is_none_or(|max| depth < max) && self.expanded.insert(id) {
+                return Some((id, depth));
+            }
+        }
+        None
+    }
+
+    /// Every queued function `pop` would return, in queue order
+    fn pop_all(&mut self, max_depth: Option<usize>) -> Vec<(NodeIndex, usize)> {
+        std::iter::from_fn(|| self.pop(max_depth)).collect()
+    }
+
+    /// Put back functions whose expansion failed so they are expanded next
+    fn retry(&mut self, functions: &[(NodeIndex, usize)]) {
+        for &(id, depth) in functions.iter().rev() {
+            self.expanded.remove(&id);
+            self.queue.push_front((id, depth));
+        }
+    }
+}
+
+/// Breadth-first walk over calls in one direction until the frontier is empty
+///
+/// The calls of every function queued at one depth are requested
+/// concurrently, but applied in queue order so the graph does not depend on
+/// which response arrives first. Nodes at `max_depth` calls away from a root
+/// are added but not expanded. Edges always point from caller to callee
+/// regardless of the direction. On error the frontier still holds the
+/// unexpanded functions, so the walk can be resumed by calling this again.
+async fn traverse<S>(
+    source: &S,
+    graph: &mut CallGraph,
+    frontier: &mut Frontier,
+    traversal: Traversal,
+    options: &TraversalOptions,
+) -> Result<()>
+where
+    S: CallGraphSource + ?Sized,
+{
+    loop {
+        let batch = frontier.pop_all(options.max_depth);
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let requests: Vec<_> = batch
+            .iter()
+            .map(|&(id, _)| {
+                let node = &graph.graph[id];
+                debug!("Expanding {:?} calls of {}", traversal, node.name);
+                node.to_item()
+            })
+            .collect();
+        let mut responses = stream::iter(requests)
+            .map(|item| async move {
+                match traversal {
+                    Traversal::Outgoing => source.outgoing_calls(&item).await,
+                    Traversal::Incoming => source.incoming_calls(&item).await,
+                }
+            })
+            .buffered(options.max_concurrent_requests);
+
+        let mut expanded = 0;
+        while let Some(neighbours) = responses.next().await {
+            let (id, depth) = batch[expanded];
+            expanded += 1;
+            let neighbours = match neighbours {
+                Ok(neighbours) => neighbours,
+                // A hung request only costs this function's calls
+                Err(e) if is_timeout(&*e) => {
+                    warn!(
+                        "Skipping {} calls of {}: {}",
+                        traversal.label(),
+                        graph.graph[id].name,
+                        e
+                    );
+                    graph.record_failure(id, e.to_string());
+                    continue;
+                }
+                Err(e) => {
+                    frontier.retry(&batch[expanded - 1..]);
+                    return Err(e);
+                }
+            };
+
+            add_neighbours(graph, frontier, (id, depth), neighbours, traversal, options);
+        }
+    }
+}
+
+/// Add the callees or callers of an expanded function and queue them
+fn add_neighbours(
+    graph: &mut CallGraph,
+    frontier: &mut Frontier,
+    (id, depth): (NodeIndex, usize),
+    neighbours: Calls,
+    traversal: Traversal,
+    options: &TraversalOptions,
+) {
+    for (neighbour, call_sites) in neighbours {
+        // Excluded functions are neither added nor expanded
+        let mut neighbour = FunctionNode::from_item(neighbour, graph.workspace_root());
+        if options
+            .exclude
+            .is_excluded(&neighbour, graph.workspace_root())
+        {
+            debug!("Excluding {}", neighbour.qualified_name());
+            continue;
+        }
+
+        // Dependencies become a single unexpanded leaf per package
+        let collapse =
+            !options.include_external && is_external(&neighbour.uri, graph.workspace_root());
+        if collapse {
+            neighbour = FunctionNode::external_package(&neighbour.uri);
+        }
+
+        let neighbour = graph.add_function(neighbour);
+        match traversal {
+            Traversal::Outgoing => graph.add_call(id, neighbour, call_sites),
+            Traversal::Incoming => graph.add_call(neighbour, id, call_sites),
+        }
+        if !collapse {
+            frontier.queue.push_back((neighbour, depth + 1));
+        }
+    }
+}
 
 // ============================================================================
 // Tests
@@ -40,32 +1020,1286 @@ impl CallGraph {
 #[cfg(test)]
 mod tests {
     use super::*;
-    #[test]
-    fn test_call_graph_creation() {
-        let _call_graph = CallGraph {};
-        // Just verify it can be created without panicking
-        assert!(true);
-    }
+    use crate::config::CallDirection;
+    use crate::index::Index;
+    use crate::lsp::test_support::{connect_client, restartable_client};
+    use crate::lsp::LspClient;
+    use futures::future::BoxFuture;
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tower_lsp::jsonrpc;
 
-    #[test]
-    fn test_call_graph_analyze_placeholder() {
-        // This is a placeholder test for the analyze function
-        // Once LSP implementation is complete, this should be expanded
-        let _config = Config {
-            file_path: None,
+    fn test_config(file_path: Option<std::path::PathBuf>) -> Config {
+        Config {
+            file_path,
             project_path: None,
             output_path: "test.dot".to_string(),
             root_function: None,
             max_depth: None,
+            direction: crate::config::CallDirection::Callees,
             exclude_patterns: vec![],
             lsp_server_command: None,
+            record_lsp: None,
+            replay_lsp: None,
+            index: None,
             verbose: false,
             analysis: crate::config::AnalysisConfig::default(),
             output: crate::config::OutputConfig::default(),
-            lsp: crate::config::LspConfig::default(),
+            // The fake servers never report progress
+            lsp: crate::config::LspConfig {
+                readiness_timeout_seconds: 0,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn item_json(uri: &str, name: &str, line: u32) -> Value {
+        let range = json!({
+            "start": {"line": line, "character": 3},
+            "end": {"line": line, "character": 3 + name.len()}
+        });
+        json!({
+            "name": name,
+            "kind": 12,
+            "uri": uri,
+            "range": range,
+            "selectionRange": range
+        })
+    }
+
+    fn symbol_json(name: &str, line: u32) -> Value {
+        json!({
+            "name": name,
+            "kind": 12,
+            "range": {"start": {"line": line, "character": 0}, "end": {"line": line + 3, "character": 1}},
+            "selectionRange": {"start": {"line": line, "character": 3}, "end": {"line": line, "character": 3 + name.len()}}
+        })
+    }
+
+    /// Serve a fixed graph: main -> helper, main -> process, process -> helper
+    fn fixture_handler(uri: String) -> impl FnMut(&str, Value) -> jsonrpc::Result<Value> {
+        move |method, params| {
+            let call = |name: &str, line: u32, at: u32| {
+                json!({
+                    "to": item_json(&uri, name, line),
+                    "fromRanges": [{
+                        "start": {"line": at, "character": 4},
+                        "end": {"line": at, "character": 10}
+                    }]
+                })
+            };
+            Ok(match method {
+                "initialize" => json!({"capabilities": {
+                    "callHierarchyProvider": true,
+                    "documentSymbolProvider": true
+                }}),
+                "textDocument/documentSymbol" => json!([
+                    symbol_json("main", 0),
+                    symbol_json("helper", 5),
+                    symbol_json("process", 9)
+                ]),
+                "textDocument/prepareCallHierarchy" => {
+                    let name = match params["position"]["line"].as_u64() {
+                        Some(5) => "helper",
+                        Some(9) => "process",
+                        _ => "main",
+                    };
+                    let line = params["position"]["line"].as_u64().unwrap() as u32;
+                    json!([item_json(&uri, name, line)])
+                }
+                "callHierarchy/outgoingCalls" => match params["item"]["name"].as_str() {
+                    Some("main") => json!([call("helper", 5, 1), call("process", 9, 2)]),
+                    Some("process") => json!([call("helper", 5, 10)]),
+                    _ => json!([]),
+                },
+                "callHierarchy/incomingCalls" => {
+                    let caller = |name: &str, line: u32, at: u32| {
+                        let mut call = call(name, line, at);
+                        call["from"] = call["to"].take();
+                        call
+                    };
+                    match params["item"]["name"].as_str() {
+                        Some("helper") => json!([caller("main", 0, 1), caller("process", 9, 10)]),
+                        Some("process") => json!([caller("main", 0, 2)]),
+                        _ => json!([]),
+                    }
+                }
+                "shutdown" => Value::Null,
+                _ => return Err(jsonrpc::Error::method_not_found()),
+            })
+        }
+    }
+
+    async fn analyze_fixture(max_depth: Option<usize>) -> CallGraph {
+        analyze_fixture_from("main", CallDirection::Callees, max_depth).await
+    }
+
+    async fn analyze_fixture_from(
+        root: &str,
+        direction: CallDirection,
+        max_depth: Option<usize>,
+    ) -> CallGraph {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("main.rs");
+        std::fs::write(&file, "fn main() {}").unwrap();
+        let uri = Url::from_file_path(file.canonicalize().unwrap()).unwrap();
+
+        let (mut client, server) = connect_client();
+        server.serve(fixture_handler(uri.to_string()));
+        client.initialize(temp_dir.path()).await.unwrap();
+
+        let mut config = test_config(Some(file));
+        config.root_function = Some(root.to_string());
+        config.direction = direction;
+        config.max_depth = max_depth;
+        CallGraph::analyze(&mut client, &config).await.unwrap()
+    }
+
+    #[test]
+    fn test_call_graph_creation() {
+        let call_graph = CallGraph::new();
+        assert_eq!(call_graph.node_count(), 0);
+        assert_eq!(call_graph.edge_count(), 0);
+    }
+
+    #[test]
+    fn test_add_item_deduplicates_and_merges_calls() {
+        let uri = "file:///src/main.rs";
+        let main: CallHierarchyItem = serde_json::from_value(item_json(uri, "main", 0)).unwrap();
+        let helper: CallHierarchyItem =
+            serde_json::from_value(item_json(uri, "helper", 5)).unwrap();
+        let site = |line| Range::new(Position::new(line, 0), Position::new(line, 6));
+
+        let mut graph = CallGraph::new();
+        let a = graph.add_item(main.clone());
+        graph.mark_root(a);
+        let b = graph.add_item(helper);
+        assert_eq!(graph.add_item(main), a);
+        graph.add_call(a, b, vec![site(1)]);
+        graph.add_call(a, b, vec![site(1), site(2)]);
+
+        assert_eq!(graph.node_count(), 2);
+        assert_eq!(graph.edge_count(), 1);
+        let edge = graph.edge(a, b).unwrap();
+        assert_eq!(edge.call_sites, vec![site(1), site(2)]);
+        assert_eq!(edge.count(), 2);
+        assert!(graph.is_root(a));
+        assert!(!graph.is_root(b));
+        assert_eq!(graph.callees(a).collect::<Vec<_>>(), vec![b]);
+        assert_eq!(graph.callers(b).collect::<Vec<_>>(), vec![a]);
+        assert_eq!(graph.find_by_name("helper").collect::<Vec<_>>(), vec![b]);
+        let helper_id = graph.node(b).unwrap().symbol_id();
+        assert_eq!(graph.find(&helper_id), Some(b));
+    }
+
+    #[test]
+    fn test_function_node_roundtrips_item() {
+        let mut item: CallHierarchyItem =
+            serde_json::from_value(item_json("file:///project/src/api/mod.rs", "handle", 2))
+                .unwrap();
+        item.detail = Some("fn handle(req: Request)".to_string());
+        item.data = Some(json!({"opaque": 1}));
+
+        let node = FunctionNode::from_item(item.clone(), Some(Path::new("/project")));
+        assert_eq!(node.container.as_deref(), Some("api"));
+        assert_eq!(node.qualified_name(), "api::handle");
+        assert_eq!(node.to_item(), item);
+    }
+
+    #[test]
+    fn test_module_path_from_uri() {
+        let root = Path::new("/project");
+        let uri = |path: &str| Url::parse(&format!("file://{path}")).unwrap();
+
+        assert_eq!(
+            module_path(&uri("/project/src/main.rs"), Some(root)).as_deref(),
+            Some("main")
+        );
+        assert_eq!(
+            module_path(&uri("/project/src/api/handlers.rs"), Some(root)).as_deref(),
+            Some("api::handlers")
+        );
+        assert_eq!(
+            module_path(&uri("/project/pkg/__init__.py"), Some(root)).as_deref(),
+            Some("pkg")
+        );
+        assert_eq!(
+            module_path(&uri("/elsewhere/util.rs"), Some(root)).as_deref(),
+            Some("util")
+        );
+    }
+
+    #[test]
+    fn test_external_package_names() {
+        let package = |path: &str| {
+            let (name, root) = external_package(&Url::from_file_path(path).unwrap());
+            (name, root.path().to_string())
+        };
+
+        assert_eq!(
+            package("/home/u/.cargo/registry/src/index.crates.io-6f17d22bba15001f/serde-1.0.210/src/de.rs"),
+            (
+                "serde".to_string(),
+                "/home/u/.cargo/registry/src/index.crates.io-6f17d22bba15001f/serde-1.0.210/"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            package(
+                "/home/u/.rustup/toolchains/stable/lib/rustlib/src/rust/library/core/src/option.rs"
+            )
+            .0,
+            "core"
+        );
+        assert_eq!(
+            package("/project/node_modules/@types/node/fs.d.ts"),
+            (
+                "@types/node".to_string(),
+                "/project/node_modules/@types/node/".to_string()
+            )
+        );
+        assert_eq!(
+            package("/usr/lib/python3/site-packages/requests/api.py").0,
+            "requests"
+        );
+        assert_eq!(
+            package("/home/u/go/pkg/mod/github.com/pkg/errors@v0.9.1/errors.go").0,
+            "github.com/pkg/errors"
+        );
+        assert_eq!(package("/usr/include/stdio.h").0, "stdio");
+        assert_eq!(package("/opt/lib/zlib/inflate.c").0, "zlib");
+    }
+
+    #[test]
+    fn test_is_external() {
+        let root = Some(Path::new("/project"));
+        let uri = |path: &str| Url::from_file_path(path).unwrap();
+
+        assert!(!is_external(&uri("/project/src/main.rs"), root));
+        assert!(is_external(
+            &uri("/project/node_modules/react/index.js"),
+            root
+        ));
+        assert!(is_external(&uri("/usr/include/stdio.h"), root));
+        assert!(is_external(
+            &Url::parse("jdt://contents/rt.jar").unwrap(),
+            root
+        ));
+        assert!(!is_external(&uri("/usr/include/stdio.h"), None));
+    }
+
+    /// main calls two functions of the serde crate and one of core
+    async fn analyze_with_dependencies(include_external: bool) -> CallGraph {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("main.rs");
+        std::fs::write(&file, "fn main() {}").unwrap();
+        let uri = Url::from_file_path(file.canonicalize().unwrap()).unwrap();
+
+        let serde = "file:///registry/src/index/serde-1.0.0/src";
+        let (mut client, server) = connect_client();
+        server.serve(move |method, params| {
+            let call =
+                |uri: &str, name: &str| json!({"to": item_json(uri, name, 0), "fromRanges": []});
+            Ok(match method {
+                "initialize" => json!({"capabilities": {"callHierarchyProvider": true}}),
+                "textDocument/documentSymbol" => json!([symbol_json("main", 0)]),
+                "textDocument/prepareCallHierarchy" => json!([item_json(uri.as_str(), "main", 0)]),
+                "callHierarchy/outgoingCalls" => match params["item"]["name"].as_str() {
+                    Some("main") => json!([
+                        call(&format!("{serde}/de.rs"), "deserialize"),
+                        call(&format!("{serde}/ser.rs"), "serialize"),
+                        call("file:///rustlib/src/rust/library/core/src/fmt.rs", "fmt")
+                    ]),
+                    Some("serialize") => json!([call(&format!("{serde}/ser.rs"), "write")]),
+                    _ => json!([]),
+                },
+                _ => return Err(jsonrpc::Error::method_not_found()),
+            })
+        });
+        client.initialize(temp_dir.path()).await.unwrap();
+
+        let mut config = test_config(Some(file));
+        config.root_function = Some("main".to_string());
+        config.analysis.include_external = include_external;
+        CallGraph::analyze(&mut client, &config).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_analyze_collapses_external_packages() {
+        let graph = analyze_with_dependencies(false).await;
+        let names: HashSet<_> = graph.nodes().map(|(_, node)| node.name.as_str()).collect();
+        assert_eq!(names, HashSet::from(["main", "serde", "core"]));
+        assert_eq!(graph.nodes().filter(|(_, node)| node.external).count(), 2);
+        assert_eq!(graph.edge_count(), 2);
+
+        let serde = graph.find_by_name("serde").next().unwrap();
+        assert_eq!(graph.callees(serde).count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_expands_external_code_when_included() {
+        let graph = analyze_with_dependencies(true).await;
+        assert_eq!(graph.node_count(), 5);
+        assert_eq!(graph.edge_count(), 4);
+        assert!(graph.nodes().all(|(_, node)| !node.external));
+    }
+
+    #[tokio::test]
+    async fn test_analyze_walks_outgoing_calls() {
+        let graph = analyze_fixture(None).await;
+
+        let names: Vec<_> = graph.nodes().map(|(_, node)| node.name.as_str()).collect();
+        assert_eq!(names, vec!["main", "helper", "process"]);
+        assert_eq!(graph.edge_count(), 3);
+        assert_eq!(graph.roots(), &[NodeIndex::new(0)]);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_honors_max_depth() {
+        let graph = analyze_fixture(Some(1)).await;
+
+        // process is reached but not expanded, so process -> helper is missing
+        assert_eq!(graph.node_count(), 3);
+        assert_eq!(graph.edge_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_merges_languages_from_several_servers() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        std::fs::write(root.join("main.rs"), "fn main() {}").unwrap();
+        std::fs::write(root.join("gen.py"), "def generate(): pass").unwrap();
+
+        // Each server only knows its own file and calls
+        let language_server = |file: &str, caller: &str, callee: &str| {
+            let uri = Url::from_file_path(root.join(file)).unwrap().to_string();
+            let (caller, callee) = (caller.to_string(), callee.to_string());
+            move |method: &str, params: Value| {
+                Ok(match method {
+                    "initialize" => json!({"capabilities": {
+                        "callHierarchyProvider": true,
+                        "documentSymbolProvider": true
+                    }}),
+                    "textDocument/documentSymbol" => {
+                        assert_eq!(params["textDocument"]["uri"], json!(uri));
+                        json!([symbol_json(&caller, 0)])
+                    }
+                    "textDocument/prepareCallHierarchy" => json!([item_json(&uri, &caller, 0)]),
+                    "callHierarchy/outgoingCalls" if params["item"]["name"] == json!(caller) => {
+                        json!([{"to": item_json(&uri, &callee, 5), "fromRanges": []}])
+                    }
+                    "callHierarchy/outgoingCalls" => json!([]),
+                    _ => return Err(jsonrpc::Error::method_not_found()),
+                })
+            }
         };
 
-        // This will be tested properly once LSP implementation is complete
-        assert!(true);
+        let mut client = LspClient::default();
+        let (rust, rust_server) = crate::lsp::test_support::connect();
+        let (python, python_server) = crate::lsp::test_support::connect();
+        rust_server.serve(language_server("main.rs", "main", "helper"));
+        python_server.serve(language_server("gen.py", "generate", "render"));
+        client.add_server(Some("rust"), rust);
+        client.add_server(Some("python"), python);
+        client.initialize(&root).await.unwrap();
+
+        let mut config = test_config(None);
+        config.project_path = Some(root.clone());
+        let graph = CallGraph::analyze(&mut client, &config).await.unwrap();
+
+        let languages: Vec<_> = graph
+            .nodes()
+            .map(|(_, node)| (node.name.as_str(), node.language()))
+            .collect();
+        assert_eq!(languages.len(), 4);
+        assert!(languages.contains(&("main", Some("rust"))));
+        assert!(languages.contains(&("render", Some("python"))));
+        assert_eq!(graph.edge_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_continues_after_request_timeout() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("main.rs");
+        std::fs::write(&file, "fn main() {}").unwrap();
+        let uri = Url::from_file_path(file.canonicalize().unwrap()).unwrap();
+
+        // The server hangs on the outgoing calls of `process`
+        let (mut connection, server) = crate::lsp::test_support::connect();
+        connection.set_request_timeout(Some(std::time::Duration::from_millis(50)));
+        let mut fixture = fixture_handler(uri.to_string());
+        server.serve_some(move |method, params| {
+            let hangs = method == "callHierarchy/outgoingCalls"
+                && params["item"]["name"] == json!("process");
+            (!hangs).then(|| fixture(method, params))
+        });
+        let mut client = LspClient::from_connection(connection);
+        client.initialize(temp_dir.path()).await.unwrap();
+
+        let mut config = test_config(Some(file));
+        config.root_function = Some("main".to_string());
+        let graph = CallGraph::analyze(&mut client, &config).await.unwrap();
+
+        assert_eq!(graph.node_count(), 3);
+        assert_eq!(graph.edge_count(), 2);
+        let [(failed, reason)] = graph.failures() else {
+            panic!("expected one failure, got {:?}", graph.failures());
+        };
+        assert_eq!(graph.node(*failed).unwrap().name, "process");
+        assert!(reason.contains("callHierarchy/outgoingCalls"));
+    }
+
+    async fn analyze_with_crashing_server(max_restarts: u32) -> Result<CallGraph> {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("main.rs");
+        std::fs::write(&file, "fn main() {}").unwrap();
+        let uri = Url::from_file_path(file.canonicalize().unwrap()).unwrap();
+
+        // The first server dies while fetching the calls of `process`
+        let mut client = restartable_client(move |start, server| {
+            let mut fixture = fixture_handler(uri.to_string());
+            server.serve_until(
+                move |method, params| Some(fixture(method, params)),
+                move |method, params| {
+                    start == 0
+                        && method == "callHierarchy/outgoingCalls"
+                        && params["item"]["name"] == json!("process")
+                },
+            );
+        })
+        .await;
+        client.initialize(temp_dir.path()).await.unwrap();
+
+        let mut config = test_config(Some(file));
+        config.root_function = Some("main".to_string());
+        config.lsp.max_restarts = max_restarts;
+        CallGraph::analyze(&mut client, &config).await
+    }
+
+    #[tokio::test]
+    async fn test_analyze_resumes_after_server_restart() {
+        let graph = analyze_with_crashing_server(1).await.unwrap();
+
+        assert_eq!(graph.node_count(), 3);
+        assert_eq!(graph.edge_count(), 3);
+        assert!(graph.failures().is_empty());
+        let process = graph.find_by_name("process").next().unwrap();
+        let helper = graph.find_by_name("helper").next().unwrap();
+        assert!(graph.edge(process, helper).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_analyze_fails_when_restarts_are_disabled() {
+        let error = analyze_with_crashing_server(0).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(CallGraphError::ServerExited(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_analyze_skips_seeds_whose_items_time_out() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("main.rs");
+        std::fs::write(&file, "fn main() {}").unwrap();
+        let uri = Url::from_file_path(file.canonicalize().unwrap()).unwrap();
+
+        // The server hangs on preparing `process`
+        let (mut connection, server) = crate::lsp::test_support::connect();
+        connection.set_request_timeout(Some(std::time::Duration::from_millis(50)));
+        let mut fixture = fixture_handler(uri.to_string());
+        server.serve_some(move |method, params| {
+            let hangs = method == "textDocument/prepareCallHierarchy"
+                && params["position"]["line"] == json!(9);
+            (!hangs).then(|| fixture(method, params))
+        });
+        let mut client = LspClient::from_connection(connection);
+        client.initialize(temp_dir.path()).await.unwrap();
+
+        let graph = CallGraph::analyze(&mut client, &test_config(Some(file)))
+            .await
+            .unwrap();
+
+        // `process` is still reached as a callee of `main`
+        assert_eq!(graph.node_count(), 3);
+        assert_eq!(graph.edge_count(), 3);
+        let process = graph.find_by_name("process").next().unwrap();
+        assert!(!graph.is_root(process));
+    }
+
+    #[tokio::test]
+    async fn test_analyze_restarts_server_that_exits_while_seeding() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("main.rs");
+        std::fs::write(&file, "fn main() {}").unwrap();
+        let uri = Url::from_file_path(file.canonicalize().unwrap()).unwrap();
+
+        // The first server dies while preparing `helper`
+        let mut client = restartable_client(move |start, server| {
+            let mut fixture = fixture_handler(uri.to_string());
+            server.serve_until(
+                move |method, params| Some(fixture(method, params)),
+                move |method, params| {
+                    start == 0
+                        && method == "textDocument/prepareCallHierarchy"
+                        && params["position"]["line"] == json!(5)
+                },
+            );
+        })
+        .await;
+        client.initialize(temp_dir.path()).await.unwrap();
+
+        let mut config = test_config(Some(file));
+        config.lsp.max_restarts = 1;
+        let graph = CallGraph::analyze(&mut client, &config).await.unwrap();
+
+        assert_eq!(graph.node_count(), 3);
+        assert_eq!(graph.edge_count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_skips_excluded_functions() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("main.rs");
+        std::fs::write(&file, "fn main() {}").unwrap();
+        let uri = Url::from_file_path(file.canonicalize().unwrap()).unwrap();
+
+        let (mut client, server) = connect_client();
+        server.serve(fixture_handler(uri.to_string()));
+        client.initialize(temp_dir.path()).await.unwrap();
+
+        let mut config = test_config(Some(file));
+        config.root_function = Some("main".to_string());
+        config.exclude_patterns = vec!["proc*".to_string()];
+        let graph = CallGraph::analyze(&mut client, &config).await.unwrap();
+
+        assert_eq!(graph.find_by_name("process").count(), 0);
+        assert_eq!(graph.node_count(), 2);
+        assert_eq!(graph.edge_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_callers_walks_incoming_calls() {
+        let graph = analyze_fixture_from("helper", CallDirection::Callers, None).await;
+
+        let names: Vec<_> = graph.nodes().map(|(_, node)| node.name.as_str()).collect();
+        assert_eq!(names, vec!["helper", "main", "process"]);
+        // Edges still point from caller to callee
+        let edges: Vec<_> = graph
+            .edges()
+            .map(|(caller, callee, _)| (caller.index(), callee.index()))
+            .collect();
+        assert_eq!(edges, vec![(1, 0), (2, 0), (1, 2)]);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_both_directions() {
+        let graph = analyze_fixture_from("process", CallDirection::Both, None).await;
+
+        let names: Vec<_> = graph.nodes().map(|(_, node)| node.name.as_str()).collect();
+        assert_eq!(names, vec!["process", "helper", "main"]);
+        // process -> helper, main -> process
+        assert_eq!(graph.edge_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_without_root_seeds_every_function() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("main.rs");
+        std::fs::write(&file, "fn main() {}").unwrap();
+        std::fs::write(temp_dir.path().join("notes.txt"), "not code").unwrap();
+        let uri = Url::from_file_path(file.canonicalize().unwrap()).unwrap();
+
+        let (mut client, server) = connect_client();
+        server.serve(fixture_handler(uri.to_string()));
+        client.initialize(temp_dir.path()).await.unwrap();
+
+        let mut config = test_config(None);
+        config.project_path = Some(temp_dir.path().to_path_buf());
+        let graph = CallGraph::analyze(&mut client, &config).await.unwrap();
+
+        assert_eq!(graph.node_count(), 3);
+        assert_eq!(graph.edge_count(), 3);
+        // Seeds are not highlighted as roots
+        assert!(graph.roots().is_empty());
+    }
+
+    #[test]
+    fn test_root_query_parsing() {
+        assert_eq!(
+            RootQuery::parse("crate::parser::Parser::new"),
+            RootQuery::Name {
+                qualifier: vec!["parser".to_string(), "Parser".to_string()],
+                name: "new".to_string(),
+            }
+        );
+        assert_eq!(
+            RootQuery::parse("main"),
+            RootQuery::Name {
+                qualifier: vec![],
+                name: "main".to_string(),
+            }
+        );
+        assert_eq!(
+            RootQuery::parse("src/lib.rs:42"),
+            RootQuery::Location {
+                path: PathBuf::from("src/lib.rs"),
+                line: 42,
+                column: None,
+            }
+        );
+        assert_eq!(
+            RootQuery::parse("src/lib.rs:42:5"),
+            RootQuery::Location {
+                path: PathBuf::from("src/lib.rs"),
+                line: 42,
+                column: Some(5),
+            }
+        );
+    }
+
+    /// Serve one file with `Foo::new` on line 1 and `Bar::new` on line 6
+    async fn resolve_in_two_impls(root: &str) -> Result<Vec<CallHierarchyItem>> {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("lib.rs");
+        std::fs::write(&file, "").unwrap();
+        let uri = Url::from_file_path(file.canonicalize().unwrap()).unwrap();
+
+        let (mut client, server) = connect_client();
+        server.serve(move |method, params| {
+            let impl_json = |name: &str, line: u32| {
+                let mut symbol = symbol_json(name, line);
+                symbol["kind"] = json!(19);
+                symbol["range"]["end"]["line"] = json!(line + 4);
+                symbol["children"] = json!([symbol_json("new", line + 1)]);
+                symbol
+            };
+            Ok(match method {
+                "initialize" => json!({"capabilities": {
+                    "callHierarchyProvider": true,
+                    "documentSymbolProvider": true
+                }}),
+                "textDocument/documentSymbol" => {
+                    json!([impl_json("impl Foo", 0), impl_json("impl Bar", 5)])
+                }
+                "textDocument/prepareCallHierarchy" => {
+                    let line = params["position"]["line"].as_u64().unwrap() as u32;
+                    json!([item_json(uri.as_str(), "new", line)])
+                }
+                _ => return Err(jsonrpc::Error::method_not_found()),
+            })
+        });
+        client.initialize(temp_dir.path()).await.unwrap();
+
+        let config = test_config(Some(file));
+        resolve_root(&client, &config, root).await
+    }
+
+    #[tokio::test]
+    async fn test_resolve_root_reports_ambiguous_names() {
+        let error = resolve_in_two_impls("new").await.unwrap_err().to_string();
+        assert!(error.contains("ambiguous"));
+        assert!(error.contains("lib.rs:2  lib::Foo::new"));
+        assert!(error.contains("lib.rs:7  lib::Bar::new"));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_root_by_qualified_name_and_location() {
+        let items = resolve_in_two_impls("Bar::new").await.unwrap();
+        assert_eq!(items[0].selection_range.start.line, 6);
+
+        let items = resolve_in_two_impls("lib.rs:3").await.unwrap();
+        assert_eq!(items[0].selection_range.start.line, 1);
+
+        let error = resolve_in_two_impls("Baz::new").await.unwrap_err();
+        assert!(error.to_string().contains("not found"));
+    }
+
+    #[tokio::test]
+    async fn test_columns_respect_negotiated_position_encoding() {
+        // `é` takes two bytes, so UTF-8 offsets run ahead of the columns
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("main.rs");
+        std::fs::write(&file, "fn é() {} fn main() { é(); }\n").unwrap();
+        let uri = Url::from_file_path(file.canonicalize().unwrap()).unwrap();
+
+        let bytes = |start: u32, end: u32| json!({"start": {"line": 0, "character": start}, "end": {"line": 0, "character": end}});
+        let function = |name: &str, range: Value| json!({"name": name, "kind": 12, "uri": uri, "range": range, "selectionRange": range});
+        let (e, main) = (function("é", bytes(0, 10)), function("main", bytes(11, 30)));
+
+        let (mut client, server) = connect_client();
+        server.serve(move |method, params| {
+            Ok(match method {
+                "initialize" => json!({"capabilities": {
+                    "positionEncoding": "utf-8",
+                    "callHierarchyProvider": true,
+                    "documentSymbolProvider": true
+                }}),
+                "textDocument/documentSymbol" => json!([e, main]),
+                "textDocument/prepareCallHierarchy" => {
+                    match params["position"]["character"].as_u64().unwrap() {
+                        0..=10 => json!([e]),
+                        _ => json!([main]),
+                    }
+                }
+                "callHierarchy/outgoingCalls" if params["item"]["name"] == "main" => {
+                    json!([{"to": e, "fromRanges": [bytes(23, 25)]}])
+                }
+                "callHierarchy/outgoingCalls" => json!([]),
+                _ => Value::Null,
+            })
+        });
+        client.initialize(temp_dir.path()).await.unwrap();
+
+        // Column 11 is the `f` of `fn main`, byte 11
+        let mut config = test_config(Some(file.clone()));
+        config.root_function = Some(format!("{}:1:11", file.display()));
+        let graph = CallGraph::analyze(&mut client, &config).await.unwrap();
+
+        let main = graph.find_by_name("main").next().unwrap();
+        let e = graph.find_by_name("é").next().unwrap();
+        assert!(graph.is_root(main));
+        let site = Range::new(Position::new(0, 22), Position::new(0, 23));
+        assert_eq!(graph.edge(main, e).unwrap().call_sites, vec![site]);
+    }
+
+    #[test]
+    fn test_source_files_skips_build_and_hidden_directories() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        for dir in ["src/api", "target/debug", ".git", "node_modules/pkg"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "src/main.rs",
+            "src/api/mod.rs",
+            "src/api/client.ts",
+            "target/debug/build.rs",
+            ".git/hook.py",
+            "node_modules/pkg/index.js",
+            "Cargo.toml",
+        ] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+
+        let files: Vec<_> = source_files(root)
+            .into_iter()
+            .map(|path| path.strip_prefix(root).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from("src/api/client.ts"),
+                PathBuf::from("src/api/mod.rs"),
+                PathBuf::from("src/main.rs"),
+            ]
+        );
+    }
+
+    /// The fixture graph served without a call hierarchy, through references
+    async fn analyze_through_references(root: &str, direction: CallDirection) -> CallGraph {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("main.rs");
+        std::fs::write(&file, "fn main() {}").unwrap();
+        let uri = Url::from_file_path(file.canonicalize().unwrap()).unwrap();
+
+        let (mut client, server) = connect_client();
+        server.serve(move |method, params| {
+            let location = |line: u32, start: u32, end: u32| {
+                json!({"uri": uri, "range": {
+                    "start": {"line": line, "character": start},
+                    "end": {"line": line, "character": end}
+                }})
+            };
+            Ok(match method {
+                "initialize" => json!({"capabilities": {
+                    "referencesProvider": true,
+                    "documentSymbolProvider": true
+                }}),
+                "textDocument/documentSymbol" => json!([
+                    symbol_json("main", 0),
+                    symbol_json("helper", 5),
+                    symbol_json("process", 9)
+                ]),
+                "textDocument/references" => match params["position"]["line"].as_u64() {
+                    // The declaration itself must not become a self-call
+                    Some(5) => json!([location(5, 3, 9), location(1, 4, 10), location(10, 4, 10)]),
+                    Some(9) => json!([location(2, 4, 11)]),
+                    _ => json!([]),
+                },
+                "shutdown" => Value::Null,
+                _ => return Err(jsonrpc::Error::method_not_found()),
+            })
+        });
+        client.initialize(temp_dir.path()).await.unwrap();
+
+        let mut config = test_config(Some(file));
+        config.root_function = Some(root.to_string());
+        config.direction = direction;
+        CallGraph::analyze(&mut client, &config).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_analyze_derives_callees_from_references() {
+        let graph = analyze_through_references("main", CallDirection::Callees).await;
+        let id = |name| graph.find_by_name(name).next().unwrap();
+
+        assert_eq!(graph.node_count(), 3);
+        assert_eq!(graph.edge_count(), 3);
+        assert!(graph.is_root(id("main")));
+        assert_eq!(
+            graph.callees(id("main")).collect::<Vec<_>>(),
+            vec![id("helper"), id("process")]
+        );
+        assert_eq!(
+            graph.callees(id("process")).collect::<Vec<_>>(),
+            vec![id("helper")]
+        );
+        let site = Range::new(Position::new(1, 4), Position::new(1, 10));
+        assert_eq!(
+            graph.edge(id("main"), id("helper")).unwrap().call_sites,
+            vec![site]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_analyze_derives_callers_from_references() {
+        let graph = analyze_through_references("helper", CallDirection::Callers).await;
+        let id = |name| graph.find_by_name(name).next().unwrap();
+
+        assert_eq!(graph.node_count(), 3);
+        assert_eq!(
+            graph.callers(id("helper")).collect::<Vec<_>>(),
+            vec![id("main"), id("process")]
+        );
+        assert_eq!(
+            graph.callers(id("process")).collect::<Vec<_>>(),
+            vec![id("main")]
+        );
+        assert!(graph.edge(id("helper"), id("helper")).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_analyze_finds_names_of_flat_symbols_for_references() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("main.rs");
+        std::fs::write(
+            &file,
+            "def main():\n    helper()\n    process()\n\n@cached\ndef helper():\n    pass\n\n\n\
+             def process():\n    helper()\n",
+        )
+        .unwrap();
+        let uri = Url::from_file_path(file.canonicalize().unwrap()).unwrap();
+
+        let (mut client, server) = connect_client();
+        server.serve(move |method, params| {
+            let range = |line: u32, start: u32, end_line: u32, end: u32| {
+                json!({
+                    "start": {"line": line, "character": start},
+                    "end": {"line": end_line, "character": end}
+                })
+            };
+            let symbol = |name: &str, range: Value| {
+                json!({"name": name, "kind": 12, "location": {"uri": uri, "range": range}})
+            };
+            let location = |line: u32, start: u32, end: u32| {
+                json!({"uri": uri, "range": range(line, start, line, end)})
+            };
+            Ok(match method {
+                "initialize" => json!({"capabilities": {
+                    "referencesProvider": true,
+                    "documentSymbolProvider": true
+                }}),
+                // Ranges of whole declarations, one starting at a decorator
+                "textDocument/documentSymbol" => json!([
+                    symbol("main", range(0, 0, 2, 13)),
+                    symbol("helper", range(4, 0, 6, 8)),
+                    symbol("process", range(9, 0, 10, 12))
+                ]),
+                // Only the names have references
+                "textDocument/references" => match (
+                    params["position"]["line"].as_u64(),
+                    params["position"]["character"].as_u64(),
+                ) {
+                    (Some(5), Some(4)) => {
+                        json!([location(5, 4, 10), location(1, 4, 10), location(10, 4, 10)])
+                    }
+                    (Some(9), Some(4)) => json!([location(2, 4, 11)]),
+                    _ => json!([]),
+                },
+                "shutdown" => Value::Null,
+                _ => return Err(jsonrpc::Error::method_not_found()),
+            })
+        });
+        client.initialize(temp_dir.path()).await.unwrap();
+
+        let mut config = test_config(Some(file));
+        config.root_function = Some("main".to_string());
+        let graph = CallGraph::analyze(&mut client, &config).await.unwrap();
+        let id = |name| graph.find_by_name(name).next().unwrap();
+
+        assert_eq!(
+            graph.callees(id("main")).collect::<Vec<_>>(),
+            vec![id("helper"), id("process")]
+        );
+        assert_eq!(
+            graph.callees(id("process")).collect::<Vec<_>>(),
+            vec![id("helper")]
+        );
+        assert!(graph.edge(id("helper"), id("helper")).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_analyze_resolves_semantic_tokens_to_callees() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("main.rs");
+        std::fs::write(
+            &file,
+            "fn main() {\n    helper();\n    process(); ext();\n}\n\nfn helper() {}\n\n\n\n\
+             fn process() {\n    helper();\n}\n",
+        )
+        .unwrap();
+        let uri = Url::from_file_path(file.canonicalize().unwrap()).unwrap();
+
+        let token_requests = Arc::new(AtomicUsize::new(0));
+        let counter = token_requests.clone();
+        let (mut client, server) = connect_client();
+        server.serve(move |method, params| {
+            if method == "textDocument/semanticTokens/full" {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+            let location = |uri: &str, line: u32, length: u32| {
+                json!({"uri": uri, "range": {
+                    "start": {"line": line, "character": 3},
+                    "end": {"line": line, "character": 3 + length}
+                }})
+            };
+            Ok(match method {
+                "initialize" => json!({"capabilities": {
+                    "semanticTokensProvider": {
+                        "legend": {
+                            "tokenTypes": ["function", "variable", "method"],
+                            "tokenModifiers": ["declaration"]
+                        },
+                        "full": true
+                    },
+                    "definitionProvider": true,
+                    "documentSymbolProvider": true
+                }}),
+                "textDocument/documentSymbol" => json!([
+                    symbol_json("main", 0),
+                    symbol_json("helper", 5),
+                    symbol_json("process", 9)
+                ]),
+                // Declarations carry the modifier; `x` on line 3 is a variable
+                "textDocument/semanticTokens/full" => json!({"data": [
+                    0, 3, 4, 0, 1,
+                    1, 4, 6, 0, 0,
+                    1, 4, 7, 2, 0,
+                    0, 11, 3, 0, 0,
+                    1, 0, 1, 1, 0,
+                    2, 3, 6, 0, 1,
+                    4, 3, 7, 0, 1,
+                    1, 4, 6, 0, 0
+                ]}),
+                "textDocument/definition" => {
+                    let position = &params["position"];
+                    match (position["line"].as_u64(), position["character"].as_u64()) {
+                        (Some(1 | 10), Some(4)) => location(uri.as_str(), 5, 6),
+                        (Some(2), Some(4)) => location(uri.as_str(), 9, 7),
+                        (Some(2), Some(15)) => location("file:///ext/lib.rs", 0, 3),
+                        _ => Value::Null,
+                    }
+                }
+                "shutdown" => Value::Null,
+                _ => return Err(jsonrpc::Error::method_not_found()),
+            })
+        });
+        client.initialize(temp_dir.path()).await.unwrap();
+
+        let mut config = test_config(Some(file));
+        config.root_function = Some("main".to_string());
+        let graph = CallGraph::analyze(&mut client, &config).await.unwrap();
+        let id = |name| graph.find_by_name(name).next().unwrap();
+
+        assert_eq!(
+            graph.callees(id("main")).collect::<Vec<_>>(),
+            vec![id("helper"), id("process"), id("ext")]
+        );
+        assert!(graph.node(id("ext")).unwrap().external);
+        assert_eq!(
+            graph.callees(id("process")).collect::<Vec<_>>(),
+            vec![id("helper")]
+        );
+        let site = Range::new(Position::new(1, 4), Position::new(1, 10));
+        assert_eq!(
+            graph.edge(id("main"), id("helper")).unwrap().call_sites,
+            vec![site]
+        );
+        // Both expanded functions share the tokens of the file
+        assert_eq!(token_requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_requires_call_hierarchy_support() {
+        let (mut client, server) = connect_client();
+        server.serve(|method, _| match method {
+            "initialize" => Ok(json!({"capabilities": {}})),
+            _ => Err(jsonrpc::Error::method_not_found()),
+        });
+        client.initialize(&std::env::temp_dir()).await.unwrap();
+
+        let result = CallGraph::analyze(&mut client, &test_config(None)).await;
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("callHierarchyProvider"));
+    }
+
+    #[tokio::test]
+    async fn test_analyze_rejects_callers_through_semantic_tokens_only() {
+        let (mut client, server) = connect_client();
+        server.serve(|method, _| match method {
+            "initialize" => Ok(json!({"capabilities": {
+                "semanticTokensProvider": {
+                    "legend": {"tokenTypes": ["function"], "tokenModifiers": []},
+                    "full": true
+                },
+                "definitionProvider": true,
+                "documentSymbolProvider": true
+            }})),
+            _ => Err(jsonrpc::Error::method_not_found()),
+        });
+        client.initialize(&std::env::temp_dir()).await.unwrap();
+
+        let mut config = test_config(None);
+        config.root_function = Some("main".to_string());
+        config.direction = CallDirection::Both;
+        let result = CallGraph::analyze(&mut client, &config).await;
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Finding callers requires"));
+    }
+
+    // ------------------------------------------------------------------------
+    // Concurrent Requests
+    // ------------------------------------------------------------------------
+
+    /// `main` calls `a0`..`a5`, each of which calls `shared` and its own leaf;
+    /// functions further down the file answer sooner
+    struct SlowSource {
+        file: Url,
+        names: Vec<String>,
+        in_flight: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    impl SlowSource {
+        fn new(root: &Path) -> Self {
+            let mut names = vec!["main".to_string(), "shared".to_string()];
+            for i in 0..6 {
+                names.push(format!("a{i}"));
+                names.push(format!("leaf{i}"));
+            }
+            Self {
+                file: Url::from_file_path(root.join("main.rs")).unwrap(),
+                names,
+                in_flight: AtomicUsize::new(0),
+                peak: AtomicUsize::new(0),
+            }
+        }
+
+        fn symbol(&self, name: &str) -> FunctionSymbol {
+            let line = self.names.iter().position(|n| n == name).unwrap() as u32;
+            FunctionSymbol {
+                name: name.to_string(),
+                kind: SymbolKind::FUNCTION,
+                detail: None,
+                container: Vec::new(),
+                uri: self.file.clone(),
+                range: Range::new(Position::new(line, 0), Position::new(line, 20)),
+                position: Position::new(line, 3),
+            }
+        }
+
+        fn callees(&self, name: &str) -> Vec<String> {
+            match name {
+                "main" => (0..6).map(|i| format!("a{i}")).collect(),
+                _ => match name.strip_prefix('a') {
+                    Some(i) => vec![format!("leaf{i}"), "shared".to_string()],
+                    None => Vec::new(),
+                },
+            }
+        }
+    }
+
+    impl CallGraphSource for SlowSource {
+        fn document_symbols<'a>(
+            &'a self,
+            _file: &'a Path,
+        ) -> BoxFuture<'a, Result<Vec<FunctionSymbol>>> {
+            Box::pin(async { Ok(self.names.iter().map(|n| self.symbol(n)).collect()) })
+        }
+
+        fn prepare_item<'a>(
+            &'a self,
+            function: &'a FunctionSymbol,
+        ) -> BoxFuture<'a, Result<Vec<CallHierarchyItem>>> {
+            Box::pin(async { Ok(vec![function.to_item()]) })
+        }
+
+        fn outgoing_calls<'a>(
+            &'a self,
+            item: &'a CallHierarchyItem,
+        ) -> BoxFuture<'a, Result<Calls>> {
+            Box::pin(async move {
+                let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                self.peak.fetch_max(in_flight, Ordering::SeqCst);
+                let delay = self.names.len() as u32 - item.selection_range.start.line;
+                tokio::time::sleep(std::time::Duration::from_millis(u64::from(delay) * 5)).await;
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                let site = Range::new(item.range.start, item.range.start);
+                Ok(self
+                    .callees(&item.name)
+                    .iter()
+                    .map(|callee| (self.symbol(callee).to_item(), vec![site]))
+                    .collect())
+            })
+        }
+
+        fn incoming_calls<'a>(
+            &'a self,
+            _item: &'a CallHierarchyItem,
+        ) -> BoxFuture<'a, Result<Calls>> {
+            Box::pin(async { Ok(Vec::new()) })
+        }
+    }
+
+    async fn analyze_slow_source(max_concurrent_requests: usize) -> (CallGraph, usize) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::write(temp_dir.path().join("main.rs"), "").unwrap();
+        let mut config = test_config(None);
+        config.project_path = Some(temp_dir.path().to_path_buf());
+        config.root_function = Some("main".to_string());
+        config.lsp.max_concurrent_requests = max_concurrent_requests;
+        let mut source = SlowSource::new(&config.workspace_root().unwrap());
+        let graph = CallGraph::analyze(&mut source, &config).await.unwrap();
+        (graph, source.peak.load(Ordering::SeqCst))
+    }
+
+    fn graph_layout(graph: &CallGraph) -> (Vec<String>, Vec<(String, String)>) {
+        let nodes = graph.nodes().map(|(_, node)| node.name.clone()).collect();
+        let name = |id| graph.node(id).unwrap().name.clone();
+        let edges = graph
+            .edges()
+            .map(|(caller, callee, _)| (name(caller), name(callee)))
+            .collect();
+        (nodes, edges)
+    }
+
+    #[tokio::test]
+    async fn test_analyze_requests_calls_concurrently_in_a_stable_order() {
+        let (sequential, peak) = analyze_slow_source(1).await;
+        assert_eq!(peak, 1);
+        assert_eq!(sequential.node_count(), 14);
+        assert_eq!(sequential.edge_count(), 18);
+
+        let (concurrent, peak) = analyze_slow_source(4).await;
+        assert_eq!(peak, 4);
+        assert_eq!(graph_layout(&concurrent), graph_layout(&sequential));
+    }
+
+    // ------------------------------------------------------------------------
+    // Index Ingestion
+    // ------------------------------------------------------------------------
+
+    /// `main` calls `run`, which calls `helper` and the standard library
+    fn index_fixture(root: &Path) -> Index {
+        let item = |uri: Url, name: &str, line: u32| {
+            let selection = Range::new(Position::new(line, 3), Position::new(line, 3));
+            CallHierarchyItem {
+                name: name.to_string(),
+                kind: SymbolKind::FUNCTION,
+                tags: None,
+                detail: None,
+                uri,
+                range: Range::new(Position::new(line, 0), Position::new(line + 2, 1)),
+                selection_range: selection,
+                data: None,
+            }
+        };
+        let main_rs = Url::from_file_path(root.join("main.rs")).unwrap();
+        let util_rs = Url::from_file_path(root.join("util.rs")).unwrap();
+        let site = |line| Range::new(Position::new(line, 4), Position::new(line, 7));
+
+        let mut index = Index::default();
+        let main = index.add_function(item(main_rs.clone(), "main", 0), Vec::new());
+        let run = index.add_function(item(main_rs, "run", 4), Vec::new());
+        let helper = index.add_function(item(util_rs, "helper", 0), Vec::new());
+        let print = index.add_function(
+            item(Url::parse("scip://std/io/print").unwrap(), "print", 0),
+            Vec::new(),
+        );
+        index.add_call(main, run, site(1));
+        index.add_call(run, helper, site(5));
+        index.add_call(run, print, site(6));
+        index
+    }
+
+    fn index_config(root: &Path, spec: &str, direction: CallDirection) -> Config {
+        let mut config = test_config(None);
+        config.project_path = Some(root.to_path_buf());
+        config.root_function = Some(spec.to_string());
+        config.direction = direction;
+        config
+    }
+
+    fn sorted_names(graph: &CallGraph) -> Vec<String> {
+        let mut names: Vec<_> = graph.nodes().map(|(_, node)| node.name.clone()).collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn test_analyze_index_walks_calls_and_collapses_external_code() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        let mut index = index_fixture(&root);
+
+        let config = index_config(&root, "main", CallDirection::Callees);
+        let graph = CallGraph::analyze(&mut index, &config).await.unwrap();
+        assert_eq!(sorted_names(&graph), ["helper", "main", "run", "std"]);
+        assert_eq!(graph.edge_count(), 3);
+        let std = graph.find_by_name("std").next().unwrap();
+        assert!(graph.node(std).unwrap().external);
+
+        let mut config = index_config(&root, "main", CallDirection::Callees);
+        config.max_depth = Some(1);
+        let graph = CallGraph::analyze(&mut index, &config).await.unwrap();
+        assert_eq!(sorted_names(&graph), ["main", "run"]);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_index_resolves_roots_by_location_and_follows_callers() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        let mut index = index_fixture(&root);
+
+        let config = index_config(&root, "util.rs:2", CallDirection::Callers);
+        let graph = CallGraph::analyze(&mut index, &config).await.unwrap();
+        assert_eq!(sorted_names(&graph), ["helper", "main", "run"]);
+        let helper = graph.find_by_name("helper").next().unwrap();
+        assert!(graph.is_root(helper));
+
+        let config = index_config(&root, "missing", CallDirection::Callers);
+        let error = CallGraph::analyze(&mut index, &config).await.unwrap_err();
+        assert!(error.to_string().contains("'missing' not found"));
+
+        // Functions only referenced from the index cannot be roots
+        let config = index_config(&root, "print", CallDirection::Callers);
+        assert!(CallGraph::analyze(&mut index, &config).await.is_err());
     }
 }
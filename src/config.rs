@@ -2,6 +2,8 @@
 
 use clap::Parser;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -37,14 +39,38 @@ pub struct CliArgs {
     #[arg(short = 'd', long = "depth")]
     pub depth: Option<usize>,
 
-    /// Package/module patterns to exclude
+    /// Which calls to follow from the root: callees, callers or both
+    #[arg(long = "direction", value_enum)]
+    pub direction: Option<CallDirection>,
+
+    /// Glob patterns to exclude: `name`, `module:a::b::*` or `path:vendor/**`
     #[arg(long = "exclude")]
     pub exclude: Vec<String>,
 
-    /// LSP server command to use
+    /// LSP server command, or tcp://host:port or unix:///path of a running server
     #[arg(long = "lsp-server")]
     pub lsp_server: Option<String>,
 
+    /// Write every LSP request and response to a JSONL transcript
+    #[arg(
+        long = "record-lsp",
+        value_name = "FILE",
+        conflicts_with = "replay_lsp"
+    )]
+    pub record_lsp: Option<PathBuf>,
+
+    /// Answer LSP requests from a recorded transcript instead of a server
+    #[arg(long = "replay-lsp", value_name = "FILE")]
+    pub replay_lsp: Option<PathBuf>,
+
+    /// Build the graph from a SCIP or LSIF index instead of a language server
+    #[arg(
+        long = "index",
+        value_name = "FILE",
+        conflicts_with_all = ["lsp_server", "record_lsp", "replay_lsp"]
+    )]
+    pub index: Option<PathBuf>,
+
     /// Verbose logging output
     #[arg(short = 'v', long = "verbose")]
     pub verbose: bool,
@@ -139,8 +165,15 @@ pub struct Config {
     pub output_path: String,
     pub root_function: Option<String>,
     pub max_depth: Option<usize>,
+    pub direction: CallDirection,
     pub exclude_patterns: Vec<String>,
     pub lsp_server_command: Option<String>,
+    /// Transcript to record the LSP session to
+    pub record_lsp: Option<PathBuf>,
+    /// Transcript to replay instead of running a server
+    pub replay_lsp: Option<PathBuf>,
+    /// SCIP or LSIF index to read calls from instead of a server
+    pub index: Option<PathBuf>,
     pub verbose: bool,
     pub analysis: AnalysisConfig,
     pub output: OutputConfig,
@@ -152,6 +185,37 @@ pub struct AnalysisConfig {
     pub max_depth: Option<usize>,
     pub exclude_patterns: Vec<String>,
     pub include_external: bool,
+    #[serde(default)]
+    pub direction: CallDirection,
+    /// Compare call hierarchy callees with those found through semantic
+    /// tokens and warn where they differ
+    #[serde(default)]
+    pub verify_calls: bool,
+}
+
+/// Direction in which calls are followed from the root function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CallDirection {
+    /// Functions that call the root, transitively (`callHierarchy/incomingCalls`)
+    Callers,
+    /// Functions called by the root, transitively (`callHierarchy/outgoingCalls`)
+    #[default]
+    Callees,
+    /// Both callers and callees of the root
+    Both,
+}
+
+impl CallDirection {
+    /// Whether outgoing calls should be followed
+    pub fn includes_callees(self) -> bool {
+        matches!(self, Self::Callees | Self::Both)
+    }
+
+    /// Whether incoming calls should be followed
+    pub fn includes_callers(self) -> bool {
+        matches!(self, Self::Callers | Self::Both)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -166,6 +230,50 @@ pub struct OutputConfig {
 pub struct LspConfig {
     pub server_command: Option<String>,
     pub timeout_seconds: u64,
+    /// How long to wait for initial indexing before querying; `0` skips the wait
+    #[serde(default = "default_readiness_timeout")]
+    pub readiness_timeout_seconds: u64,
+    /// How often a server that exits mid-analysis is restarted; `0` gives up at once
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: u32,
+    /// How many call requests may be in flight at once during a traversal
+    #[serde(default = "default_max_concurrent_requests")]
+    pub max_concurrent_requests: usize,
+    /// Options of the `server_command` server, or of detected servers
+    #[serde(flatten)]
+    pub options: ServerOptions,
+    /// One server per language, keyed by language name (`[lsp.servers.rust]`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub servers: BTreeMap<String, ServerConfig>,
+}
+
+/// A language server dedicated to one language
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerConfig {
+    /// Command line used to start the server
+    pub command: String,
+    #[serde(flatten)]
+    pub options: ServerOptions,
+}
+
+/// How a server is started and configured
+///
+/// `initialization_options` and `settings` are arbitrary TOML, handed to the
+/// server as JSON.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServerOptions {
+    /// Arguments appended to the server command
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Environment variables set for the server process
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Sent as `initializationOptions` with the `initialize` request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initialization_options: Option<Value>,
+    /// Answers `workspace/configuration`, looked up by section
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<Value>,
 }
 
 // ============================================================================
@@ -186,6 +294,23 @@ impl Config {
     {
         Self::from(CliArgs::parse_from(itr))
     }
+
+    /// Absolute workspace root handed to the language server
+    ///
+    /// This is the project directory, or the directory containing the
+    /// analyzed file, falling back to the current directory.
+    pub fn workspace_root(&self) -> std::io::Result<PathBuf> {
+        let root = match (&self.project_path, &self.file_path) {
+            (Some(project), _) => project.clone(),
+            (None, Some(file)) => file
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| PathBuf::from(".")),
+            (None, None) => PathBuf::from("."),
+        };
+        root.canonicalize()
+    }
 }
 
 impl From<CliArgs> for Config {
@@ -199,12 +324,16 @@ impl From<CliArgs> for Config {
             output_path: args.output.unwrap_or_else(|| "callgraph.dot".to_string()),
             root_function: args.root,
             max_depth: args.depth.or(config_file.analysis.max_depth),
+            direction: args.direction.unwrap_or(config_file.analysis.direction),
             exclude_patterns: if args.exclude.is_empty() {
                 config_file.analysis.exclude_patterns.clone()
             } else {
                 args.exclude
             },
             lsp_server_command: args.lsp_server.or(config_file.lsp.server_command.clone()),
+            record_lsp: args.record_lsp,
+            replay_lsp: args.replay_lsp,
+            index: args.index,
             verbose: args.verbose,
             analysis: config_file.analysis.clone(),
             output: config_file.output.clone(),
@@ -219,6 +348,8 @@ impl Default for AnalysisConfig {
             max_depth: None,
             exclude_patterns: vec!["test_*".to_string(), "*_test".to_string()],
             include_external: false,
+            direction: CallDirection::Callees,
+            verify_calls: false,
         }
     }
 }
@@ -234,11 +365,28 @@ impl Default for OutputConfig {
     }
 }
 
+fn default_readiness_timeout() -> u64 {
+    120
+}
+
+fn default_max_restarts() -> u32 {
+    2
+}
+
+fn default_max_concurrent_requests() -> usize {
+    16
+}
+
 impl Default for LspConfig {
     fn default() -> Self {
         Self {
             server_command: None,
             timeout_seconds: 30,
+            readiness_timeout_seconds: default_readiness_timeout(),
+            max_restarts: default_max_restarts(),
+            max_concurrent_requests: default_max_concurrent_requests(),
+            options: ServerOptions::default(),
+            servers: BTreeMap::new(),
         }
     }
 }
@@ -281,8 +429,12 @@ mod tests {
             output: None,
             root: None,
             depth: None,
+            direction: None,
             exclude: vec![],
             lsp_server: None,
+            record_lsp: None,
+            replay_lsp: None,
+            index: None,
             verbose: false,
         };
 
@@ -306,8 +458,12 @@ mod tests {
             output: None,
             root: None,
             depth: None,
+            direction: None,
             exclude: vec![],
             lsp_server: None,
+            record_lsp: None,
+            replay_lsp: None,
+            index: None,
             verbose: false,
         };
 
@@ -327,8 +483,12 @@ mod tests {
             output: None,
             root: None,
             depth: None,
+            direction: None,
             exclude: vec![],
             lsp_server: None,
+            record_lsp: None,
+            replay_lsp: None,
+            index: None,
             verbose: false,
         };
 
@@ -352,8 +512,12 @@ mod tests {
             output: None,
             root: None,
             depth: Some(0),
+            direction: None,
             exclude: vec![],
             lsp_server: None,
+            record_lsp: None,
+            replay_lsp: None,
+            index: None,
             verbose: false,
         };
 
@@ -377,14 +541,50 @@ mod tests {
             output: Some("output.dot".to_string()),
             root: Some("main".to_string()),
             depth: Some(5),
+            direction: None,
             exclude: vec!["test_*".to_string()],
             lsp_server: Some("rust-analyzer".to_string()),
+            record_lsp: None,
+            replay_lsp: None,
+            index: None,
             verbose: true,
         };
 
         assert!(args.validate().is_ok());
     }
 
+    #[test]
+    fn test_record_and_replay_are_exclusive() {
+        let args = CliArgs::try_parse_from(["cgg", "-p", ".", "--replay-lsp", "session.jsonl"]);
+        assert_eq!(
+            args.unwrap().replay_lsp,
+            Some(PathBuf::from("session.jsonl"))
+        );
+
+        let args = CliArgs::try_parse_from([
+            "cgg",
+            "-p",
+            ".",
+            "--record-lsp",
+            "a.jsonl",
+            "--replay-lsp",
+            "b.jsonl",
+        ]);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_index_excludes_language_servers() {
+        let args = CliArgs::try_parse_from(["cgg", "-p", ".", "--index", "index.scip"]);
+        assert_eq!(args.unwrap().index, Some(PathBuf::from("index.scip")));
+
+        for flag in ["--lsp-server", "--record-lsp", "--replay-lsp"] {
+            let args =
+                CliArgs::try_parse_from(["cgg", "-p", ".", "--index", "index.scip", flag, "x"]);
+            assert!(args.is_err(), "{flag}");
+        }
+    }
+
     #[test]
     fn test_config_from_cli_args() {
         let temp_dir = tempfile::tempdir().unwrap();
@@ -397,8 +597,12 @@ mod tests {
             output: Some("custom.dot".to_string()),
             root: Some("main".to_string()),
             depth: Some(10),
+            direction: Some(CallDirection::Both),
             exclude: vec!["test_*".to_string(), "bench_*".to_string()],
             lsp_server: Some("rust-analyzer".to_string()),
+            record_lsp: None,
+            replay_lsp: None,
+            index: None,
             verbose: true,
         };
 
@@ -409,12 +613,13 @@ mod tests {
         assert_eq!(config.output_path, "custom.dot");
         assert_eq!(config.root_function, Some("main".to_string()));
         assert_eq!(config.max_depth, Some(10));
+        assert_eq!(config.direction, CallDirection::Both);
         assert_eq!(
             config.exclude_patterns,
             vec!["test_*".to_string(), "bench_*".to_string()]
         );
         assert_eq!(config.lsp_server_command, Some("rust-analyzer".to_string()));
-        assert_eq!(config.verbose, true);
+        assert!(config.verbose);
     }
 
     #[test]
@@ -429,8 +634,12 @@ mod tests {
             output: None, // Test default output
             root: None,
             depth: None,
+            direction: None,
             exclude: vec![], // Test default exclude patterns from config file
             lsp_server: None,
+            record_lsp: None,
+            replay_lsp: None,
+            index: None,
             verbose: false,
         };
 
@@ -439,13 +648,68 @@ mod tests {
         assert_eq!(config.output_path, "callgraph.dot");
         assert_eq!(config.root_function, None);
         assert_eq!(config.max_depth, None);
+        assert_eq!(config.direction, CallDirection::Callees);
         // Should use default exclude patterns from config file
         assert_eq!(
             config.exclude_patterns,
             vec!["test_*".to_string(), "*_test".to_string()]
         );
         assert_eq!(config.lsp_server_command, None);
-        assert_eq!(config.verbose, false);
+        assert!(!config.verbose);
+    }
+
+    #[test]
+    fn test_workspace_root_uses_file_parent() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_file = temp_dir.path().join("test.rs");
+        std::fs::write(&temp_file, "fn main() {}").unwrap();
+
+        let args = CliArgs {
+            file: Some(temp_file),
+            project: None,
+            output: None,
+            root: None,
+            depth: None,
+            direction: None,
+            exclude: vec![],
+            lsp_server: None,
+            record_lsp: None,
+            replay_lsp: None,
+            index: None,
+            verbose: false,
+        };
+        let config = Config::from(args);
+
+        assert_eq!(
+            config.workspace_root().unwrap(),
+            temp_dir.path().canonicalize().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_workspace_root_prefers_project() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let args = CliArgs {
+            file: None,
+            project: Some(temp_dir.path().to_path_buf()),
+            output: None,
+            root: None,
+            depth: None,
+            direction: None,
+            exclude: vec![],
+            lsp_server: None,
+            record_lsp: None,
+            replay_lsp: None,
+            index: None,
+            verbose: false,
+        };
+        let config = Config::from(args);
+
+        assert_eq!(
+            config.workspace_root().unwrap(),
+            temp_dir.path().canonicalize().unwrap()
+        );
     }
 
     #[test]
@@ -456,16 +720,42 @@ mod tests {
             config.exclude_patterns,
             vec!["test_*".to_string(), "*_test".to_string()]
         );
-        assert_eq!(config.include_external, false);
+        assert!(!config.include_external);
+        assert_eq!(config.direction, CallDirection::Callees);
+        assert!(!config.verify_calls);
+    }
+
+    #[test]
+    fn test_direction_flag_parsing() {
+        let args = CliArgs::parse_from(["call-graph-generator", "--direction", "callers"]);
+        assert_eq!(args.direction, Some(CallDirection::Callers));
+        assert!(CliArgs::try_parse_from(["call-graph-generator", "--direction", "up"]).is_err());
+    }
+
+    #[test]
+    fn test_direction_from_config_file() {
+        let config: ConfigFile = toml::from_str(
+            r#"
+            [analysis]
+            max_depth = 3
+            exclude_patterns = []
+            include_external = false
+            direction = "both"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.analysis.direction, CallDirection::Both);
+        assert!(config.analysis.direction.includes_callers());
+        assert!(config.analysis.direction.includes_callees());
     }
 
     #[test]
     fn test_output_config_defaults() {
         let config = OutputConfig::default();
         assert_eq!(config.format, "dot");
-        assert_eq!(config.show_types, true);
-        assert_eq!(config.show_parameters, false);
-        assert_eq!(config.cluster_by_module, true);
+        assert!(config.show_types);
+        assert!(!config.show_parameters);
+        assert!(config.cluster_by_module);
     }
 
     #[test]
@@ -473,5 +763,77 @@ mod tests {
         let config = LspConfig::default();
         assert_eq!(config.server_command, None);
         assert_eq!(config.timeout_seconds, 30);
+        assert_eq!(config.readiness_timeout_seconds, 120);
+        assert_eq!(config.max_restarts, 2);
+        assert_eq!(config.max_concurrent_requests, 16);
+        assert!(config.servers.is_empty());
+    }
+
+    #[test]
+    fn test_per_language_servers_from_config_file() {
+        let config: ConfigFile = toml::from_str(
+            r#"
+            [lsp]
+            timeout_seconds = 30
+
+            [lsp.servers.rust]
+            command = "rust-analyzer"
+
+            [lsp.servers.typescript]
+            command = "typescript-language-server --stdio"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.lsp.servers.len(), 2);
+        assert_eq!(config.lsp.servers["rust"].command, "rust-analyzer");
+        assert_eq!(config.lsp.servers["rust"].options, ServerOptions::default());
+        assert_eq!(
+            config.lsp.servers["typescript"].command,
+            "typescript-language-server --stdio"
+        );
+    }
+
+    #[test]
+    fn test_server_options_convert_toml_to_json() {
+        let config: ConfigFile = toml::from_str(
+            r#"
+            [lsp]
+            server_command = "clangd"
+            timeout_seconds = 30
+            args = ["--compile-commands-dir=build"]
+            env = { CLANGD_FLAGS = "--log=error" }
+
+            [lsp.initialization_options]
+            fallbackFlags = ["-std=c++20"]
+
+            [lsp.servers.rust]
+            command = "rust-analyzer"
+
+            [lsp.servers.rust.settings.rust-analyzer]
+            cargo.allFeatures = true
+            procMacro.enable = true
+            "#,
+        )
+        .unwrap();
+
+        let options = &config.lsp.options;
+        assert_eq!(options.args, vec!["--compile-commands-dir=build"]);
+        assert_eq!(options.env["CLANGD_FLAGS"], "--log=error");
+        assert_eq!(
+            options.initialization_options,
+            Some(serde_json::json!({"fallbackFlags": ["-std=c++20"]}))
+        );
+        assert_eq!(
+            config.lsp.servers["rust"].options.settings,
+            Some(serde_json::json!({
+                "rust-analyzer": {"cargo": {"allFeatures": true}, "procMacro": {"enable": true}}
+            }))
+        );
+
+        // Options survive a save and reload
+        let saved = toml::to_string_pretty(&config).unwrap();
+        let reloaded: ConfigFile = toml::from_str(&saved).unwrap();
+        assert_eq!(reloaded.lsp.options, config.lsp.options);
+        assert_eq!(reloaded.lsp.servers, config.lsp.servers);
     }
 }
@@ -0,0 +1,171 @@
+//! Exclusion filtering of functions, modules and paths
+
+use crate::analyzer::FunctionNode;
+use crate::{CallGraphError, Result};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use std::path::Path;
+
+// ============================================================================
+// Exclude Filter
+// ============================================================================
+
+/// Compiled `exclude_patterns`
+///
+/// Each pattern is a glob with an optional prefix selecting what it is
+/// matched against:
+///
+/// - `name:test_*` or `test_*` - the function name
+/// - `module:tests::*` - the module path and qualified name, e.g. `api::handlers::process`
+/// - `path:vendor/**` - the file path relative to the workspace root
+///
+/// Module and path patterns also exclude everything nested below a match, so
+/// `module:tests` covers `tests::unit::helper` and `path:vendor` covers
+/// `vendor/lib/mod.rs`. `*` never crosses a `::` or `/` boundary; use `**`.
+#[derive(Debug, Clone, Default)]
+pub struct ExcludeFilter {
+    names: GlobSet,
+    modules: GlobSet,
+    paths: GlobSet,
+}
+
+impl ExcludeFilter {
+    /// Compile the given patterns, rejecting malformed globs
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Self> {
+        let mut names = GlobSetBuilder::new();
+        let mut modules = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+            if let Some(module) = pattern.strip_prefix("module:") {
+                modules.add(separated_glob(pattern, &module.replace("::", "/"))?);
+            } else if let Some(path) = pattern.strip_prefix("path:") {
+                paths.add(separated_glob(pattern, path.trim_end_matches('/'))?);
+            } else {
+                let name = pattern.strip_prefix("name:").unwrap_or(pattern);
+                names.add(Glob::new(name).map_err(|e| invalid_pattern(pattern, e))?);
+            }
+        }
+
+        Ok(Self {
+            names: names.build().map_err(|e| invalid_pattern("", e))?,
+            modules: modules.build().map_err(|e| invalid_pattern("", e))?,
+            paths: paths.build().map_err(|e| invalid_pattern("", e))?,
+        })
+    }
+
+    /// Whether no pattern was configured
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.modules.is_empty() && self.paths.is_empty()
+    }
+
+    /// Whether a function matches any pattern
+    pub fn is_excluded(&self, node: &FunctionNode, workspace_root: Option<&Path>) -> bool {
+        if self.names.is_match(&node.name) {
+            return true;
+        }
+
+        if !self.modules.is_empty() {
+            let qualified = node.qualified_name().replace("::", "/");
+            if Path::new(&qualified)
+                .ancestors()
+                .any(|prefix| !prefix.as_os_str().is_empty() && self.modules.is_match(prefix))
+            {
+                return true;
+            }
+        }
+
+        if !self.paths.is_empty() {
+            if let Ok(path) = node.uri.to_file_path() {
+                let relative = workspace_root
+                    .and_then(|root| path.strip_prefix(root).ok())
+                    .unwrap_or(&path);
+                return relative
+                    .ancestors()
+                    .any(|prefix| !prefix.as_os_str().is_empty() && self.paths.is_match(prefix));
+            }
+        }
+        false
+    }
+}
+
+/// A glob whose `*` stops at `/`
+fn separated_glob(pattern: &str, glob: &str) -> Result<Glob> {
+    GlobBuilder::new(glob)
+        .literal_separator(true)
+        .build()
+        .map_err(|e| invalid_pattern(pattern, e))
+}
+
+fn invalid_pattern(
+    pattern: &str,
+    error: globset::Error,
+) -> Box<dyn std::error::Error + Send + Sync> {
+    CallGraphError::ConfigError(format!("Invalid exclude pattern '{pattern}': {error}")).into()
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_lsp::lsp_types::{CallHierarchyItem, SymbolKind, Url};
+
+    fn node(name: &str, path: &str) -> FunctionNode {
+        let uri = Url::from_file_path(path).unwrap();
+        let item = CallHierarchyItem {
+            name: name.to_string(),
+            kind: SymbolKind::FUNCTION,
+            tags: None,
+            detail: None,
+            uri,
+            range: Default::default(),
+            selection_range: Default::default(),
+            data: None,
+        };
+        FunctionNode::from_item(item, Some(Path::new("/project")))
+    }
+
+    #[test]
+    fn test_name_patterns_match_function_names() {
+        let filter = ExcludeFilter::new(&["test_*", "name:*_test"]).unwrap();
+        assert!(filter.is_excluded(&node("test_parse", "/project/src/lib.rs"), None));
+        assert!(filter.is_excluded(&node("parse_test", "/project/src/lib.rs"), None));
+        assert!(!filter.is_excluded(&node("parse", "/project/src/lib.rs"), None));
+    }
+
+    #[test]
+    fn test_module_patterns_match_module_and_descendants() {
+        let root = Some(Path::new("/project"));
+        let filter = ExcludeFilter::new(&["module:tests", "module:api::*::debug"]).unwrap();
+
+        assert!(filter.is_excluded(&node("helper", "/project/src/tests.rs"), root));
+        assert!(filter.is_excluded(&node("helper", "/project/src/tests/unit.rs"), root));
+        assert!(filter.is_excluded(&node("dump", "/project/src/api/v1/debug.rs"), root));
+        assert!(!filter.is_excluded(&node("dump", "/project/src/api/v1/v2/debug.rs"), root));
+        assert!(!filter.is_excluded(&node("helper", "/project/src/api/mod.rs"), root));
+
+        let filter = ExcludeFilter::new(&["module:api::handlers::process"]).unwrap();
+        assert!(filter.is_excluded(&node("process", "/project/src/api/handlers.rs"), root));
+        assert!(!filter.is_excluded(&node("respond", "/project/src/api/handlers.rs"), root));
+    }
+
+    #[test]
+    fn test_path_patterns_match_relative_paths() {
+        let root = Some(Path::new("/project"));
+        let filter = ExcludeFilter::new(&["path:vendor/**", "path:benches/"]).unwrap();
+
+        assert!(filter.is_excluded(&node("f", "/project/vendor/zlib/inflate.c"), root));
+        assert!(filter.is_excluded(&node("f", "/project/benches/parse.rs"), root));
+        assert!(!filter.is_excluded(&node("f", "/project/src/vendor.rs"), root));
+    }
+
+    #[test]
+    fn test_invalid_pattern_is_a_config_error() {
+        let error = ExcludeFilter::new(&["path:src/[a"]).unwrap_err();
+        assert!(error.to_string().contains("path:src/[a"));
+        assert!(ExcludeFilter::new::<&str>(&[]).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,330 @@
+//! Precomputed SCIP and LSIF indexes as a source of calls
+//!
+//! Indexers such as rust-analyzer, scip-typescript or lsif-node record every
+//! definition and reference of a project in one file. A reference to a
+//! function from inside another function counts as a call, so the graph can
+//! be built from the index without starting a language server.
+
+mod lsif;
+mod scip;
+
+use crate::source::{CallGraphSource, Calls, FunctionSymbol};
+use crate::{CallGraphError, Result};
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::path::Path;
+use tower_lsp::lsp_types::{CallHierarchyItem, Position, Range, Url};
+use tracing::info;
+
+/// Functions and calls read from an index
+///
+/// Positions count Unicode characters, whatever encoding the indexer used.
+#[derive(Debug, Clone, Default)]
+pub struct Index {
+    functions: Vec<IndexedFunction>,
+    calls: Vec<IndexedCall>,
+    /// Function by document, line and character where its name starts
+    positions: HashMap<(Url, u32, u32), usize>,
+    /// Call by caller and callee
+    edges: HashMap<(usize, usize), usize>,
+    /// Calls from each function, by position in `calls`
+    outgoing: Vec<Vec<usize>>,
+    /// Calls to each function, by position in `calls`
+    incoming: Vec<Vec<usize>>,
+}
+
+/// A function defined in, or only referenced from, the index
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedFunction {
+    pub item: CallHierarchyItem,
+    /// Enclosing symbols such as `Foo` for a method of `Foo`
+    pub container: Vec<String>,
+}
+
+/// References to `callee` from inside `caller`, by position in `functions`
+#[derive(Debug, Clone, PartialEq)]
+struct IndexedCall {
+    caller: usize,
+    callee: usize,
+    call_sites: Vec<Range>,
+}
+
+impl Index {
+    /// Read an index, detecting its format from the content
+    ///
+    /// LSIF dumps are JSON, one element per line or a single array; anything
+    /// else is decoded as a SCIP protobuf. Documents are located relative to
+    /// `workspace_root` rather than where the indexer ran.
+    pub fn load(path: &Path, workspace_root: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path).map_err(|e| {
+            CallGraphError::ConfigError(format!("Cannot read index {}: {}", path.display(), e))
+        })?;
+        let index = match bytes.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'{' | b'[') => lsif::read(&bytes, workspace_root),
+            _ => scip::read(&bytes, workspace_root),
+        }
+        .map_err(|e| {
+            CallGraphError::AnalysisError(format!("Invalid index {}: {}", path.display(), e))
+        })?;
+
+        info!(
+            "Loaded {} functions and {} calls from {}",
+            index.functions.len(),
+            index.calls.len(),
+            path.display()
+        );
+        Ok(index)
+    }
+
+    /// Every function in order of appearance
+    pub fn functions(&self) -> impl Iterator<Item = &IndexedFunction> {
+        self.functions.iter()
+    }
+
+    /// The function whose name starts at `position` in `uri`
+    pub fn function_at(&self, uri: &Url, position: Position) -> Option<&IndexedFunction> {
+        self.position(uri, position).map(|id| &self.functions[id])
+    }
+
+    /// Callees of a function with the call sites inside it
+    pub fn outgoing_calls(&self, item: &CallHierarchyItem) -> Vec<(CallHierarchyItem, Vec<Range>)> {
+        self.calls_of(item, &self.outgoing, |call| call.callee)
+    }
+
+    /// Callers of a function with the call sites inside each caller
+    pub fn incoming_calls(&self, item: &CallHierarchyItem) -> Vec<(CallHierarchyItem, Vec<Range>)> {
+        self.calls_of(item, &self.incoming, |call| call.caller)
+    }
+
+    fn calls_of(
+        &self,
+        item: &CallHierarchyItem,
+        adjacency: &[Vec<usize>],
+        other: impl Fn(&IndexedCall) -> usize,
+    ) -> Vec<(CallHierarchyItem, Vec<Range>)> {
+        let Some(id) = self.position(&item.uri, item.selection_range.start) else {
+            return Vec::new();
+        };
+        adjacency[id]
+            .iter()
+            .map(|&call| &self.calls[call])
+            .map(|call| {
+                (
+                    self.functions[other(call)].item.clone(),
+                    call.call_sites.clone(),
+                )
+            })
+            .collect()
+    }
+
+    fn position(&self, uri: &Url, position: Position) -> Option<usize> {
+        let key = (uri.clone(), position.line, position.character);
+        self.positions.get(&key).copied()
+    }
+
+    // ------------------------------------------------------------------------
+    // Construction
+    // ------------------------------------------------------------------------
+
+    /// Insert a function, returning the existing id if one starts at the same place
+    pub(crate) fn add_function(
+        &mut self,
+        item: CallHierarchyItem,
+        container: Vec<String>,
+    ) -> usize {
+        let start = item.selection_range.start;
+        let key = (item.uri.clone(), start.line, start.character);
+        if let Some(&id) = self.positions.get(&key) {
+            return id;
+        }
+        self.functions.push(IndexedFunction { item, container });
+        self.outgoing.push(Vec::new());
+        self.incoming.push(Vec::new());
+        self.positions.insert(key, self.functions.len() - 1);
+        self.functions.len() - 1
+    }
+
+    /// Record a call site, merging it into an existing call between the two
+    pub(crate) fn add_call(&mut self, caller: usize, callee: usize, call_site: Range) {
+        match self.edges.get(&(caller, callee)) {
+            Some(&call) => {
+                let call_sites = &mut self.calls[call].call_sites;
+                if !call_sites.contains(&call_site) {
+                    call_sites.push(call_site);
+                }
+            }
+            None => {
+                let call = self.calls.len();
+                self.edges.insert((caller, callee), call);
+                self.outgoing[caller].push(call);
+                self.incoming[callee].push(call);
+                self.calls.push(IndexedCall {
+                    caller,
+                    callee,
+                    call_sites: vec![call_site],
+                });
+            }
+        }
+    }
+}
+
+// ============================================================================
+// Call Graph Source
+// ============================================================================
+
+impl IndexedFunction {
+    fn to_symbol(&self) -> FunctionSymbol {
+        FunctionSymbol {
+            name: self.item.name.clone(),
+            kind: self.item.kind,
+            detail: self.item.detail.clone(),
+            container: self.container.clone(),
+            uri: self.item.uri.clone(),
+            range: self.item.range,
+            position: self.item.selection_range.start,
+        }
+    }
+}
+
+/// Index positions already count characters, so the defaults apply
+impl CallGraphSource for Index {
+    fn document_symbols<'a>(
+        &'a self,
+        file: &'a Path,
+    ) -> BoxFuture<'a, Result<Vec<FunctionSymbol>>> {
+        let file = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+        let symbols = self
+            .functions()
+            .filter(|function| {
+                function
+                    .item
+                    .uri
+                    .to_file_path()
+                    .is_ok_and(|path| path == file)
+            })
+            .map(IndexedFunction::to_symbol)
+            .collect();
+        Box::pin(async { Ok(symbols) })
+    }
+
+    fn prepare_item<'a>(
+        &'a self,
+        function: &'a FunctionSymbol,
+    ) -> BoxFuture<'a, Result<Vec<CallHierarchyItem>>> {
+        let items = self
+            .function_at(&function.uri, function.position)
+            .map(|function| function.item.clone())
+            .into_iter()
+            .collect();
+        Box::pin(async { Ok(items) })
+    }
+
+    fn outgoing_calls<'a>(&'a self, item: &'a CallHierarchyItem) -> BoxFuture<'a, Result<Calls>> {
+        Box::pin(async { Ok(Index::outgoing_calls(self, item)) })
+    }
+
+    fn incoming_calls<'a>(&'a self, item: &'a CallHierarchyItem) -> BoxFuture<'a, Result<Calls>> {
+        Box::pin(async { Ok(Index::incoming_calls(self, item)) })
+    }
+
+    /// Functions only referenced from the index have no file and are left out
+    fn workspace_symbols<'a>(
+        &'a self,
+        query: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Vec<FunctionSymbol>>>> {
+        let symbols = self
+            .functions()
+            .filter(|function| function.item.uri.to_file_path().is_ok())
+            .filter(|function| function.item.name.contains(query))
+            .map(IndexedFunction::to_symbol)
+            .collect();
+        Box::pin(async { Ok(Some(symbols)) })
+    }
+}
+
+/// Whether `inner` lies within `outer`
+fn contains(outer: &Range, inner: &Range) -> bool {
+    outer.start <= inner.start && inner.end <= outer.end
+}
+
+/// The smallest of several ranges that contain `range`
+fn innermost<T>(
+    candidates: impl IntoIterator<Item = (Range, T)>,
+    range: &Range,
+) -> Option<(Range, T)> {
+    candidates
+        .into_iter()
+        .filter(|(outer, _)| contains(outer, range))
+        .min_by_key(|(outer, _)| {
+            (
+                outer.end.line - outer.start.line,
+                outer.end.character.saturating_sub(outer.start.character),
+            )
+        })
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_lsp::lsp_types::SymbolKind;
+
+    fn item(name: &str, line: u32) -> CallHierarchyItem {
+        let range = Range::new(Position::new(line, 3), Position::new(line, 3));
+        CallHierarchyItem {
+            name: name.to_string(),
+            kind: SymbolKind::FUNCTION,
+            tags: None,
+            detail: None,
+            uri: Url::parse("file:///project/src/main.rs").unwrap(),
+            range,
+            selection_range: range,
+            data: None,
+        }
+    }
+
+    fn site(line: u32) -> Range {
+        Range::new(Position::new(line, 4), Position::new(line, 8))
+    }
+
+    #[test]
+    fn test_calls_are_merged_per_caller_and_callee() {
+        let mut index = Index::default();
+        let main = index.add_function(item("main", 0), Vec::new());
+        let run = index.add_function(item("run", 5), Vec::new());
+        assert_eq!(index.add_function(item("main", 0), Vec::new()), main);
+
+        index.add_call(main, run, site(1));
+        index.add_call(main, run, site(2));
+        index.add_call(main, run, site(1));
+
+        assert_eq!(
+            index.outgoing_calls(&item("main", 0)),
+            [(item("run", 5), vec![site(1), site(2)])]
+        );
+        assert_eq!(
+            index.incoming_calls(&item("run", 5)),
+            [(item("main", 0), vec![site(1), site(2)])]
+        );
+        assert!(index.outgoing_calls(&item("run", 5)).is_empty());
+        assert!(index.outgoing_calls(&item("missing", 9)).is_empty());
+    }
+
+    #[test]
+    fn test_load_rejects_unreadable_and_malformed_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let missing = Index::load(&temp_dir.path().join("index.scip"), temp_dir.path());
+        assert!(missing
+            .unwrap_err()
+            .to_string()
+            .contains("Cannot read index"));
+
+        let path = temp_dir.path().join("dump.lsif");
+        std::fs::write(&path, "{\"id\": 1,\n").unwrap();
+        let malformed = Index::load(&path, temp_dir.path());
+        assert!(malformed.unwrap_err().to_string().contains("Invalid index"));
+    }
+}
@@ -0,0 +1,430 @@
+//! Reading LSIF dumps
+//!
+//! A dump is a graph of vertices and edges. Functions are the ranges tagged
+//! as the definition of a function, method or constructor; any other range
+//! whose `textDocument/definition` result is such a function is a reference
+//! to it, made by the innermost function around the range.
+
+use super::{contains, innermost, Index};
+use crate::lsp::{PositionEncoding, SourceLines};
+use crate::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use tower_lsp::lsp_types::{CallHierarchyItem, Position, Range, SymbolKind, Url};
+
+// ============================================================================
+// Dump Format
+// ============================================================================
+
+/// Vertex and edge ids are numbers or strings depending on the indexer
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(untagged)]
+enum Id {
+    Number(u64),
+    String(String),
+}
+
+/// A vertex or an edge; only the properties used here are read
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Element {
+    id: Id,
+    label: String,
+    // metaData
+    project_root: Option<String>,
+    position_encoding: Option<String>,
+    // document
+    uri: Option<String>,
+    // range
+    start: Option<Position>,
+    end: Option<Position>,
+    tag: Option<Tag>,
+    // edges
+    out_v: Option<Id>,
+    in_v: Option<Id>,
+    #[serde(default)]
+    in_vs: Vec<Id>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Tag {
+    #[serde(rename = "type")]
+    tag_type: String,
+    text: String,
+    kind: Option<SymbolKind>,
+    full_range: Option<Range>,
+    detail: Option<String>,
+}
+
+impl Tag {
+    fn is_definition(&self) -> bool {
+        self.tag_type == "definition"
+    }
+}
+
+/// Elements of a dump, one per line or as a single JSON array
+fn elements(bytes: &[u8]) -> Result<Vec<Element>> {
+    let text = std::str::from_utf8(bytes)?;
+    if text.trim_start().starts_with('[') {
+        return Ok(serde_json::from_str(text)?);
+    }
+    let mut elements = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let element =
+            serde_json::from_str(line).map_err(|e| format!("line {}: {}", number + 1, e))?;
+        elements.push(element);
+    }
+    Ok(elements)
+}
+
+// ============================================================================
+// Index Construction
+// ============================================================================
+
+/// A range vertex in character columns
+struct RangeVertex {
+    id: Id,
+    document: usize,
+    range: Range,
+    tag: Option<Tag>,
+}
+
+/// Build an index from an LSIF dump
+pub(super) fn read(bytes: &[u8], workspace_root: &Path) -> Result<Index> {
+    let elements = elements(bytes)?;
+
+    let mut project_root = None;
+    let mut encoding = PositionEncoding::Utf16;
+    let mut documents: Vec<(Id, Url)> = Vec::new();
+    let mut raw_ranges: Vec<&Element> = Vec::new();
+    let mut document_of: HashMap<&Id, &Id> = HashMap::new();
+    let mut next: HashMap<&Id, &Id> = HashMap::new();
+    let mut definition: HashMap<&Id, &Id> = HashMap::new();
+    let mut items: HashMap<&Id, Vec<&Id>> = HashMap::new();
+    for element in &elements {
+        match (element.label.as_str(), &element.out_v) {
+            ("metaData", _) => {
+                project_root = element.project_root.as_deref().and_then(file_path);
+                encoding = match element.position_encoding.as_deref() {
+                    Some("utf-8") => PositionEncoding::Utf8,
+                    Some("utf-32") => PositionEncoding::Utf32,
+                    _ => PositionEncoding::Utf16,
+                };
+            }
+            ("document", None) => {
+                if let Some(uri) = element.uri.as_deref() {
+                    let uri = local_uri(uri, project_root.as_deref(), workspace_root)?;
+                    documents.push((element.id.clone(), uri));
+                }
+            }
+            ("range", None) => raw_ranges.push(element),
+            ("contains", Some(out)) => {
+                for range in &element.in_vs {
+                    document_of.insert(range, out);
+                }
+            }
+            ("next", Some(out)) => {
+                if let Some(target) = &element.in_v {
+                    next.insert(out, target);
+                }
+            }
+            ("textDocument/definition", Some(out)) => {
+                if let Some(target) = &element.in_v {
+                    definition.insert(out, target);
+                }
+            }
+            ("item", Some(out)) => items.entry(out).or_default().extend(&element.in_vs),
+            _ => {}
+        }
+    }
+
+    // Positions are converted with the text of the local checkout
+    let lines: Vec<SourceLines> = documents
+        .iter()
+        .map(|(_, uri)| match uri.to_file_path() {
+            Ok(path) if encoding != PositionEncoding::Utf32 => SourceLines::read(&path),
+            _ => SourceLines::default(),
+        })
+        .collect();
+    let document_index: HashMap<&Id, usize> = documents
+        .iter()
+        .enumerate()
+        .map(|(index, (id, _))| (id, index))
+        .collect();
+    let ranges: Vec<RangeVertex> = raw_ranges
+        .into_iter()
+        .filter_map(|element| {
+            let document = *document_index.get(document_of.get(&element.id)?)?;
+            let lines = &lines[document];
+            let range = Range::new(element.start?, element.end?);
+            let mut tag = element.tag.clone();
+            if let Some(full_range) = tag.as_mut().and_then(|tag| tag.full_range.as_mut()) {
+                *full_range = lines.range_to_columns(encoding, *full_range);
+            }
+            Some(RangeVertex {
+                id: element.id.clone(),
+                document,
+                range: lines.range_to_columns(encoding, range),
+                tag,
+            })
+        })
+        .collect();
+
+    let mut containers = containers(&ranges, documents.len());
+    let mut index = Index::default();
+    let mut functions: HashMap<&Id, usize> = HashMap::new();
+    // Per document, the full range of every function defined in it
+    let mut scopes: Vec<Vec<(Range, usize)>> = vec![Vec::new(); documents.len()];
+    for vertex in &ranges {
+        let Some(tag) = vertex.tag.as_ref().filter(|tag| tag.is_definition()) else {
+            continue;
+        };
+        let Some(kind) = tag.kind.filter(|&kind| is_function_kind(kind)) else {
+            continue;
+        };
+        let full_range = tag.full_range.unwrap_or(vertex.range);
+        let item = CallHierarchyItem {
+            name: tag.text.clone(),
+            kind,
+            tags: None,
+            detail: tag.detail.clone(),
+            uri: documents[vertex.document].1.clone(),
+            range: full_range,
+            selection_range: vertex.range,
+            data: None,
+        };
+        let container = containers.remove(&vertex.id).unwrap_or_default();
+        let id = index.add_function(item, container);
+        functions.insert(&vertex.id, id);
+        scopes[vertex.document].push((full_range, id));
+    }
+
+    for vertex in &ranges {
+        if functions.contains_key(&vertex.id) {
+            continue;
+        }
+        let targets = definitions(&vertex.id, &next, &definition, &items);
+        // An untagged definition lists itself as its own definition
+        if targets.contains(&&vertex.id) {
+            continue;
+        }
+        let Some((_, caller)) = innermost(scopes[vertex.document].iter().copied(), &vertex.range)
+        else {
+            continue;
+        };
+        for target in targets {
+            if let Some(&callee) = functions.get(target) {
+                index.add_call(caller, callee, vertex.range);
+            }
+        }
+    }
+    Ok(index)
+}
+
+/// Ranges a range's `textDocument/definition` result lists, through any
+/// chain of result sets
+fn definitions<'a>(
+    range: &'a Id,
+    next: &HashMap<&'a Id, &'a Id>,
+    definition: &HashMap<&'a Id, &'a Id>,
+    items: &HashMap<&'a Id, Vec<&'a Id>>,
+) -> Vec<&'a Id> {
+    let mut current = range;
+    // Bounded in case a malformed dump chains result sets in a cycle
+    for _ in 0..=next.len() {
+        if let Some(result) = definition.get(current) {
+            return items.get(result).cloned().unwrap_or_default();
+        }
+        match next.get(current) {
+            Some(&following) => current = following,
+            None => break,
+        }
+    }
+    Vec::new()
+}
+
+/// Names of the tagged definitions around every definition, outermost first
+///
+/// Each document is swept once in order of start, keeping the definitions
+/// that are still open on a stack.
+fn containers(ranges: &[RangeVertex], documents: usize) -> HashMap<&Id, Vec<String>> {
+    let mut by_document: Vec<Vec<(&Id, Range, Option<&str>)>> = vec![Vec::new(); documents];
+    for vertex in ranges {
+        let Some(tag) = vertex.tag.as_ref().filter(|tag| tag.is_definition()) else {
+            continue;
+        };
+        // Only definitions with a full range can enclose others
+        let name = tag.full_range.map(|_| tag.text.as_str());
+        let range = tag.full_range.unwrap_or(vertex.range);
+        by_document[vertex.document].push((&vertex.id, range, name));
+    }
+
+    let mut containers = HashMap::new();
+    for mut definitions in by_document {
+        definitions.sort_by(|(_, a, _), (_, b, _)| a.start.cmp(&b.start).then(b.end.cmp(&a.end)));
+        let mut open: Vec<(Range, &str)> = Vec::new();
+        for (id, range, name) in definitions {
+            while open
+                .last()
+                .is_some_and(|(outer, _)| !contains(outer, &range))
+            {
+                open.pop();
+            }
+            let names = open
+                .iter()
+                .filter(|(outer, _)| *outer != range)
+                .map(|(_, name)| name.to_string())
+                .collect();
+            containers.insert(id, names);
+            if let Some(name) = name {
+                open.push((range, name));
+            }
+        }
+    }
+    containers
+}
+
+fn is_function_kind(kind: SymbolKind) -> bool {
+    matches!(
+        kind,
+        SymbolKind::FUNCTION | SymbolKind::METHOD | SymbolKind::CONSTRUCTOR
+    )
+}
+
+fn file_path(uri: &str) -> Option<std::path::PathBuf> {
+    Url::parse(uri).ok()?.to_file_path().ok()
+}
+
+/// A document URI moved from the indexer's project root to the workspace
+fn local_uri(uri: &str, project_root: Option<&Path>, workspace_root: &Path) -> Result<Url> {
+    let parsed = Url::parse(uri)?;
+    let relative = parsed
+        .to_file_path()
+        .ok()
+        .zip(project_root)
+        .and_then(|(path, root)| path.strip_prefix(root).ok().map(Path::to_path_buf));
+    Ok(match relative {
+        Some(relative) => Url::from_file_path(workspace_root.join(relative)).unwrap_or(parsed),
+        None => parsed,
+    })
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn range(id: u64, line: u32, start: u32, end: u32, tag: Option<Value>) -> Value {
+        let mut vertex = json!({
+            "id": id,
+            "type": "vertex",
+            "label": "range",
+            "start": {"line": line, "character": start},
+            "end": {"line": line, "character": end},
+        });
+        if let Some(tag) = tag {
+            vertex["tag"] = tag;
+        }
+        vertex
+    }
+
+    fn definition_tag(text: &str, kind: u32, full: [u32; 4]) -> Value {
+        json!({
+            "type": "definition",
+            "text": text,
+            "kind": kind,
+            "fullRange": {
+                "start": {"line": full[0], "character": full[1]},
+                "end": {"line": full[2], "character": full[3]},
+            },
+        })
+    }
+
+    fn edge(id: u64, label: &str, out: u64, targets: &[u64]) -> Value {
+        match targets {
+            [target] if label != "contains" && label != "item" => json!({
+                "id": id, "type": "edge", "label": label, "outV": out, "inV": target,
+            }),
+            _ => json!({
+                "id": id, "type": "edge", "label": label, "outV": out, "inVs": targets,
+            }),
+        }
+    }
+
+    /// `class App { main() { this.run(); this.run(); } run() {} }` spread
+    /// over lines, indexed on a CI machine under `/ci/build`
+    fn dump() -> String {
+        let elements = vec![
+            json!({"id": 1, "type": "vertex", "label": "metaData", "version": "0.5.0",
+                   "projectRoot": "file:///ci/build", "positionEncoding": "utf-16"}),
+            json!({"id": 2, "type": "vertex", "label": "document",
+                   "uri": "file:///ci/build/src/app.ts", "languageId": "typescript"}),
+            range(3, 0, 6, 9, Some(definition_tag("App", 5, [0, 0, 9, 1]))),
+            range(4, 1, 2, 6, Some(definition_tag("main", 6, [1, 2, 4, 3]))),
+            range(5, 2, 9, 12, None),
+            range(6, 3, 9, 12, None),
+            range(7, 5, 2, 5, Some(definition_tag("run", 6, [5, 2, 5, 10]))),
+            edge(8, "contains", 2, &[3, 4, 5, 6, 7]),
+            json!({"id": 9, "type": "vertex", "label": "resultSet"}),
+            json!({"id": 10, "type": "vertex", "label": "definitionResult"}),
+            edge(11, "next", 7, &[9]),
+            edge(12, "next", 5, &[9]),
+            edge(13, "next", 6, &[9]),
+            edge(14, "textDocument/definition", 9, &[10]),
+            edge(15, "item", 10, &[7]),
+        ];
+        elements
+            .iter()
+            .map(Value::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_references_resolve_to_tagged_functions() {
+        let index = read(dump().as_bytes(), Path::new("/project")).unwrap();
+        let functions: Vec<_> = index.functions().collect();
+        assert_eq!(functions.len(), 2);
+
+        let main = &functions[0];
+        assert_eq!(main.item.name, "main");
+        assert_eq!(main.item.kind, SymbolKind::METHOD);
+        assert_eq!(main.item.uri.as_str(), "file:///project/src/app.ts");
+        assert_eq!(main.container, ["App"]);
+
+        let calls = index.outgoing_calls(&main.item);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].0.name, "run");
+        assert_eq!(
+            calls[0].1,
+            [
+                Range::new(Position::new(2, 9), Position::new(2, 12)),
+                Range::new(Position::new(3, 9), Position::new(3, 12)),
+            ]
+        );
+        assert_eq!(index.incoming_calls(&functions[1].item)[0].0.name, "main");
+    }
+
+    #[test]
+    fn test_dump_may_be_a_json_array() {
+        let array = format!("[{}]", dump().replace('\n', ","));
+        let index = read(array.as_bytes(), Path::new("/project")).unwrap();
+        assert_eq!(index.functions().count(), 2);
+    }
+
+    #[test]
+    fn test_malformed_line_is_reported() {
+        let dump = format!("{}\nnot json", dump());
+        let error = read(dump.as_bytes(), Path::new("/project")).unwrap_err();
+        assert!(error.to_string().starts_with("line 16:"), "{error}");
+    }
+}
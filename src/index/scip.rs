@@ -0,0 +1,689 @@
+//! Reading SCIP indexes (`index.scip`)
+//!
+//! Only the messages and fields the call graph needs are declared; prost
+//! skips everything else. Field numbers follow `scip.proto`.
+
+use super::{innermost, Index};
+use crate::lsp::{PositionEncoding, SourceLines};
+use crate::{CallGraphError, Result};
+use prost::Message;
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+use tower_lsp::lsp_types::{CallHierarchyItem, Position, Range, SymbolKind, Url};
+
+/// `SymbolRole.Definition`
+const DEFINITION: i32 = 0x1;
+/// `SymbolRole.Import`
+const IMPORT: i32 = 0x2;
+
+// ============================================================================
+// Protobuf Messages
+// ============================================================================
+
+#[derive(Clone, PartialEq, Message)]
+pub(super) struct ScipIndex {
+    #[prost(message, repeated, tag = "2")]
+    pub documents: Vec<Document>,
+    #[prost(message, repeated, tag = "3")]
+    pub external_symbols: Vec<SymbolInformation>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(super) struct Document {
+    /// Path relative to the project root, with `/` separators
+    #[prost(string, tag = "1")]
+    pub relative_path: String,
+    #[prost(message, repeated, tag = "2")]
+    pub occurrences: Vec<Occurrence>,
+    #[prost(message, repeated, tag = "3")]
+    pub symbols: Vec<SymbolInformation>,
+    /// Source text, only present if the indexer embeds it
+    #[prost(string, tag = "5")]
+    pub text: String,
+    /// 1 = UTF-8, 2 = UTF-16, 3 = UTF-32 code units
+    #[prost(int32, tag = "6")]
+    pub position_encoding: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(super) struct Occurrence {
+    /// `[line, start, end]` or `[start line, start, end line, end]`
+    #[prost(int32, repeated, tag = "1")]
+    pub range: Vec<i32>,
+    #[prost(string, tag = "2")]
+    pub symbol: String,
+    #[prost(int32, tag = "3")]
+    pub symbol_roles: i32,
+    /// Range of the whole definition around a definition occurrence
+    #[prost(int32, repeated, tag = "7")]
+    pub enclosing_range: Vec<i32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(super) struct SymbolInformation {
+    #[prost(string, tag = "1")]
+    pub symbol: String,
+    #[prost(int32, tag = "5")]
+    pub kind: i32,
+    #[prost(string, tag = "6")]
+    pub display_name: String,
+    #[prost(message, optional, tag = "7")]
+    pub signature_documentation: Option<Document>,
+}
+
+// ============================================================================
+// Symbols
+// ============================================================================
+
+/// What a descriptor names, from its suffix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Suffix {
+    /// `name/`
+    Namespace,
+    /// `name#`
+    Type,
+    /// `name.`
+    Term,
+    /// `name(disambiguator).`
+    Method,
+    /// `[name]`
+    TypeParameter,
+    /// `(name)`
+    Parameter,
+    /// `name:`
+    Meta,
+    /// `name!`
+    Macro,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Descriptor {
+    name: String,
+    suffix: Suffix,
+}
+
+/// A global symbol: `scheme manager package version descriptors...`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Symbol {
+    package: String,
+    descriptors: Vec<Descriptor>,
+}
+
+impl Symbol {
+    /// Parse a global symbol; `local N` symbols and malformed ones give `None`
+    fn parse(symbol: &str) -> Option<Self> {
+        if symbol.starts_with("local ") {
+            return None;
+        }
+        let mut chars = symbol.chars().peekable();
+        let _scheme = field(&mut chars)?;
+        let manager = field(&mut chars)?;
+        let package = field(&mut chars)?;
+        let _version = field(&mut chars)?;
+        let descriptors = descriptors(&mut chars)?;
+        let package = match package.as_str() {
+            "." | "" => manager,
+            _ => package,
+        };
+        Some(Self {
+            package,
+            descriptors,
+        })
+    }
+
+    /// Names of the types, terms and methods enclosing the last descriptor
+    fn container(&self) -> Vec<String> {
+        let Some((_, enclosing)) = self.descriptors.split_last() else {
+            return Vec::new();
+        };
+        enclosing
+            .iter()
+            .filter(|descriptor| {
+                matches!(
+                    descriptor.suffix,
+                    Suffix::Type | Suffix::Term | Suffix::Method
+                )
+            })
+            .map(|descriptor| descriptor.name.clone())
+            .collect()
+    }
+
+    /// Stand-in document for a symbol defined outside the index
+    ///
+    /// The host is the package and the path the descriptors, so each external
+    /// function gets its own node; the analysis collapses them per package
+    /// unless external code is included.
+    fn external_uri(&self) -> Option<Url> {
+        let host: String = self
+            .package
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+                _ => '-',
+            })
+            .collect();
+        let host = host.trim_matches('-');
+        let host = if host.is_empty() { "external" } else { host };
+        let mut uri = Url::parse(&format!("scip://{host}/")).ok()?;
+        uri.path_segments_mut()
+            .ok()?
+            .pop_if_empty()
+            .extend(self.descriptors.iter().map(|descriptor| &descriptor.name));
+        Some(uri)
+    }
+}
+
+/// A space-terminated field where a doubled space stands for a space
+fn field(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut field = String::new();
+    loop {
+        match chars.next()? {
+            ' ' if chars.peek() == Some(&' ') => {
+                chars.next();
+                field.push(' ');
+            }
+            ' ' => return Some(field),
+            c => field.push(c),
+        }
+    }
+}
+
+fn descriptors(chars: &mut Peekable<Chars>) -> Option<Vec<Descriptor>> {
+    let mut descriptors = Vec::new();
+    while let Some(&c) = chars.peek() {
+        let (name, suffix) = match c {
+            '(' | '[' => {
+                chars.next();
+                let name = name(chars)?;
+                let close = if c == '(' { ')' } else { ']' };
+                if chars.next()? != close {
+                    return None;
+                }
+                let suffix = if c == '(' {
+                    Suffix::Parameter
+                } else {
+                    Suffix::TypeParameter
+                };
+                (name, suffix)
+            }
+            _ => {
+                let name = name(chars)?;
+                let suffix = match chars.next()? {
+                    '/' => Suffix::Namespace,
+                    '#' => Suffix::Type,
+                    '.' => Suffix::Term,
+                    ':' => Suffix::Meta,
+                    '!' => Suffix::Macro,
+                    '(' => {
+                        // The disambiguator tells overloads apart
+                        while chars.next()? != ')' {}
+                        if chars.next()? != '.' {
+                            return None;
+                        }
+                        Suffix::Method
+                    }
+                    _ => return None,
+                };
+                (name, suffix)
+            }
+        };
+        descriptors.push(Descriptor { name, suffix });
+    }
+    (!descriptors.is_empty()).then_some(descriptors)
+}
+
+/// An identifier, or a backquoted name where a doubled backquote stands for one
+fn name(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut name = String::new();
+    if chars.peek() == Some(&'`') {
+        chars.next();
+        loop {
+            match chars.next()? {
+                '`' if chars.peek() == Some(&'`') => {
+                    chars.next();
+                    name.push('`');
+                }
+                '`' => return Some(name),
+                c => name.push(c),
+            }
+        }
+    }
+    while let Some(&c) = chars.peek() {
+        if !(c.is_alphanumeric() || matches!(c, '_' | '+' | '-' | '$')) {
+            break;
+        }
+        name.push(c);
+        chars.next();
+    }
+    Some(name)
+}
+
+/// `SymbolInformation.Kind` values of function-like symbols
+fn function_kind(kind: i32) -> Option<SymbolKind> {
+    match kind {
+        9 => Some(SymbolKind::CONSTRUCTOR),
+        17 => Some(SymbolKind::FUNCTION),
+        26 => Some(SymbolKind::METHOD),
+        _ => None,
+    }
+}
+
+/// A function as described by its symbol and, if known, its information
+struct FunctionSymbol {
+    name: String,
+    kind: SymbolKind,
+    detail: Option<String>,
+    container: Vec<String>,
+}
+
+/// Describe `symbol` if it is a function: by its kind, or by a method
+/// descriptor where the indexer leaves the kind out
+fn function_symbol(
+    symbol: &str,
+    information: Option<&SymbolInformation>,
+) -> Option<FunctionSymbol> {
+    let parsed = Symbol::parse(symbol);
+    let last = parsed.as_ref().and_then(|parsed| parsed.descriptors.last());
+    let container = parsed.as_ref().map(Symbol::container).unwrap_or_default();
+    let kind = match information.and_then(|information| function_kind(information.kind)) {
+        Some(kind) => kind,
+        None if last.is_some_and(|last| last.suffix == Suffix::Method) => {
+            let in_type = parsed.as_ref().is_some_and(|parsed| {
+                parsed.descriptors[..parsed.descriptors.len() - 1]
+                    .iter()
+                    .any(|descriptor| descriptor.suffix == Suffix::Type)
+            });
+            if in_type {
+                SymbolKind::METHOD
+            } else {
+                SymbolKind::FUNCTION
+            }
+        }
+        None => return None,
+    };
+
+    let name = information
+        .map(|information| information.display_name.clone())
+        .filter(|name| !name.is_empty())
+        .or_else(|| last.map(|last| last.name.clone()))
+        .unwrap_or_else(|| symbol.to_string());
+    let detail = information
+        .and_then(|information| information.signature_documentation.as_ref())
+        .map(|signature| signature.text.clone())
+        .filter(|text| !text.is_empty());
+    Some(FunctionSymbol {
+        name,
+        kind,
+        detail,
+        container,
+    })
+}
+
+// ============================================================================
+// Index Construction
+// ============================================================================
+
+/// A document with what is needed to convert its positions
+struct Source<'a> {
+    document: &'a Document,
+    uri: Url,
+    lines: SourceLines,
+    encoding: PositionEncoding,
+    /// Symbol information declared in this document, including locals
+    symbols: HashMap<&'a str, &'a SymbolInformation>,
+    /// Functions defined here with their extent
+    scopes: Vec<Scope>,
+}
+
+impl Source<'_> {
+    /// Convert an occurrence range to character columns
+    fn range(&self, values: &[i32]) -> Option<Range> {
+        let values: Vec<u32> = values
+            .iter()
+            .map(|&value| u32::try_from(value).ok())
+            .collect::<Option<_>>()?;
+        let range = match values[..] {
+            [line, start, end] => Range::new(Position::new(line, start), Position::new(line, end)),
+            [start_line, start, end_line, end] => Range::new(
+                Position::new(start_line, start),
+                Position::new(end_line, end),
+            ),
+            _ => return None,
+        };
+        Some(self.lines.range_to_columns(self.encoding, range))
+    }
+
+    /// Local symbols are only unique within their document
+    fn key(&self, symbol: &str) -> String {
+        if symbol.starts_with("local ") {
+            format!("{} {}", self.document.relative_path, symbol)
+        } else {
+            symbol.to_string()
+        }
+    }
+
+    /// The function a reference at `site` occurs in
+    ///
+    /// Older indexers leave out enclosing ranges; there the closest
+    /// definition before the reference is the best guess.
+    fn caller(&self, site: &Range) -> Option<usize> {
+        if self.scopes.iter().any(|scope| scope.enclosing) {
+            let scopes = self
+                .scopes
+                .iter()
+                .filter(|scope| scope.enclosing)
+                .map(|scope| (scope.range, scope.function));
+            innermost(scopes, site).map(|(_, function)| function)
+        } else {
+            self.scopes
+                .iter()
+                .filter(|scope| scope.range.start <= site.start)
+                .max_by_key(|scope| scope.range.start)
+                .map(|scope| scope.function)
+        }
+    }
+}
+
+struct Scope {
+    function: usize,
+    range: Range,
+    /// Whether `range` is the whole definition rather than just the name
+    enclosing: bool,
+}
+
+/// Build an index from a SCIP protobuf
+pub(super) fn read(bytes: &[u8], workspace_root: &Path) -> Result<Index> {
+    let scip = ScipIndex::decode(bytes)?;
+    let external: HashMap<&str, &SymbolInformation> = scip
+        .external_symbols
+        .iter()
+        .chain(scip.documents.iter().flat_map(|document| &document.symbols))
+        .filter(|information| !information.symbol.starts_with("local "))
+        .map(|information| (information.symbol.as_str(), information))
+        .collect();
+
+    let mut index = Index::default();
+    let mut definitions: HashMap<String, usize> = HashMap::new();
+    let mut sources = Vec::new();
+    for document in &scip.documents {
+        let path = workspace_root.join(&document.relative_path);
+        let uri = Url::from_file_path(&path).map_err(|_| {
+            CallGraphError::AnalysisError(format!("Invalid document path {}", path.display()))
+        })?;
+        let lines = if document.text.is_empty() {
+            SourceLines::read(&path)
+        } else {
+            SourceLines::new(&document.text)
+        };
+        let encoding = match document.position_encoding {
+            1 => PositionEncoding::Utf8,
+            3 => PositionEncoding::Utf32,
+            _ => PositionEncoding::Utf16,
+        };
+        let symbols = document
+            .symbols
+            .iter()
+            .map(|information| (information.symbol.as_str(), information))
+            .collect();
+        let mut source = Source {
+            document,
+            uri,
+            lines,
+            encoding,
+            symbols,
+            scopes: Vec::new(),
+        };
+
+        for occurrence in &document.occurrences {
+            if occurrence.symbol_roles & DEFINITION == 0 {
+                continue;
+            }
+            let information = source
+                .symbols
+                .get(occurrence.symbol.as_str())
+                .or_else(|| external.get(occurrence.symbol.as_str()))
+                .copied();
+            let Some(function) = function_symbol(&occurrence.symbol, information) else {
+                continue;
+            };
+            let Some(selection_range) = source.range(&occurrence.range) else {
+                continue;
+            };
+            let enclosing = source.range(&occurrence.enclosing_range);
+            let item = CallHierarchyItem {
+                name: function.name,
+                kind: function.kind,
+                tags: None,
+                detail: function.detail,
+                uri: source.uri.clone(),
+                range: enclosing.unwrap_or(selection_range),
+                selection_range,
+                data: None,
+            };
+            let id = index.add_function(item, function.container);
+            definitions.insert(source.key(&occurrence.symbol), id);
+            source.scopes.push(Scope {
+                function: id,
+                range: enclosing.unwrap_or(selection_range),
+                enclosing: enclosing.is_some(),
+            });
+        }
+        sources.push(source);
+    }
+
+    for source in &sources {
+        for occurrence in &source.document.occurrences {
+            if occurrence.symbol_roles & (DEFINITION | IMPORT) != 0 {
+                continue;
+            }
+            let key = source.key(&occurrence.symbol);
+            let callee = match definitions.get(&key) {
+                Some(&callee) => callee,
+                None => {
+                    let information = external.get(occurrence.symbol.as_str()).copied();
+                    let Some(callee) =
+                        external_function(&mut index, &occurrence.symbol, information)
+                    else {
+                        continue;
+                    };
+                    definitions.insert(key, callee);
+                    callee
+                }
+            };
+            let Some(site) = source.range(&occurrence.range) else {
+                continue;
+            };
+            if let Some(caller) = source.caller(&site) {
+                index.add_call(caller, callee, site);
+            }
+        }
+    }
+    Ok(index)
+}
+
+/// Add a function that is referenced but not defined in the index
+fn external_function(
+    index: &mut Index,
+    symbol: &str,
+    information: Option<&SymbolInformation>,
+) -> Option<usize> {
+    let function = function_symbol(symbol, information)?;
+    let uri = Symbol::parse(symbol)?.external_uri()?;
+    let item = CallHierarchyItem {
+        name: function.name,
+        kind: function.kind,
+        tags: None,
+        detail: function.detail,
+        uri,
+        range: Range::default(),
+        selection_range: Range::default(),
+        data: None,
+    };
+    Some(index.add_function(item, function.container))
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIN: &str = "rust-analyzer cargo app 0.1.0 main().";
+    const RUN: &str = "rust-analyzer cargo app 0.1.0 runner/Runner#run().";
+    const PRINT: &str = "rust-analyzer cargo std 1.80.0 io/print().";
+
+    fn occurrence(symbol: &str, range: &[i32], roles: i32, enclosing: &[i32]) -> Occurrence {
+        Occurrence {
+            range: range.to_vec(),
+            symbol: symbol.to_string(),
+            symbol_roles: roles,
+            enclosing_range: enclosing.to_vec(),
+        }
+    }
+
+    fn information(symbol: &str, kind: i32, display_name: &str) -> SymbolInformation {
+        SymbolInformation {
+            symbol: symbol.to_string(),
+            kind,
+            display_name: display_name.to_string(),
+            signature_documentation: None,
+        }
+    }
+
+    /// `main` calls `Runner::run` twice; `run` prints through the standard library
+    fn index() -> ScipIndex {
+        let text = "fn main() {\n    Runner::run(); Runner::run();\n}\n\
+                    impl Runner {\n    fn run() { print(\"é\"); }\n}\n";
+        let document = Document {
+            relative_path: "src/main.rs".to_string(),
+            occurrences: vec![
+                occurrence(MAIN, &[0, 3, 7], DEFINITION, &[0, 0, 2, 1]),
+                occurrence(RUN, &[1, 12, 15], 0, &[]),
+                occurrence(RUN, &[1, 27, 30], 0, &[]),
+                occurrence(RUN, &[4, 7, 10], DEFINITION, &[4, 4, 29]),
+                occurrence(PRINT, &[4, 15, 20], 0, &[]),
+                occurrence("local 1", &[4, 21, 25], 0, &[]),
+            ],
+            symbols: vec![
+                information(MAIN, 17, "main"),
+                information(RUN, 0, ""),
+                information("local 1", 0, "s"),
+            ],
+            text: text.to_string(),
+            position_encoding: 1,
+        };
+        ScipIndex {
+            documents: vec![document],
+            external_symbols: vec![information(PRINT, 17, "print")],
+        }
+    }
+
+    fn read_index(scip: &ScipIndex) -> Index {
+        read(&scip.encode_to_vec(), Path::new("/project")).unwrap()
+    }
+
+    fn names(calls: Vec<(CallHierarchyItem, Vec<Range>)>) -> Vec<String> {
+        calls.into_iter().map(|(item, _)| item.name).collect()
+    }
+
+    #[test]
+    fn test_symbol_parsing() {
+        let symbol =
+            Symbol::parse("scip-typescript npm @types/node 1.0 `fs.d.ts`/`x``y`#(a)").unwrap();
+        assert_eq!(symbol.package, "@types/node");
+        assert_eq!(
+            symbol.descriptors,
+            [
+                Descriptor {
+                    name: "fs.d.ts".to_string(),
+                    suffix: Suffix::Namespace
+                },
+                Descriptor {
+                    name: "x`y".to_string(),
+                    suffix: Suffix::Type
+                },
+                Descriptor {
+                    name: "a".to_string(),
+                    suffix: Suffix::Parameter
+                },
+            ]
+        );
+
+        let symbol = Symbol::parse(RUN).unwrap();
+        assert_eq!(symbol.container(), ["Runner"]);
+        assert_eq!(symbol.descriptors[2].suffix, Suffix::Method);
+
+        // Doubled spaces escape a space inside a field
+        let symbol = Symbol::parse("scip-python python my  pkg 1 f().").unwrap();
+        assert_eq!(symbol.package, "my pkg");
+
+        assert_eq!(Symbol::parse("local 7"), None);
+        assert_eq!(Symbol::parse("scip cargo app 1 unterminated("), None);
+    }
+
+    #[test]
+    fn test_external_uri_names_the_package() {
+        let uri = Symbol::parse(PRINT).unwrap().external_uri().unwrap();
+        assert_eq!(uri.as_str(), "scip://std/io/print");
+        let uri = Symbol::parse("scip-typescript npm @types/node 1 fs/read().")
+            .unwrap()
+            .external_uri()
+            .unwrap();
+        assert_eq!(uri.host_str(), Some("types-node"));
+    }
+
+    #[test]
+    fn test_references_inside_definitions_become_calls() {
+        let index = read_index(&index());
+        let functions: Vec<_> = index.functions().collect();
+        assert_eq!(functions.len(), 3);
+
+        let main = &functions[0].item;
+        assert_eq!(main.uri.as_str(), "file:///project/src/main.rs");
+        assert_eq!(
+            main.range,
+            Range::new(Position::new(0, 0), Position::new(2, 1))
+        );
+        let run = &functions[1];
+        assert_eq!(run.item.name, "run");
+        assert_eq!(run.item.kind, SymbolKind::METHOD);
+        assert_eq!(run.container, ["Runner"]);
+
+        let calls = index.outgoing_calls(main);
+        assert_eq!(names(calls.clone()), ["run"]);
+        assert_eq!(calls[0].1.len(), 2);
+        assert_eq!(names(index.incoming_calls(&run.item)), ["main"]);
+
+        // The standard library is referenced, not defined
+        let calls = index.outgoing_calls(&run.item);
+        assert_eq!(names(calls.clone()), ["print"]);
+        assert_eq!(calls[0].0.uri.host_str(), Some("std"));
+    }
+
+    #[test]
+    fn test_positions_are_converted_to_characters() {
+        let index = read_index(&index());
+        let run = &index.functions().nth(1).unwrap().item;
+        // The line holds `é` (two UTF-8 bytes) before the end of `run`
+        assert_eq!(
+            run.range,
+            Range::new(Position::new(4, 4), Position::new(4, 28))
+        );
+    }
+
+    #[test]
+    fn test_callers_without_enclosing_ranges_are_guessed() {
+        let mut scip = index();
+        for occurrence in &mut scip.documents[0].occurrences {
+            occurrence.enclosing_range.clear();
+        }
+        let index = read_index(&scip);
+        let main = &index.functions().next().unwrap().item;
+        assert_eq!(main.range, main.selection_range);
+        assert_eq!(names(index.outgoing_calls(main)), ["run"]);
+    }
+}
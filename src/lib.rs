@@ -5,13 +5,19 @@
 
 pub mod analyzer;
 pub mod config;
+pub mod filter;
+pub mod index;
 pub mod lsp;
 pub mod output;
+pub mod source;
 
-pub use analyzer::CallGraph;
+pub use analyzer::{CallEdge, CallGraph, FunctionNode, NodeIndex, SymbolId};
 pub use config::{CliArgs, Config};
-pub use lsp::LspClient;
+pub use filter::ExcludeFilter;
+pub use index::Index;
+pub use lsp::{LspClient, LspConnection};
 pub use output::DotGenerator;
+pub use source::{CallGraphSource, FunctionSymbol};
 
 /// Main result type for the library
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
@@ -33,4 +39,10 @@ pub enum CallGraphError {
 
     #[error("Output generation error: {0}")]
     OutputError(String),
+
+    #[error("LSP request timed out: {0}")]
+    Timeout(String),
+
+    #[error("Language server exited: {0}")]
+    ServerExited(String),
 }
@@ -1,64 +1,23 @@
 //! LSP (Language Server Protocol) communication module
 
-use crate::config::Config;
-use crate::Result;
-
-// ============================================================================
-// LSP Client
-// ============================================================================
-
-/// LSP client for communicating with language servers
-pub struct LspClient {
-    // TODO: Add actual LSP client fields
-}
-
-impl LspClient {
-    /// Create a new LSP client
-    pub async fn new(_config: &Config) -> Result<Self> {
-        // TODO: Implement LSP client initialization
-        Ok(Self {})
-    }
-}
-
-// ============================================================================
-// LSP Protocol Types
-// ============================================================================
-
-// TODO: Implement LSP protocol types and structures
-
-// ============================================================================
-// LSP Response Handlers
-// ============================================================================
-
-// TODO: Implement LSP response handlers
-
-// ============================================================================
-// Tests
-// ============================================================================
-
+mod client;
+mod connection;
+mod detect;
+mod handlers;
+mod position;
+mod protocol;
+mod source;
 #[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_lsp_client_creation_placeholder() {
-        // This is a placeholder test for LSP client creation
-        // Once LSP implementation is complete, this should be expanded
-        let _config = Config {
-            file_path: None,
-            project_path: None,
-            output_path: "test.dot".to_string(),
-            root_function: None,
-            max_depth: None,
-            exclude_patterns: vec![],
-            lsp_server_command: None,
-            verbose: false,
-            analysis: crate::config::AnalysisConfig::default(),
-            output: crate::config::OutputConfig::default(),
-            lsp: crate::config::LspConfig::default(),
-        };
-
-        // This will be tested properly once LSP implementation is complete
-        assert!(true);
-    }
-}
+pub(crate) mod test_support;
+mod transcript;
+mod transport;
+
+pub use client::LspClient;
+pub use connection::LspConnection;
+pub use detect::{detect_languages, detect_servers, KnownLanguage, KNOWN_LANGUAGES};
+pub use position::{PositionEncoding, SourceLines};
+pub use protocol::{language_id, read_message, write_message, Message};
+pub use transcript::{Exchange, Recorder, ReplayTransport, Reply, Transcript};
+#[cfg(unix)]
+pub use transport::UnixTransport;
+pub use transport::{Channel, StdioTransport, TcpTransport, Transport};
@@ -0,0 +1,490 @@
+//! Pool of language servers with one connection per language
+
+use super::connection::LspConnection;
+use super::detect::{self, KnownLanguage};
+use super::position::PositionEncoding;
+use super::source::Fallbacks;
+use super::transcript::{self, Recorder, ReplayTransport, Transcript};
+use super::transport::{self, Transport};
+use crate::config::{Config, ServerOptions};
+use crate::{CallGraphError, Result};
+use futures::future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tower_lsp::lsp_types::{request, Url};
+use tracing::{info, warn};
+
+// ============================================================================
+// LSP Client
+// ============================================================================
+
+/// LSP client for communicating with language servers
+///
+/// Runs one server per language and routes every document to the server for
+/// its language. A server without a language handles all remaining documents.
+#[derive(Default)]
+pub struct LspClient {
+    servers: Vec<LanguageServer>,
+    /// Workspace root the servers were initialized for
+    root: Option<PathBuf>,
+    /// Call discovery for servers without a call hierarchy
+    pub(super) fallbacks: Fallbacks,
+}
+
+/// A running server and the language it is responsible for
+struct LanguageServer {
+    /// Name of a known language, or `None` for a catch-all server
+    language: Option<&'static str>,
+    connection: LspConnection,
+    /// How to reach the server again if it exits; `None` if it cannot be
+    transport: Option<Box<dyn Transport>>,
+}
+
+impl LanguageServer {
+    fn label(&self) -> &'static str {
+        self.language.unwrap_or("all documents")
+    }
+}
+
+impl LspClient {
+    /// Start and initialize the language servers for the configured workspace
+    ///
+    /// Servers come from `[lsp.servers.<lang>]`, plus `--lsp-server` or
+    /// `[lsp] server_command` as a catch-all. Without either, one server is
+    /// detected for every language found in the analyzed code. A server is
+    /// either a command line or a `tcp://` / `unix://` address.
+    ///
+    /// With `--replay-lsp` the servers are replaced by the recorded session,
+    /// and with `--record-lsp` the session is written to a transcript.
+    pub async fn new(config: &Config) -> Result<Self> {
+        let root = config.workspace_root()?;
+        let mut client = match &config.replay_lsp {
+            Some(path) => Self::replay(path, &root).await?,
+            None => Self::start(config, &root).await?,
+        };
+        if let Some(path) = &config.record_lsp {
+            info!("Recording the LSP session to {}", path.display());
+            let transcript = Arc::new(Transcript::create(path)?);
+            for server in &mut client.servers {
+                let recorder = Recorder::new(transcript.clone(), server.language, &root);
+                server.connection.set_recorder(Some(recorder));
+            }
+        }
+        client.initialize(&root).await?;
+        Ok(client)
+    }
+
+    /// Connect to the configured or detected servers
+    async fn start(config: &Config, root: &Path) -> Result<Self> {
+        let mut servers: Vec<(Option<&'static str>, String, &ServerOptions)> = Vec::new();
+        for (name, server) in &config.lsp.servers {
+            match KnownLanguage::named(name) {
+                Some(language) => {
+                    servers.push((Some(language.name), server.command.clone(), &server.options))
+                }
+                None => warn!("Ignoring [lsp.servers.{}]: unknown language", name),
+            }
+        }
+        if let Some(command) = config
+            .lsp_server_command
+            .as_deref()
+            .or(config.lsp.server_command.as_deref())
+        {
+            servers.push((None, command.to_string(), &config.lsp.options));
+        }
+        if servers.is_empty() {
+            servers = detect::detect_servers(config)?
+                .into_iter()
+                .map(|(language, command)| {
+                    (
+                        Some(language.name),
+                        command.to_string(),
+                        &config.lsp.options,
+                    )
+                })
+                .collect();
+        }
+
+        let timeout = (config.lsp.timeout_seconds > 0)
+            .then(|| Duration::from_secs(config.lsp.timeout_seconds));
+        let mut client = Self::default();
+        for (language, server, options) in servers {
+            let transport = transport::parse(&server, root, options)?;
+            let mut connection = LspConnection::connect(transport.as_ref()).await?;
+            connection.set_request_timeout(timeout);
+            connection.set_initialization_options(options.initialization_options.clone());
+            connection.set_settings(options.settings.clone().unwrap_or_default());
+            client.add_restartable_server(language, connection, transport);
+        }
+        Ok(client)
+    }
+
+    /// Stand in for the servers of a recorded session
+    async fn replay(path: &Path, root: &Path) -> Result<Self> {
+        info!("Replaying the LSP session from {}", path.display());
+        let transports = ReplayTransport::from_transcript(transcript::load(path)?, root);
+        if transports.is_empty() {
+            return Err(CallGraphError::ConfigError(format!(
+                "LSP transcript {} is empty",
+                path.display()
+            ))
+            .into());
+        }
+
+        let mut client = Self::default();
+        for transport in transports {
+            let language = transport
+                .server()
+                .and_then(KnownLanguage::named)
+                .map(|language| language.name);
+            let connection = LspConnection::connect(&transport).await?;
+            client.add_restartable_server(language, connection, Box::new(transport));
+        }
+        Ok(client)
+    }
+
+    /// Wrap a single connection that handles every document
+    pub fn from_connection(connection: LspConnection) -> Self {
+        let mut client = Self::default();
+        client.add_server(None, connection);
+        client
+    }
+
+    /// Add a server for a language, or for every document when `None`
+    pub fn add_server(&mut self, language: Option<&'static str>, connection: LspConnection) {
+        self.servers.push(LanguageServer {
+            language,
+            connection,
+            transport: None,
+        });
+    }
+
+    /// Add a server that is reconnected through `transport` should it exit
+    pub fn add_restartable_server(
+        &mut self,
+        language: Option<&'static str>,
+        connection: LspConnection,
+        transport: Box<dyn Transport>,
+    ) {
+        self.servers.push(LanguageServer {
+            language,
+            connection,
+            transport: Some(transport),
+        });
+    }
+
+    /// Initialize every server for a workspace root concurrently
+    pub async fn initialize(&mut self, root: &Path) -> Result<()> {
+        self.root = Some(root.to_path_buf());
+        future::try_join_all(self.servers.iter_mut().map(|server| async move {
+            server.connection.initialize(root).await?;
+            info!("Language server ready for {}", server.label());
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+        }))
+        .await?;
+        Ok(())
+    }
+
+    /// Start and initialize again every server whose connection has closed
+    ///
+    /// Returns how many servers were restarted. Fails if a server that exited
+    /// was not added with a way to start it again.
+    pub async fn restart_exited(&mut self) -> Result<usize> {
+        let mut restarted = 0;
+        for server in &mut self.servers {
+            if !server.connection.is_closed() {
+                continue;
+            }
+            let (Some(transport), Some(root)) = (&server.transport, &self.root) else {
+                return Err(CallGraphError::ServerExited(format!(
+                    "the server for {} cannot be restarted",
+                    server.label()
+                ))
+                .into());
+            };
+            warn!("Restarting the language server for {}", server.label());
+            let mut connection = LspConnection::connect(transport.as_ref()).await?;
+            connection.set_request_timeout(server.connection.request_timeout());
+            connection.set_recorder(server.connection.recorder().cloned());
+            connection
+                .set_initialization_options(server.connection.initialization_options().cloned());
+            connection.set_settings(server.connection.settings());
+            connection.initialize(root).await?;
+            server.connection = connection;
+            restarted += 1;
+        }
+        Ok(restarted)
+    }
+
+    /// Wait until every server has finished its `$/progress` work
+    ///
+    /// Returns `false` if a server is still busy when `timeout` expires.
+    pub async fn wait_until_ready(&self, timeout: Duration) -> bool {
+        let ready = future::join_all(
+            self.connections()
+                .map(|connection| connection.wait_until_ready(timeout)),
+        )
+        .await;
+        ready.into_iter().all(|ready| ready)
+    }
+
+    /// Shut down every server, reporting the first failure
+    pub async fn shutdown(&mut self) -> Result<()> {
+        let results = future::join_all(
+            self.servers
+                .iter_mut()
+                .map(|server| server.connection.shutdown()),
+        )
+        .await;
+        results.into_iter().collect()
+    }
+
+    // ------------------------------------------------------------------------
+    // Routing
+    // ------------------------------------------------------------------------
+
+    /// The server responsible for a document
+    ///
+    /// Documents of an unknown language go to the catch-all server, or to the
+    /// first server if there is none.
+    pub fn connection_for(&self, uri: &Url) -> Option<&LspConnection> {
+        let language = KnownLanguage::for_path(Path::new(uri.path())).map(|l| l.name);
+        self.servers
+            .iter()
+            .find(|server| server.language.is_some() && server.language == language)
+            .or_else(|| self.servers.iter().find(|server| server.language.is_none()))
+            .or_else(|| self.servers.first().filter(|_| language.is_none()))
+            .map(|server| &server.connection)
+    }
+
+    /// Position encoding negotiated with the server for a document
+    ///
+    /// Documents no server handles get the protocol default, UTF-16.
+    pub fn position_encoding(&self, uri: &Url) -> PositionEncoding {
+        self.connection_for(uri)
+            .map(LspConnection::position_encoding)
+            .unwrap_or_default()
+    }
+
+    /// Every running server
+    pub fn connections(&self) -> impl Iterator<Item = &LspConnection> {
+        self.servers.iter().map(|server| &server.connection)
+    }
+
+    /// Send a typed request to the server responsible for a document
+    pub async fn request_for<R>(&self, uri: &Url, params: R::Params) -> Result<R::Result>
+    where
+        R: request::Request,
+    {
+        self.require_connection(uri)?.request::<R>(params).await
+    }
+
+    /// Open a document on the server responsible for it
+    ///
+    /// Returns the document URI to use in subsequent requests.
+    pub async fn open_document(&self, path: &Path) -> Result<Url> {
+        let uri = Url::from_file_path(path.canonicalize()?).map_err(|_| {
+            CallGraphError::ConfigError(format!("Invalid document path: {}", path.display()))
+        })?;
+        self.require_connection(&uri)?.open_document(path).await
+    }
+
+    fn require_connection(&self, uri: &Url) -> Result<&LspConnection> {
+        self.connection_for(uri).ok_or_else(|| {
+            CallGraphError::AnalysisError(format!("No language server configured for {uri}")).into()
+        })
+    }
+
+    // ------------------------------------------------------------------------
+    // Capabilities
+    // ------------------------------------------------------------------------
+
+    /// Whether any server provides the call hierarchy
+    pub fn supports_call_hierarchy(&self) -> bool {
+        self.connections()
+            .any(LspConnection::supports_call_hierarchy)
+    }
+
+    /// Whether any server provides `textDocument/documentSymbol`
+    pub fn supports_document_symbols(&self) -> bool {
+        self.connections()
+            .any(LspConnection::supports_document_symbols)
+    }
+
+    /// Whether any server provides `workspace/symbol`
+    pub fn supports_workspace_symbols(&self) -> bool {
+        self.connections()
+            .any(LspConnection::supports_workspace_symbols)
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp::test_support::{connect, restartable_client};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_lsp_client_requires_a_server() {
+        // An empty project gives the detection nothing to go on
+        let temp_dir = tempfile::tempdir().unwrap();
+        let config = test_config(temp_dir.path());
+
+        let result = LspClient::new(&config).await;
+        assert!(result.is_err());
+    }
+
+    fn test_config(project: &Path) -> Config {
+        Config {
+            file_path: None,
+            project_path: Some(project.to_path_buf()),
+            output_path: "test.dot".to_string(),
+            root_function: None,
+            max_depth: None,
+            direction: crate::config::CallDirection::Callees,
+            exclude_patterns: vec![],
+            lsp_server_command: None,
+            record_lsp: None,
+            replay_lsp: None,
+            index: None,
+            verbose: false,
+            analysis: crate::config::AnalysisConfig::default(),
+            output: crate::config::OutputConfig::default(),
+            lsp: crate::config::LspConfig::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_documents_are_routed_by_language() {
+        let mut client = LspClient::default();
+        let (rust, rust_server) = connect();
+        let (python, python_server) = connect();
+        client.add_server(Some("rust"), rust);
+        client.add_server(Some("python"), python);
+
+        rust_server.serve(|method, _| match method {
+            "initialize" => Ok(json!({"capabilities": {"callHierarchyProvider": true}})),
+            _ => Ok(json!("rust")),
+        });
+        python_server.serve(|method, _| match method {
+            "initialize" => Ok(json!({"capabilities": {}})),
+            _ => Ok(json!("python")),
+        });
+        client.initialize(&std::env::temp_dir()).await.unwrap();
+        assert!(client.supports_call_hierarchy());
+        assert!(!client.supports_document_symbols());
+
+        let ask = |path: &str| {
+            let uri = Url::parse(&format!("file://{path}")).unwrap();
+            let connection = client.connection_for(&uri);
+            async move {
+                connection
+                    .unwrap()
+                    .send_request("test/which", json!({}))
+                    .await
+            }
+        };
+        assert_eq!(ask("/p/src/lib.rs").await.unwrap(), json!("rust"));
+        assert_eq!(ask("/p/tools/gen.py").await.unwrap(), json!("python"));
+
+        // No catch-all: known languages without a server are not routed
+        let uri = Url::parse("file:///p/web/app.ts").unwrap();
+        assert!(client.connection_for(&uri).is_none());
+        let uri = Url::parse("jdt://contents/rt.jar").unwrap();
+        assert!(client.connection_for(&uri).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_catch_all_server_takes_remaining_documents() {
+        let mut client = LspClient::default();
+        let (rust, _rust_server) = connect();
+        let (fallback, _fallback_server) = connect();
+        client.add_server(Some("rust"), rust);
+        client.add_server(None, fallback);
+
+        let rust_uri = Url::parse("file:///p/src/lib.rs").unwrap();
+        let ts_uri = Url::parse("file:///p/web/app.ts").unwrap();
+        let rust = client.connection_for(&rust_uri).unwrap();
+        let other = client.connection_for(&ts_uri).unwrap();
+        assert!(!std::ptr::eq(rust, other));
+        assert!(std::ptr::eq(other, client.connections().nth(1).unwrap()));
+    }
+
+    async fn which(client: &LspClient) -> Result<serde_json::Value> {
+        let uri = Url::parse("file:///p/src/lib.rs").unwrap();
+        let connection = client.connection_for(&uri).unwrap();
+        connection.send_request("test/which", json!({})).await
+    }
+
+    #[tokio::test]
+    async fn test_exited_server_is_restarted() {
+        let mut client = restartable_client(|start, server| {
+            // The first server dies on its first query
+            server.serve_until(
+                move |method, _| match method {
+                    "initialize" => Some(Ok(json!({"capabilities": {}}))),
+                    _ => Some(Ok(json!(start))),
+                },
+                move |method, _| start == 0 && method == "test/which",
+            );
+        })
+        .await;
+        client.initialize(&std::env::temp_dir()).await.unwrap();
+        assert_eq!(client.restart_exited().await.unwrap(), 0);
+
+        assert!(which(&client).await.is_err());
+        assert!(client.connections().next().unwrap().is_closed());
+
+        assert_eq!(client.restart_exited().await.unwrap(), 1);
+        assert_eq!(which(&client).await.unwrap(), json!(1));
+    }
+
+    #[tokio::test]
+    async fn test_recorded_session_is_replayed_without_a_server() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let transcript_path = temp_dir.path().join("session.jsonl");
+
+        let (mut connection, server) = connect();
+        let transcript = Arc::new(Transcript::create(&transcript_path).unwrap());
+        connection.set_recorder(Some(Recorder::new(
+            transcript,
+            Some("rust"),
+            temp_dir.path(),
+        )));
+        server.serve(|method, _| match method {
+            "initialize" => Ok(json!({"capabilities": {"callHierarchyProvider": true}})),
+            "shutdown" => Ok(serde_json::Value::Null),
+            _ => Ok(json!("recorded")),
+        });
+        let mut client = LspClient::default();
+        client.add_server(Some("rust"), connection);
+        client.initialize(temp_dir.path()).await.unwrap();
+        assert_eq!(which(&client).await.unwrap(), json!("recorded"));
+        client.shutdown().await.unwrap();
+
+        let mut config = test_config(temp_dir.path());
+        config.replay_lsp = Some(transcript_path);
+        let mut client = LspClient::new(&config).await.unwrap();
+        assert!(client.supports_call_hierarchy());
+        assert_eq!(which(&client).await.unwrap(), json!("recorded"));
+        let uri = Url::parse("file:///p/web/app.ts").unwrap();
+        assert!(client.connection_for(&uri).is_none());
+        client.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_server_without_transport_is_not_restarted() {
+        let (connection, server) = connect();
+        drop(server);
+        let mut client = LspClient::from_connection(connection);
+        client.initialize(&std::env::temp_dir()).await.unwrap_err();
+
+        let error = client.restart_exited().await.unwrap_err();
+        assert!(error.to_string().contains("cannot be restarted"));
+    }
+}
@@ -0,0 +1,980 @@
+//! Connection speaking JSON-RPC to a single language server
+
+use super::handlers::{self, Progress};
+use super::position::PositionEncoding;
+use super::protocol::{self, Message};
+use super::transcript::{Recorder, Reply};
+use super::transport::{StdioTransport, Transport};
+use crate::{CallGraphError, Result};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::process::Child;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tower_lsp::jsonrpc::{self, Id, Request, Response};
+use tower_lsp::lsp_types::{
+    notification, request, CallHierarchyServerCapability, DidChangeConfigurationParams,
+    DidOpenTextDocumentParams, InitializedParams, SemanticTokensFullOptions, SemanticTokensLegend,
+    SemanticTokensServerCapabilities, ServerCapabilities, TextDocumentItem, Url,
+};
+use tracing::{debug, error, info, warn};
+
+type SharedWriter = Arc<tokio::sync::Mutex<Box<dyn AsyncWrite + Send + Unpin>>>;
+
+type SharedSettings = Arc<Mutex<Value>>;
+
+/// How long the server gets to exit on its own after `exit` before it is killed
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// How long the server must report no progress before it counts as ready
+const READINESS_QUIET_PERIOD: Duration = Duration::from_secs(1);
+
+/// How many of the server's last stderr lines are kept for error reports
+const STDERR_TAIL_LINES: usize = 20;
+
+/// How long a server that closed its output gets to exit before it is reported
+const EXIT_STATUS_WAIT: Duration = Duration::from_millis(500);
+
+// ============================================================================
+// LSP Connection
+// ============================================================================
+
+/// Connection to one language server
+///
+/// Requests may be issued concurrently; responses are matched to their
+/// callers by request id by a background reader task.
+pub struct LspConnection {
+    writer: SharedWriter,
+    pending: Arc<PendingRequests>,
+    progress: Arc<Progress>,
+    next_id: AtomicI64,
+    reader_task: JoinHandle<()>,
+    child: Mutex<Option<Child>>,
+    stderr_tail: Arc<Mutex<StderrTail>>,
+    capabilities: ServerCapabilities,
+    open_documents: Mutex<HashSet<Url>>,
+    /// Requests unanswered for this long are cancelled; `None` waits forever
+    request_timeout: Option<Duration>,
+    recorder: Option<Recorder>,
+    /// Sent as `initializationOptions` in the `initialize` request
+    initialization_options: Option<Value>,
+    /// Answer to `workspace/configuration`, shared with the reader task
+    settings: SharedSettings,
+    shut_down: bool,
+}
+
+impl LspConnection {
+    /// Spawn a language server process and connect to its stdin/stdout
+    ///
+    /// The command line is split on whitespace into program and arguments.
+    pub async fn spawn(command_line: &str, working_dir: &Path) -> Result<Self> {
+        Self::connect(&StdioTransport::new(command_line, working_dir)).await
+    }
+
+    /// Open a transport and connect to the server behind it
+    pub async fn connect(transport: &dyn Transport) -> Result<Self> {
+        let channel = transport.connect().await?;
+        let mut connection = Self::from_streams(channel.reader, channel.writer);
+        if let Some(stderr) = channel.stderr {
+            tokio::spawn(forward_stderr(stderr, connection.stderr_tail.clone()));
+        }
+        connection.child = Mutex::new(channel.process);
+        Ok(connection)
+    }
+
+    /// Create a connection on top of an already connected byte stream pair
+    pub fn from_streams<R, W>(reader: R, writer: W) -> Self
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let writer: SharedWriter = Arc::new(tokio::sync::Mutex::new(Box::new(writer)));
+        let pending = Arc::new(PendingRequests::default());
+        let progress = Arc::new(Progress::default());
+        let settings = SharedSettings::default();
+        let reader_task = tokio::spawn(read_loop(
+            BufReader::new(reader),
+            writer.clone(),
+            pending.clone(),
+            progress.clone(),
+            settings.clone(),
+        ));
+
+        Self {
+            writer,
+            pending,
+            progress,
+            next_id: AtomicI64::new(1),
+            reader_task,
+            child: Mutex::new(None),
+            stderr_tail: Arc::default(),
+            capabilities: ServerCapabilities::default(),
+            open_documents: Mutex::new(HashSet::new()),
+            request_timeout: None,
+            recorder: None,
+            initialization_options: None,
+            settings,
+            shut_down: false,
+        }
+    }
+
+    /// Limit how long each request may take before it is cancelled
+    pub fn set_request_timeout(&mut self, timeout: Option<Duration>) {
+        self.request_timeout = timeout;
+    }
+
+    /// Limit set by `set_request_timeout`
+    pub fn request_timeout(&self) -> Option<Duration> {
+        self.request_timeout
+    }
+
+    /// Write every request with its response, and every notification, to a transcript
+    pub fn set_recorder(&mut self, recorder: Option<Recorder>) {
+        self.recorder = recorder;
+    }
+
+    /// Recorder set by `set_recorder`
+    pub fn recorder(&self) -> Option<&Recorder> {
+        self.recorder.as_ref()
+    }
+
+    /// Server-specific options for the next `initialize` request
+    pub fn set_initialization_options(&mut self, options: Option<Value>) {
+        self.initialization_options = options;
+    }
+
+    /// Options set by `set_initialization_options`
+    pub fn initialization_options(&self) -> Option<&Value> {
+        self.initialization_options.as_ref()
+    }
+
+    /// Settings the server is given on `workspace/configuration`
+    ///
+    /// They are also pushed with `workspace/didChangeConfiguration` right
+    /// after `initialized`, for servers that never ask.
+    pub fn set_settings(&mut self, settings: Value) {
+        *self.settings.lock().unwrap() = settings;
+    }
+
+    /// Settings set by `set_settings`; `null` when there are none
+    pub fn settings(&self) -> Value {
+        self.settings.lock().unwrap().clone()
+    }
+
+    /// Perform the `initialize` / `initialized` handshake for a workspace root
+    pub async fn initialize(&mut self, root: &Path) -> Result<&ServerCapabilities> {
+        let root_uri = Url::from_directory_path(root).map_err(|_| {
+            CallGraphError::ConfigError(format!(
+                "Workspace root must be an absolute directory path: {}",
+                root.display()
+            ))
+        })?;
+        let workspace_name = root
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| root.display().to_string());
+
+        let mut params = protocol::initialize_params(root_uri, workspace_name);
+        params.initialization_options = self.initialization_options.clone();
+        let result = self.request::<request::Initialize>(params).await?;
+        if let Some(server_info) = &result.server_info {
+            info!(
+                "Connected to {} {}",
+                server_info.name,
+                server_info.version.as_deref().unwrap_or_default()
+            );
+        }
+        self.capabilities = result.capabilities;
+
+        self.notify::<notification::Initialized>(InitializedParams {})
+            .await?;
+        let settings = self.settings();
+        if !settings.is_null() {
+            self.notify::<notification::DidChangeConfiguration>(DidChangeConfigurationParams {
+                settings,
+            })
+            .await?;
+        }
+        // Indexing usually starts right after `initialized`
+        self.progress.touch();
+        Ok(&self.capabilities)
+    }
+
+    /// Perform `shutdown` and `exit`, then reap the server process
+    ///
+    /// A server this connection did not start, such as one reached over a
+    /// socket, may be shared with other clients, so only the stream to it is
+    /// closed. Calling this more than once is a no-op.
+    pub async fn shutdown(&mut self) -> Result<()> {
+        if self.shut_down {
+            return Ok(());
+        }
+        self.shut_down = true;
+
+        let Some(child) = self.child.get_mut().unwrap().take() else {
+            if let Err(e) = self.writer.lock().await.shutdown().await {
+                debug!("Failed to close the connection: {}", e);
+            }
+            return Ok(());
+        };
+        let response = self.request::<request::Shutdown>(()).await;
+        if let Err(e) = self.notify::<notification::Exit>(()).await {
+            debug!("Failed to send exit notification: {}", e);
+        }
+        reap(child).await?;
+        response
+    }
+
+    /// Send a typed LSP request and wait for its response
+    pub async fn request<R>(&self, params: R::Params) -> Result<R::Result>
+    where
+        R: request::Request,
+    {
+        let result = self
+            .send_request(R::METHOD, serde_json::to_value(params)?)
+            .await?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Send a typed LSP notification
+    pub async fn notify<N>(&self, params: N::Params) -> Result<()>
+    where
+        N: notification::Notification,
+    {
+        self.send_notification(N::METHOD, serde_json::to_value(params)?)
+            .await
+    }
+
+    /// Send a raw JSON-RPC request and wait for the matching response
+    pub async fn send_request(&self, method: &str, params: Value) -> Result<Value> {
+        let id = Id::Number(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let Some(receiver) = self.pending.register(id.clone()) else {
+            return Err(self.exit_error().await.into());
+        };
+
+        debug!("--> {} (id {})", method, id);
+        let recorded_params = self.recorder.as_ref().map(|_| params.clone());
+        let request = build_message(method, Some(id.clone()), params);
+        if let Err(e) = write_locked(&self.writer, &request).await {
+            // A server that stopped reading its input is gone for good
+            debug!("Failed to send {}: {}", method, e);
+            self.pending.close();
+            return Err(self.exit_error().await.into());
+        }
+
+        let response = match self.request_timeout {
+            Some(limit) => match tokio::time::timeout(limit, receiver).await {
+                Ok(response) => response,
+                Err(_) => {
+                    // Tell the server to stop working on it; a late answer is dropped
+                    self.pending.remove(&id);
+                    warn!("{} (id {}) timed out after {:?}", method, id, limit);
+                    let cancel = build_message("$/cancelRequest", None, json!({ "id": id }));
+                    if let Err(e) = write_locked(&self.writer, &cancel).await {
+                        debug!("Failed to cancel request {}: {}", id, e);
+                    }
+                    return Err(CallGraphError::Timeout(format!(
+                        "{method} did not answer within {}s",
+                        limit.as_secs_f32()
+                    ))
+                    .into());
+                }
+            },
+            None => receiver.await,
+        };
+
+        let Ok(response) = response else {
+            return Err(self.exit_error().await.into());
+        };
+        if let (Some(recorder), Some(params)) = (&self.recorder, recorded_params) {
+            recorder.record(method, params, Some(Reply::from(&response)));
+        }
+        response.map_err(|e| CallGraphError::LspError(e).into())
+    }
+
+    /// Send a raw JSON-RPC notification
+    pub async fn send_notification(&self, method: &str, params: Value) -> Result<()> {
+        debug!("--> {} (notification)", method);
+        if let Some(recorder) = &self.recorder {
+            recorder.record(method, params.clone(), None);
+        }
+        let notification = build_message(method, None, params);
+        if let Err(e) = write_locked(&self.writer, &notification).await {
+            debug!("Failed to send {}: {}", method, e);
+            self.pending.close();
+            return Err(self.exit_error().await.into());
+        }
+        Ok(())
+    }
+
+    /// Send `textDocument/didOpen` for a file unless it is already open
+    ///
+    /// Returns the document URI to use in subsequent requests.
+    pub async fn open_document(&self, path: &Path) -> Result<Url> {
+        let path = path.canonicalize()?;
+        let uri = Url::from_file_path(&path).map_err(|_| {
+            CallGraphError::ConfigError(format!("Invalid document path: {}", path.display()))
+        })?;
+        if !self.open_documents.lock().unwrap().insert(uri.clone()) {
+            return Ok(uri);
+        }
+
+        let opened = async {
+            let text = tokio::fs::read_to_string(&path).await?;
+            let params = DidOpenTextDocumentParams {
+                text_document: TextDocumentItem {
+                    uri: uri.clone(),
+                    language_id: protocol::language_id(&path).to_string(),
+                    version: 0,
+                    text,
+                },
+            };
+            self.notify::<notification::DidOpenTextDocument>(params)
+                .await
+        }
+        .await;
+        // A document the server never received must be opened again next time
+        if let Err(e) = opened {
+            self.open_documents.lock().unwrap().remove(&uri);
+            return Err(e);
+        }
+        Ok(uri)
+    }
+
+    /// Wait until the server has finished the work it reports via `$/progress`
+    ///
+    /// Returns `false` if the server is still busy when `timeout` expires.
+    pub async fn wait_until_ready(&self, timeout: Duration) -> bool {
+        self.progress
+            .wait_until_idle(READINESS_QUIET_PERIOD, timeout)
+            .await
+    }
+
+    /// Process id of the spawned server, if this connection owns one
+    pub fn server_pid(&self) -> Option<u32> {
+        self.child.lock().unwrap().as_ref().and_then(Child::id)
+    }
+
+    /// Whether the server has closed the connection, e.g. because it crashed
+    pub fn is_closed(&self) -> bool {
+        self.pending.is_closed()
+    }
+
+    /// Describe why the server went away, including the tail of its stderr
+    async fn exit_error(&self) -> CallGraphError {
+        let deadline = tokio::time::Instant::now() + EXIT_STATUS_WAIT;
+        let status = loop {
+            let status = match self.child.lock().unwrap().as_mut() {
+                Some(child) => child.try_wait().ok().flatten(),
+                None => break None,
+            };
+            // Let the last stderr lines arrive as well
+            let drained = self.stderr_tail.lock().unwrap().closed;
+            if (status.is_some() && drained) || tokio::time::Instant::now() >= deadline {
+                break status;
+            }
+            tokio::time::sleep(Duration::from_millis(25)).await;
+        };
+
+        let mut message = match status {
+            Some(status) => format!("server process ended with {status}"),
+            None => "server closed the connection".to_string(),
+        };
+        let tail = self.stderr_tail.lock().unwrap();
+        if !tail.lines.is_empty() {
+            message.push_str("; last stderr output:");
+            for line in &tail.lines {
+                message.push_str("\n  ");
+                message.push_str(line);
+            }
+        }
+        CallGraphError::ServerExited(message)
+    }
+
+    // ------------------------------------------------------------------------
+    // Capabilities
+    // ------------------------------------------------------------------------
+
+    /// Capabilities announced by the server during initialization
+    pub fn capabilities(&self) -> &ServerCapabilities {
+        &self.capabilities
+    }
+
+    /// Whether `textDocument/prepareCallHierarchy` and friends are available
+    pub fn supports_call_hierarchy(&self) -> bool {
+        match &self.capabilities.call_hierarchy_provider {
+            Some(CallHierarchyServerCapability::Simple(enabled)) => *enabled,
+            Some(CallHierarchyServerCapability::Options(_)) => true,
+            None => false,
+        }
+    }
+
+    /// Whether `textDocument/documentSymbol` is available
+    pub fn supports_document_symbols(&self) -> bool {
+        protocol::is_enabled(self.capabilities.document_symbol_provider.as_ref())
+    }
+
+    /// Whether `workspace/symbol` is available
+    pub fn supports_workspace_symbols(&self) -> bool {
+        protocol::is_enabled(self.capabilities.workspace_symbol_provider.as_ref())
+    }
+
+    /// Whether `textDocument/references` is available
+    pub fn supports_references(&self) -> bool {
+        protocol::is_enabled(self.capabilities.references_provider.as_ref())
+    }
+
+    /// Whether `textDocument/definition` is available
+    pub fn supports_definition(&self) -> bool {
+        protocol::is_enabled(self.capabilities.definition_provider.as_ref())
+    }
+
+    /// Token legend, if `textDocument/semanticTokens/full` is available
+    pub fn semantic_tokens_legend(&self) -> Option<&SemanticTokensLegend> {
+        let options = match self.capabilities.semantic_tokens_provider.as_ref()? {
+            SemanticTokensServerCapabilities::SemanticTokensOptions(options) => options,
+            SemanticTokensServerCapabilities::SemanticTokensRegistrationOptions(options) => {
+                &options.semantic_tokens_options
+            }
+        };
+        match options.full {
+            Some(SemanticTokensFullOptions::Bool(true))
+            | Some(SemanticTokensFullOptions::Delta { .. }) => Some(&options.legend),
+            _ => None,
+        }
+    }
+
+    /// Unit of the `character` of positions exchanged with this server
+    pub fn position_encoding(&self) -> PositionEncoding {
+        PositionEncoding::negotiated(&self.capabilities)
+    }
+}
+
+impl Drop for LspConnection {
+    fn drop(&mut self) {
+        self.reader_task.abort();
+
+        // Without an explicit shutdown, still ask the server to exit politely.
+        // If the runtime is already gone the child is killed on drop instead.
+        if self.shut_down {
+            return;
+        }
+        let (Some(child), Ok(runtime)) = (
+            self.child.get_mut().unwrap().take(),
+            tokio::runtime::Handle::try_current(),
+        ) else {
+            return;
+        };
+        let writer = self.writer.clone();
+        let shutdown_id = Id::Number(self.next_id.fetch_add(1, Ordering::Relaxed));
+        runtime.spawn(async move {
+            let shutdown = build_message("shutdown", Some(shutdown_id), Value::Null);
+            let exit = build_message("exit", None, Value::Null);
+            if write_locked(&writer, &shutdown).await.is_ok() {
+                let _ = write_locked(&writer, &exit).await;
+            }
+            if let Err(e) = reap(child).await {
+                debug!("Failed to reap language server: {}", e);
+            }
+        });
+    }
+}
+
+// ============================================================================
+// Request Correlation
+// ============================================================================
+
+type ResponseSender = oneshot::Sender<jsonrpc::Result<Value>>;
+
+/// In-flight requests waiting for a response, keyed by request id
+///
+/// Becomes `None` once the connection is closed so that late registrations
+/// fail immediately instead of waiting forever.
+struct PendingRequests(Mutex<Option<HashMap<Id, ResponseSender>>>);
+
+impl Default for PendingRequests {
+    fn default() -> Self {
+        Self(Mutex::new(Some(HashMap::new())))
+    }
+}
+
+impl PendingRequests {
+    fn register(&self, id: Id) -> Option<oneshot::Receiver<jsonrpc::Result<Value>>> {
+        let (sender, receiver) = oneshot::channel();
+        let mut guard = self.0.lock().unwrap();
+        guard.as_mut()?.insert(id, sender);
+        Some(receiver)
+    }
+
+    fn remove(&self, id: &Id) -> Option<ResponseSender> {
+        self.0.lock().unwrap().as_mut()?.remove(id)
+    }
+
+    /// Drop every waiter, waking them with a closed-connection error
+    fn close(&self) {
+        self.0.lock().unwrap().take();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.0.lock().unwrap().is_none()
+    }
+}
+
+// ============================================================================
+// Background Tasks
+// ============================================================================
+
+/// Dispatch incoming messages until the server closes the stream
+async fn read_loop<R>(
+    mut reader: BufReader<R>,
+    writer: SharedWriter,
+    pending: Arc<PendingRequests>,
+    progress: Arc<Progress>,
+    settings: SharedSettings,
+) where
+    R: AsyncRead + Unpin,
+{
+    loop {
+        match protocol::read_message(&mut reader).await {
+            Ok(Some(Message::Response(response))) => {
+                let (id, body) = response.into_parts();
+                debug!("<-- response (id {})", id);
+                match pending.remove(&id) {
+                    Some(sender) => {
+                        let _ = sender.send(body);
+                    }
+                    // Usually the answer to a request that already timed out
+                    None => debug!("Received response for unknown request id {}", id),
+                }
+            }
+            Ok(Some(Message::Request(request))) => {
+                let (method, id, params) = request.into_parts();
+                match id {
+                    Some(id) => {
+                        debug!("<-- {} (server request {})", method, id);
+                        let settings = settings.lock().unwrap().clone();
+                        let body =
+                            handlers::handle_server_request(&method, params.as_ref(), &settings);
+                        let response = Response::from_parts(id, body);
+                        if let Err(e) = write_locked(&writer, &response).await {
+                            error!("Failed to answer server request {}: {}", method, e);
+                            break;
+                        }
+                    }
+                    None => handlers::handle_notification(&method, params.as_ref(), &progress),
+                }
+            }
+            Ok(None) => {
+                debug!("Language server closed its output stream");
+                break;
+            }
+            Err(e) => {
+                error!("Failed to read message from language server: {}", e);
+                break;
+            }
+        }
+    }
+
+    pending.close();
+}
+
+/// Wait for the server process to exit, killing it after a grace period
+async fn reap(mut child: Child) -> io::Result<()> {
+    match tokio::time::timeout(SHUTDOWN_GRACE_PERIOD, child.wait()).await {
+        Ok(status) => {
+            debug!("Language server exited with {}", status?);
+            Ok(())
+        }
+        Err(_) => {
+            warn!("Language server did not exit after shutdown; killing it");
+            child.kill().await
+        }
+    }
+}
+
+/// The last lines a server wrote to stderr
+#[derive(Default)]
+struct StderrTail {
+    lines: VecDeque<String>,
+    /// Set once the server closed stderr, usually because it exited
+    closed: bool,
+}
+
+/// Relay the server's stderr into our log, keeping the last lines around
+async fn forward_stderr<R>(stderr: R, tail: Arc<Mutex<StderrTail>>)
+where
+    R: AsyncRead + Unpin,
+{
+    let mut lines = BufReader::new(stderr).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        debug!("[server stderr] {}", line);
+        let mut tail = tail.lock().unwrap();
+        if tail.lines.len() == STDERR_TAIL_LINES {
+            tail.lines.pop_front();
+        }
+        tail.lines.push_back(line);
+    }
+    tail.lock().unwrap().closed = true;
+}
+
+// ============================================================================
+// Helpers
+// ============================================================================
+
+fn build_message(method: &str, id: Option<Id>, params: Value) -> Request {
+    let mut builder = Request::build(method.to_string());
+    if let Some(id) = id {
+        builder = builder.id(id);
+    }
+    if !params.is_null() {
+        builder = builder.params(params);
+    }
+    builder.finish()
+}
+
+async fn write_locked<T>(writer: &SharedWriter, message: &T) -> io::Result<()>
+where
+    T: serde::Serialize,
+{
+    let mut writer = writer.lock().await;
+    protocol::write_message(&mut *writer, message).await
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp::test_support::connect;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_responses_are_correlated_by_id() {
+        let (client, mut server) = connect();
+
+        let server_task = tokio::spawn(async move {
+            let first = server.next_request().await.unwrap();
+            let second = server.next_request().await.unwrap();
+            // Answer out of order to exercise id correlation
+            for request in [second, first] {
+                let id = request.id().cloned().unwrap();
+                let echo = json!({ "method": request.method() });
+                server.send(&Response::from_ok(id, echo)).await;
+            }
+        });
+
+        let (a, b) = tokio::join!(
+            client.send_request("test/first", Value::Null),
+            client.send_request("test/second", json!({"x": 1})),
+        );
+        assert_eq!(a.unwrap(), json!({"method": "test/first"}));
+        assert_eq!(b.unwrap(), json!({"method": "test/second"}));
+        server_task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_error_response_maps_to_lsp_error() {
+        let (client, mut server) = connect();
+
+        tokio::spawn(async move {
+            let request = server.next_request().await.unwrap();
+            let id = request.id().cloned().unwrap();
+            server
+                .send(&Response::from_error(
+                    id,
+                    jsonrpc::Error::method_not_found(),
+                ))
+                .await;
+        });
+
+        let err = client
+            .send_request("test/missing", Value::Null)
+            .await
+            .unwrap_err();
+        let err = err.downcast::<CallGraphError>().unwrap();
+        assert!(matches!(*err, CallGraphError::LspError(_)));
+    }
+
+    #[tokio::test]
+    async fn test_closed_connection_fails_pending_request() {
+        let (client, mut server) = connect();
+
+        tokio::spawn(async move {
+            server.next_request().await.unwrap();
+            drop(server);
+        });
+
+        let err = client
+            .send_request("test/never", Value::Null)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(CallGraphError::ServerExited(_))
+        ));
+        assert!(err.to_string().contains("closed the connection"));
+        assert!(client.is_closed());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_exited_server_reports_status_and_stderr_tail() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let server = temp_dir.path().join("crashing-server");
+        std::fs::write(
+            &server,
+            "#!/bin/sh\nfor i in $(seq 1 30); do echo \"line $i\" >&2; done\n\
+             echo 'fatal: out of memory' >&2\nexit 3\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&server, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let client = LspConnection::spawn(server.to_str().unwrap(), temp_dir.path())
+            .await
+            .unwrap();
+        let err = client
+            .send_request("test/crash", Value::Null)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("exit status: 3"), "{err}");
+        assert!(err.ends_with("fatal: out of memory"), "{err}");
+        // Only the tail is kept
+        assert!(err.contains("line 30"));
+        assert!(!err.contains("line 1\n"));
+        assert!(client.is_closed());
+    }
+
+    #[tokio::test]
+    async fn test_server_request_is_answered() {
+        let (_client, mut server) = connect();
+
+        let request = Request::build("workspace/configuration")
+            .id(99)
+            .params(json!({"items": [{"section": "rust-analyzer"}]}))
+            .finish();
+        server.send(&request).await;
+
+        let (id, body) = server.next_response().await.unwrap().into_parts();
+        assert_eq!(id, Id::Number(99));
+        assert_eq!(body.unwrap(), json!([null]));
+    }
+
+    #[tokio::test]
+    async fn test_initialize_passes_options_and_settings() {
+        let (mut client, mut server) = connect();
+        let settings = json!({"rust-analyzer": {"cargo": {"allFeatures": true}}});
+        client.set_initialization_options(Some(json!({"procMacro": {"enable": true}})));
+        client.set_settings(settings.clone());
+
+        let expected = settings.clone();
+        let server_task = tokio::spawn(async move {
+            let initialize = server.next_request().await.unwrap();
+            assert_eq!(
+                initialize.params().unwrap()["initializationOptions"],
+                json!({"procMacro": {"enable": true}})
+            );
+            let id = initialize.id().cloned().unwrap();
+            server
+                .send(&Response::from_ok(id, json!({"capabilities": {}})))
+                .await;
+
+            assert_eq!(server.next_request().await.unwrap().method(), "initialized");
+            let changed = server.next_request().await.unwrap();
+            assert_eq!(changed.method(), "workspace/didChangeConfiguration");
+            assert_eq!(changed.params().unwrap()["settings"], expected);
+
+            let request = Request::build("workspace/configuration")
+                .id(7)
+                .params(json!({"items": [{"section": "rust-analyzer.cargo"}]}))
+                .finish();
+            server.send(&request).await;
+            let (_, body) = server.next_response().await.unwrap().into_parts();
+            assert_eq!(body.unwrap(), json!([{"allFeatures": true}]));
+        });
+
+        client.initialize(&std::env::temp_dir()).await.unwrap();
+        server_task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_typed_notification_is_sent_without_id() {
+        let (client, mut server) = connect();
+
+        client
+            .notify::<notification::Initialized>(tower_lsp::lsp_types::InitializedParams {})
+            .await
+            .unwrap();
+
+        let message = server.next_request().await.unwrap();
+        assert_eq!(message.method(), "initialized");
+        assert!(message.id().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_initialize_records_server_capabilities() {
+        let (mut client, mut server) = connect();
+        let root = std::env::temp_dir();
+        let expected_root_uri = Url::from_directory_path(&root).unwrap().to_string();
+
+        let server_task = tokio::spawn(async move {
+            let initialize = server.next_request().await.unwrap();
+            assert_eq!(initialize.method(), "initialize");
+            let params = initialize.params().unwrap();
+            assert_eq!(params["rootUri"], json!(expected_root_uri));
+
+            let result = json!({
+                "capabilities": {
+                    "callHierarchyProvider": true,
+                    "documentSymbolProvider": {"label": "symbols"},
+                    "referencesProvider": false
+                },
+                "serverInfo": {"name": "fake-server"}
+            });
+            let id = initialize.id().cloned().unwrap();
+            server.send(&Response::from_ok(id, result)).await;
+
+            let initialized = server.next_request().await.unwrap();
+            assert_eq!(initialized.method(), "initialized");
+        });
+
+        client.initialize(&root).await.unwrap();
+        server_task.await.unwrap();
+
+        assert!(client.supports_call_hierarchy());
+        assert!(client.supports_document_symbols());
+        assert!(!client.supports_references());
+        assert!(!client.supports_workspace_symbols());
+        assert_eq!(client.position_encoding(), PositionEncoding::Utf16);
+        assert!(client.semantic_tokens_legend().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_initialize_negotiates_position_encoding() {
+        let (mut client, server) = connect();
+        server.serve(|method, params| match method {
+            "initialize" => {
+                let offered = &params["capabilities"]["general"]["positionEncodings"];
+                assert_eq!(offered[0], json!("utf-8"));
+                Ok(json!({"capabilities": {"positionEncoding": "utf-8"}}))
+            }
+            _ => Ok(Value::Null),
+        });
+
+        client.initialize(&std::env::temp_dir()).await.unwrap();
+        assert_eq!(client.position_encoding(), PositionEncoding::Utf8);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_shutdown_sends_shutdown_then_exit() {
+        let (mut client, mut server) = connect();
+        // Stand-in for a server process this connection started
+        let process = tokio::process::Command::new("true").spawn().unwrap();
+        client.child = Mutex::new(Some(process));
+
+        let server_task = tokio::spawn(async move {
+            let shutdown = server.next_request().await.unwrap();
+            assert_eq!(shutdown.method(), "shutdown");
+            let id = shutdown.id().cloned().unwrap();
+            server.send(&Response::from_ok(id, Value::Null)).await;
+
+            let exit = server.next_request().await.unwrap();
+            assert_eq!(exit.method(), "exit");
+            assert!(exit.id().is_none());
+        });
+
+        client.shutdown().await.unwrap();
+        server_task.await.unwrap();
+        // A second call must not talk to the server again
+        client.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_open_document_sends_did_open_once() {
+        let (client, mut server) = connect();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("lib.rs");
+        std::fs::write(&path, "fn helper() {}").unwrap();
+
+        let first = client.open_document(&path).await.unwrap();
+        let second = client.open_document(&path).await.unwrap();
+        assert_eq!(first, second);
+
+        let did_open = server.next_request().await.unwrap();
+        assert_eq!(did_open.method(), "textDocument/didOpen");
+        let params = did_open.params().unwrap();
+        assert_eq!(params["textDocument"]["languageId"], json!("rust"));
+        assert_eq!(params["textDocument"]["text"], json!("fn helper() {}"));
+
+        // Only one didOpen must have been written
+        drop(client);
+        assert!(server.next_request().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_open_document_retries_after_failure() {
+        let (client, mut server) = connect();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("lib.rs");
+        std::fs::write(&path, b"fn \xff() {}").unwrap();
+
+        // Not UTF-8, so nothing is sent
+        assert!(client.open_document(&path).await.is_err());
+
+        std::fs::write(&path, "fn helper() {}").unwrap();
+        client.open_document(&path).await.unwrap();
+        let did_open = server.next_request().await.unwrap();
+        assert_eq!(did_open.method(), "textDocument/didOpen");
+        assert_eq!(
+            did_open.params().unwrap()["textDocument"]["text"],
+            json!("fn helper() {}")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_sends_cancel() {
+        let (mut client, mut server) = connect();
+        client.set_request_timeout(Some(Duration::from_millis(50)));
+
+        let server_task = tokio::spawn(async move {
+            let request = server.next_request().await.unwrap();
+            let cancel = server.next_request().await.unwrap();
+            (request, cancel)
+        });
+
+        let err = client
+            .send_request("test/slow", Value::Null)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CallGraphError>(),
+            Some(CallGraphError::Timeout(_))
+        ));
+
+        let (request, cancel) = server_task.await.unwrap();
+        assert_eq!(cancel.method(), "$/cancelRequest");
+        assert_eq!(cancel.id(), None);
+        assert_eq!(
+            cancel.params().unwrap()["id"],
+            serde_json::to_value(request.id().unwrap()).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_spawn_missing_program_fails() {
+        let result =
+            LspConnection::spawn("definitely-not-a-language-server-binary", Path::new(".")).await;
+        assert!(result.is_err());
+    }
+}
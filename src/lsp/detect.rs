@@ -0,0 +1,316 @@
+//! Automatic selection of a language server for the analyzed code
+
+use super::protocol::language_id;
+use crate::analyzer::source_files;
+use crate::config::Config;
+use crate::{CallGraphError, Result};
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+// ============================================================================
+// Known Servers
+// ============================================================================
+
+/// A language and the servers that can analyze it
+#[derive(Debug)]
+pub struct KnownLanguage {
+    /// Short name used in messages and configuration, e.g. `typescript`
+    pub name: &'static str,
+    /// LSP language ids of the documents this language covers
+    pub language_ids: &'static [&'static str],
+    /// Files in the project root that identify the language
+    pub markers: &'static [&'static str],
+    /// Server command lines in order of preference
+    pub commands: &'static [&'static str],
+}
+
+/// Languages recognized by the auto-detection, in order of precedence
+pub const KNOWN_LANGUAGES: &[KnownLanguage] = &[
+    KnownLanguage {
+        name: "rust",
+        language_ids: &["rust"],
+        markers: &["Cargo.toml"],
+        commands: &["rust-analyzer"],
+    },
+    KnownLanguage {
+        name: "typescript",
+        language_ids: &[
+            "typescript",
+            "typescriptreact",
+            "javascript",
+            "javascriptreact",
+        ],
+        markers: &["tsconfig.json", "package.json"],
+        commands: &["typescript-language-server --stdio"],
+    },
+    KnownLanguage {
+        name: "go",
+        language_ids: &["go"],
+        markers: &["go.mod"],
+        commands: &["gopls"],
+    },
+    KnownLanguage {
+        name: "python",
+        language_ids: &["python"],
+        markers: &["pyproject.toml", "setup.py", "requirements.txt"],
+        commands: &["pyright-langserver --stdio", "pylsp"],
+    },
+    KnownLanguage {
+        name: "c",
+        language_ids: &["c", "cpp"],
+        markers: &[
+            "compile_commands.json",
+            "compile_flags.txt",
+            "CMakeLists.txt",
+        ],
+        commands: &["clangd"],
+    },
+    KnownLanguage {
+        name: "java",
+        language_ids: &["java"],
+        markers: &["pom.xml", "build.gradle", "build.gradle.kts"],
+        commands: &["jdtls"],
+    },
+];
+
+impl KnownLanguage {
+    /// Look up the language a document belongs to
+    pub fn for_path(path: &Path) -> Option<&'static KnownLanguage> {
+        let id = language_id(path);
+        KNOWN_LANGUAGES
+            .iter()
+            .find(|language| language.language_ids.contains(&id))
+    }
+
+    /// Look up a language by its name or one of its LSP language ids
+    pub fn named(name: &str) -> Option<&'static KnownLanguage> {
+        KNOWN_LANGUAGES
+            .iter()
+            .find(|language| language.name == name || language.language_ids.contains(&name))
+    }
+
+    /// The first of this language's servers that is installed
+    pub fn installed_command(&self) -> Option<&'static str> {
+        let path = env::var_os("PATH").unwrap_or_default();
+        self.commands.iter().copied().find(|command| {
+            let program = command.split_whitespace().next().unwrap_or_default();
+            find_in_path(program, &path).is_some()
+        })
+    }
+}
+
+// ============================================================================
+// Detection
+// ============================================================================
+
+/// Languages present in the analyzed file or project, most prominent first
+///
+/// A single file is classified by its extension. A project is classified by
+/// marker files such as `Cargo.toml` in its root, followed by any other
+/// languages found among its source files ordered by file count.
+pub fn detect_languages(config: &Config) -> Result<Vec<&'static KnownLanguage>> {
+    if let (Some(file), None) = (&config.file_path, &config.project_path) {
+        return Ok(KnownLanguage::for_path(file).into_iter().collect());
+    }
+
+    let root = config.workspace_root()?;
+    let mut languages: Vec<&'static KnownLanguage> = KNOWN_LANGUAGES
+        .iter()
+        .filter(|language| {
+            language
+                .markers
+                .iter()
+                .any(|marker| root.join(marker).is_file())
+        })
+        .collect();
+
+    let mut counts: HashMap<&'static str, usize> = HashMap::new();
+    for file in source_files(&root) {
+        if let Some(language) = KnownLanguage::for_path(&file) {
+            *counts.entry(language.name).or_default() += 1;
+        }
+    }
+    let mut by_count: Vec<_> = KNOWN_LANGUAGES
+        .iter()
+        .filter(|language| counts.contains_key(language.name))
+        .filter(|language| !languages.iter().any(|known| known.name == language.name))
+        .collect();
+    by_count.sort_by_key(|language| std::cmp::Reverse(counts[language.name]));
+    languages.extend(by_count);
+
+    debug!(
+        "Detected languages: {:?}",
+        languages.iter().map(|l| l.name).collect::<Vec<_>>()
+    );
+    Ok(languages)
+}
+
+/// Pick an installed server for every detected language
+///
+/// Languages without an installed server are skipped with a warning. The
+/// error lists what was looked for so users know what to install or which
+/// `--lsp-server` to pass.
+pub fn detect_servers(config: &Config) -> Result<Vec<(&'static KnownLanguage, &'static str)>> {
+    let languages = detect_languages(config)?;
+    if languages.is_empty() {
+        let markers: Vec<_> = KNOWN_LANGUAGES
+            .iter()
+            .flat_map(|language| language.markers.iter().copied())
+            .collect();
+        return Err(CallGraphError::ConfigError(format!(
+            "Could not detect the project language: found none of {} and no known source files; \
+             use --lsp-server to choose a server",
+            markers.join(", ")
+        ))
+        .into());
+    }
+
+    let mut servers = Vec::new();
+    for language in &languages {
+        match language.installed_command() {
+            Some(command) => {
+                info!("Detected {} code, using {}", language.name, command);
+                servers.push((*language, command));
+            }
+            None => warn!(
+                "Detected {} code but none of {} is installed",
+                language.name,
+                language.commands.join(", ")
+            ),
+        }
+    }
+    if !servers.is_empty() {
+        return Ok(servers);
+    }
+
+    let looked_for: Vec<_> = languages
+        .iter()
+        .map(|language| format!("{} ({})", language.name, language.commands.join(" or ")))
+        .collect();
+    Err(CallGraphError::ConfigError(format!(
+        "No language server found on PATH for {}; install one or use --lsp-server",
+        looked_for.join(", ")
+    ))
+    .into())
+}
+
+/// Locate an executable in a `PATH`-style list of directories
+fn find_in_path(program: &str, path: &OsStr) -> Option<PathBuf> {
+    let extensions: &[&str] = if cfg!(windows) {
+        &["", ".exe", ".cmd", ".bat"]
+    } else {
+        &[""]
+    };
+    env::split_paths(path)
+        .flat_map(|dir| {
+            extensions
+                .iter()
+                .map(move |extension| dir.join(format!("{program}{extension}")))
+        })
+        .find(|candidate| is_executable(candidate))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AnalysisConfig, CallDirection, LspConfig, OutputConfig};
+
+    fn test_config(file_path: Option<PathBuf>, project_path: Option<PathBuf>) -> Config {
+        Config {
+            file_path,
+            project_path,
+            output_path: "test.dot".to_string(),
+            root_function: None,
+            max_depth: None,
+            direction: CallDirection::Callees,
+            exclude_patterns: vec![],
+            lsp_server_command: None,
+            record_lsp: None,
+            replay_lsp: None,
+            index: None,
+            verbose: false,
+            analysis: AnalysisConfig::default(),
+            output: OutputConfig::default(),
+            lsp: LspConfig::default(),
+        }
+    }
+
+    fn names(languages: Vec<&KnownLanguage>) -> Vec<&str> {
+        languages.iter().map(|language| language.name).collect()
+    }
+
+    #[test]
+    fn test_detects_file_language_from_extension() {
+        let config = test_config(Some(PathBuf::from("web/app.tsx")), None);
+        assert_eq!(
+            names(detect_languages(&config).unwrap()),
+            vec!["typescript"]
+        );
+
+        let config = test_config(Some(PathBuf::from("notes.txt")), None);
+        assert!(detect_languages(&config).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_detects_project_languages_from_markers_and_sources() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        std::fs::write(root.join("go.mod"), "module example").unwrap();
+        std::fs::create_dir(root.join("scripts")).unwrap();
+        for file in ["scripts/a.py", "scripts/b.py", "main.go", "tool.c"] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+
+        let config = test_config(None, Some(root.to_path_buf()));
+        assert_eq!(
+            names(detect_languages(&config).unwrap()),
+            vec!["go", "python", "c"]
+        );
+    }
+
+    #[test]
+    fn test_error_lists_markers_when_nothing_is_detected() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let config = test_config(None, Some(temp_dir.path().to_path_buf()));
+
+        let error = detect_servers(&config).unwrap_err().to_string();
+        assert!(error.contains("Cargo.toml"));
+        assert!(error.contains("compile_commands.json"));
+        assert!(error.contains("--lsp-server"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_in_path_requires_an_executable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let server = temp_dir.path().join("gopls");
+        std::fs::write(&server, "#!/bin/sh\n").unwrap();
+        let path = env::join_paths([Path::new("/nonexistent"), temp_dir.path()]).unwrap();
+
+        assert_eq!(find_in_path("gopls", &path), None);
+        std::fs::set_permissions(&server, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(find_in_path("gopls", &path), Some(server));
+        assert_eq!(find_in_path("clangd", &path), None);
+    }
+}
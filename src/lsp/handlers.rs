@@ -0,0 +1,329 @@
+//! Handlers for requests and notifications initiated by the language server
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tower_lsp::jsonrpc;
+use tower_lsp::lsp_types::{NumberOrString, ProgressParams, ProgressParamsValue, WorkDoneProgress};
+use tracing::{debug, info, trace, warn};
+
+// ============================================================================
+// Server Requests
+// ============================================================================
+
+/// Produce the client's answer to a request sent by the server
+///
+/// We only act as a passive client, so most requests are acknowledged with
+/// `null`. Configuration is answered from the user's `settings`. Unknown
+/// methods are rejected so the server does not wait forever.
+pub fn handle_server_request(
+    method: &str,
+    params: Option<&Value>,
+    settings: &Value,
+) -> jsonrpc::Result<Value> {
+    match method {
+        "window/workDoneProgress/create"
+        | "client/registerCapability"
+        | "client/unregisterCapability"
+        | "window/showMessageRequest"
+        | "workspace/codeLens/refresh"
+        | "workspace/semanticTokens/refresh"
+        | "workspace/inlayHint/refresh"
+        | "workspace/diagnostic/refresh" => Ok(Value::Null),
+        "workspace/configuration" => {
+            // A `null` item means "use your defaults"
+            let items = params
+                .and_then(|p| p.get("items"))
+                .and_then(Value::as_array)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            Ok(items
+                .iter()
+                .map(|item| configuration_section(settings, item.get("section")))
+                .collect())
+        }
+        _ => {
+            debug!("Rejecting unsupported server request: {}", method);
+            Err(jsonrpc::Error::method_not_found())
+        }
+    }
+}
+
+/// Look up a configuration section such as `rust-analyzer` or `cargo.features`
+///
+/// A key spelled exactly like the section wins over walking the dotted path.
+fn configuration_section(settings: &Value, section: Option<&Value>) -> Value {
+    let Some(section) = section.and_then(Value::as_str).filter(|s| !s.is_empty()) else {
+        return settings.clone();
+    };
+    if let Some(value) = settings.get(section) {
+        return value.clone();
+    }
+    section
+        .split('.')
+        .try_fold(settings, |value, key| value.get(key))
+        .cloned()
+        .unwrap_or(Value::Null)
+}
+
+// ============================================================================
+// Server Notifications
+// ============================================================================
+
+/// Process a notification sent by the server
+pub fn handle_notification(method: &str, params: Option<&Value>, progress: &Progress) {
+    match method {
+        "$/progress" => match params.map(|p| serde_json::from_value(p.clone())) {
+            Some(Ok(params)) => progress.update(params),
+            _ => warn!("Ignoring malformed $/progress notification"),
+        },
+        "window/logMessage" | "window/showMessage" => {
+            let message = params
+                .and_then(|p| p.get("message"))
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            debug!("Language server: {}", message);
+        }
+        _ => trace!("Ignoring server notification: {}", method),
+    }
+}
+
+// ============================================================================
+// Progress
+// ============================================================================
+
+/// Work-done progress the server reports through `$/progress`
+///
+/// Servers such as rust-analyzer answer queries with empty results until
+/// their initial indexing is done, which they announce as progress.
+#[derive(Debug)]
+pub struct Progress {
+    state: Mutex<ProgressState>,
+    /// Bumped on every change so waiters can wake up
+    changed: watch::Sender<u64>,
+}
+
+#[derive(Debug)]
+struct ProgressState {
+    /// Running tasks by token: title and last percentage shown to the user
+    active: HashMap<NumberOrString, (String, Option<u32>)>,
+    last_change: Instant,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(ProgressState {
+                active: HashMap::new(),
+                last_change: Instant::now(),
+            }),
+            changed: watch::Sender::new(0),
+        }
+    }
+}
+
+impl Progress {
+    fn update(&self, params: ProgressParams) {
+        let ProgressParamsValue::WorkDone(value) = params.value;
+        let mut state = self.state.lock().unwrap();
+        match value {
+            WorkDoneProgress::Begin(begin) => {
+                info!(
+                    "{}{}",
+                    begin.title,
+                    begin.message.map(|m| format!(": {m}")).unwrap_or_default()
+                );
+                state
+                    .active
+                    .insert(params.token, (begin.title, begin.percentage));
+            }
+            WorkDoneProgress::Report(report) => {
+                let Some((title, shown)) = state.active.get_mut(&params.token) else {
+                    return;
+                };
+                let message = report.message.unwrap_or_default();
+                // Only every tenth percent is worth a line of output
+                match report.percentage {
+                    Some(percentage) if shown.is_none_or(|s| percentage / 10 > s / 10) => {
+                        info!("{}: {}% {}", title, percentage, message);
+                        *shown = Some(percentage);
+                    }
+                    _ => debug!("{}: {}", title, message),
+                }
+            }
+            WorkDoneProgress::End(_) => {
+                if let Some((title, _)) = state.active.remove(&params.token) {
+                    info!("{}: done", title);
+                }
+            }
+        }
+        state.last_change = Instant::now();
+        drop(state);
+        self.changed.send_modify(|version| *version += 1);
+    }
+
+    /// Restart the quiet period, e.g. once the server has been initialized
+    pub fn touch(&self) {
+        self.state.lock().unwrap().last_change = Instant::now();
+        self.changed.send_modify(|version| *version += 1);
+    }
+
+    /// Wait until no progress has been running for `quiet_period`
+    ///
+    /// Servers often run several tasks back to back, so a short gap between
+    /// them does not count. Returns `false` if `timeout` expires first.
+    pub async fn wait_until_idle(&self, quiet_period: Duration, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut changed = self.changed.subscribe();
+        loop {
+            let ready_at = {
+                let state = self.state.lock().unwrap();
+                state
+                    .active
+                    .is_empty()
+                    .then_some(state.last_change + quiet_period)
+            };
+            let now = Instant::now();
+            match ready_at {
+                Some(ready_at) if ready_at <= now => return true,
+                _ if deadline <= now => return false,
+                _ => {}
+            }
+
+            let wake_at = ready_at.map_or(deadline, |ready_at| ready_at.min(deadline));
+            tokio::select! {
+                _ = changed.changed() => {}
+                _ = tokio::time::sleep_until(wake_at) => {}
+            }
+        }
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_configuration_request_answers_every_item() {
+        let params = json!({"items": [{"section": "rust-analyzer"}, {"section": "files"}]});
+        let result =
+            handle_server_request("workspace/configuration", Some(&params), &Value::Null).unwrap();
+        assert_eq!(result, json!([null, null]));
+    }
+
+    #[test]
+    fn test_configuration_request_answers_from_settings() {
+        let settings = json!({
+            "rust-analyzer": {"cargo": {"allFeatures": true}},
+            "clangd.fallbackFlags": ["-std=c++20"],
+        });
+        let params = json!({"items": [
+            {"section": "rust-analyzer"},
+            {"section": "rust-analyzer.cargo.allFeatures"},
+            {"section": "clangd.fallbackFlags"},
+            {"section": "files"},
+            {},
+        ]});
+        let result =
+            handle_server_request("workspace/configuration", Some(&params), &settings).unwrap();
+        assert_eq!(
+            result,
+            json!([
+                {"cargo": {"allFeatures": true}},
+                true,
+                ["-std=c++20"],
+                null,
+                settings,
+            ])
+        );
+    }
+
+    #[test]
+    fn test_progress_create_is_acknowledged() {
+        let params = json!({"token": "rustAnalyzer/Indexing"});
+        let result = handle_server_request(
+            "window/workDoneProgress/create",
+            Some(&params),
+            &Value::Null,
+        );
+        assert_eq!(result, Ok(Value::Null));
+    }
+
+    fn progress(token: &str, value: Value) -> Value {
+        json!({"token": token, "value": value})
+    }
+
+    #[tokio::test]
+    async fn test_progress_waits_for_every_task_to_end() {
+        let tracker = Progress::default();
+        let quiet = Duration::from_millis(20);
+        let begin = |title: &str| json!({"kind": "begin", "title": title});
+
+        handle_notification(
+            "$/progress",
+            Some(&progress("index", begin("Indexing"))),
+            &tracker,
+        );
+        handle_notification(
+            "$/progress",
+            Some(&progress("fetch", begin("Fetching"))),
+            &tracker,
+        );
+        let report = json!({"kind": "report", "message": "1/2", "percentage": 50});
+        handle_notification("$/progress", Some(&progress("index", report)), &tracker);
+        assert!(
+            !tracker
+                .wait_until_idle(quiet, Duration::from_millis(50))
+                .await
+        );
+
+        let end = json!({"kind": "end"});
+        handle_notification(
+            "$/progress",
+            Some(&progress("index", end.clone())),
+            &tracker,
+        );
+        assert!(
+            !tracker
+                .wait_until_idle(quiet, Duration::from_millis(50))
+                .await
+        );
+
+        handle_notification("$/progress", Some(&progress("fetch", end)), &tracker);
+        assert!(tracker.wait_until_idle(quiet, Duration::from_secs(5)).await);
+    }
+
+    #[tokio::test]
+    async fn test_progress_wakes_up_when_work_ends() {
+        let tracker = std::sync::Arc::new(Progress::default());
+        let begin = json!({"kind": "begin", "title": "Indexing"});
+        handle_notification("$/progress", Some(&progress("index", begin)), &tracker);
+
+        let waiter = tokio::spawn({
+            let tracker = tracker.clone();
+            async move {
+                tracker
+                    .wait_until_idle(Duration::ZERO, Duration::from_secs(5))
+                    .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let end = json!({"kind": "end"});
+        handle_notification("$/progress", Some(&progress("index", end)), &tracker);
+        assert!(waiter.await.unwrap());
+    }
+
+    #[test]
+    fn test_unknown_request_is_rejected() {
+        let error = handle_server_request("custom/unknown", None, &Value::Null).unwrap_err();
+        assert_eq!(error, jsonrpc::Error::method_not_found());
+    }
+}
@@ -0,0 +1,285 @@
+//! Conversion between LSP positions and columns in source text
+//!
+//! LSP counts the `character` of a position in code units of the position
+//! encoding negotiated at `initialize`: UTF-16 unless the server picks
+//! another. The analysis counts columns in Unicode characters, which is what
+//! editors display for `file:line:col`.
+
+use std::path::Path;
+use tower_lsp::lsp_types::{Position, PositionEncodingKind, Range, ServerCapabilities};
+
+/// Unit of the `character` field of LSP positions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PositionEncoding {
+    Utf8,
+    /// The protocol default
+    #[default]
+    Utf16,
+    /// Unicode characters
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Encodings the client accepts, most preferred first
+    pub fn supported() -> Vec<PositionEncodingKind> {
+        vec![
+            PositionEncodingKind::UTF8,
+            PositionEncodingKind::UTF16,
+            PositionEncodingKind::UTF32,
+        ]
+    }
+
+    /// The encoding a server chose in its `initialize` result
+    pub fn negotiated(capabilities: &ServerCapabilities) -> Self {
+        match capabilities
+            .position_encoding
+            .as_ref()
+            .map(|kind| kind.as_str())
+        {
+            Some("utf-8") => Self::Utf8,
+            Some("utf-32") => Self::Utf32,
+            _ => Self::Utf16,
+        }
+    }
+
+    /// Code units of `c` in this encoding
+    fn width(self, c: char) -> u32 {
+        match self {
+            Self::Utf8 => c.len_utf8() as u32,
+            Self::Utf16 => c.len_utf16() as u32,
+            Self::Utf32 => 1,
+        }
+    }
+
+    /// Zero-based character column of an LSP `character` offset into `line`
+    ///
+    /// Offsets inside a character round down; offsets past the end count on
+    /// as if the line were padded with single-unit characters.
+    pub fn to_column(self, line: &str, character: u32) -> u32 {
+        if self == Self::Utf32 {
+            return character;
+        }
+        let mut units = 0;
+        for (column, c) in line.chars().enumerate() {
+            let next = units + self.width(c);
+            if next > character {
+                return column as u32;
+            }
+            units = next;
+        }
+        line.chars().count() as u32 + (character - units)
+    }
+
+    /// LSP `character` offset of a zero-based character column of `line`
+    pub fn to_character(self, line: &str, column: u32) -> u32 {
+        if self == Self::Utf32 {
+            return column;
+        }
+        let mut chars = line.chars();
+        let mut units = 0;
+        for _ in 0..column {
+            units += chars.next().map_or(1, |c| self.width(c));
+        }
+        units
+    }
+}
+
+// ============================================================================
+// Source Text
+// ============================================================================
+
+/// Lines of a source file, for converting positions inside it
+#[derive(Debug, Clone, Default)]
+pub struct SourceLines {
+    lines: Vec<String>,
+}
+
+impl SourceLines {
+    pub fn new(text: &str) -> Self {
+        Self {
+            lines: text.lines().map(str::to_string).collect(),
+        }
+    }
+
+    /// Read a file; an unreadable file has no lines
+    pub fn read(path: &Path) -> Self {
+        std::fs::read_to_string(path)
+            .map(|text| Self::new(&text))
+            .unwrap_or_default()
+    }
+
+    /// A zero-based line, empty if the file is shorter
+    pub fn line(&self, line: u32) -> &str {
+        self.lines
+            .get(line as usize)
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+
+    /// Convert a position from `encoding` to character columns
+    pub fn to_columns(&self, encoding: PositionEncoding, position: Position) -> Position {
+        let line = self.line(position.line);
+        Position::new(position.line, encoding.to_column(line, position.character))
+    }
+
+    /// Convert both ends of a range from `encoding` to character columns
+    pub fn range_to_columns(&self, encoding: PositionEncoding, range: Range) -> Range {
+        Range::new(
+            self.to_columns(encoding, range.start),
+            self.to_columns(encoding, range.end),
+        )
+    }
+
+    /// Text of a single-line range in character columns
+    pub fn text(&self, range: Range) -> String {
+        let length = range.end.character.saturating_sub(range.start.character);
+        self.line(range.start.line)
+            .chars()
+            .skip(range.start.character as usize)
+            .take(length as usize)
+            .collect()
+    }
+
+    /// First whole-word occurrence of `word` inside a range, both in
+    /// character columns
+    pub fn find_word(&self, range: Range, word: &str) -> Option<Position> {
+        let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+        let word: Vec<char> = word.chars().collect();
+        if word.is_empty() {
+            return None;
+        }
+        (range.start.line..=range.end.line).find_map(|line| {
+            let text: Vec<char> = self.line(line).chars().collect();
+            let from = if line == range.start.line {
+                range.start.character as usize
+            } else {
+                0
+            };
+            let to = if line == range.end.line {
+                (range.end.character as usize).min(text.len())
+            } else {
+                text.len()
+            };
+            (from..to)
+                .find(|&start| {
+                    let end = start + word.len();
+                    end <= to
+                        && text[start..end] == word[..]
+                        && (start == 0 || !is_ident(text[start - 1]))
+                        && text.get(end).is_none_or(|&c| !is_ident(c))
+                })
+                .map(|start| Position::new(line, start as u32))
+        })
+    }
+
+    /// Convert a position in character columns to `encoding`
+    pub fn to_encoding(&self, encoding: PositionEncoding, position: Position) -> Position {
+        let line = self.line(position.line);
+        Position::new(
+            position.line,
+            encoding.to_character(line, position.character),
+        )
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// `é` is 2 UTF-8 bytes and 1 UTF-16 unit; `𝔽` is 4 bytes and 2 units
+    const LINE: &str = "let é = 𝔽(x);";
+
+    #[test]
+    fn test_negotiated_encoding_defaults_to_utf16() {
+        let capabilities = |value| serde_json::from_value(value).unwrap();
+        assert_eq!(
+            PositionEncoding::negotiated(&capabilities(json!({}))),
+            PositionEncoding::Utf16
+        );
+        assert_eq!(
+            PositionEncoding::negotiated(&capabilities(json!({"positionEncoding": "utf-8"}))),
+            PositionEncoding::Utf8
+        );
+        assert_eq!(
+            PositionEncoding::negotiated(&capabilities(json!({"positionEncoding": "utf-32"}))),
+            PositionEncoding::Utf32
+        );
+    }
+
+    #[test]
+    fn test_offsets_convert_to_character_columns() {
+        // Column 9 is the `(` after `𝔽`
+        assert_eq!(PositionEncoding::Utf8.to_column(LINE, 13), 9);
+        assert_eq!(PositionEncoding::Utf16.to_column(LINE, 10), 9);
+        assert_eq!(PositionEncoding::Utf32.to_column(LINE, 9), 9);
+
+        assert_eq!(PositionEncoding::Utf8.to_character(LINE, 9), 13);
+        assert_eq!(PositionEncoding::Utf16.to_character(LINE, 9), 10);
+        assert_eq!(PositionEncoding::Utf32.to_character(LINE, 9), 9);
+    }
+
+    #[test]
+    fn test_conversion_round_trips_every_column() {
+        for encoding in [
+            PositionEncoding::Utf8,
+            PositionEncoding::Utf16,
+            PositionEncoding::Utf32,
+        ] {
+            for column in 0..=LINE.chars().count() as u32 + 2 {
+                let character = encoding.to_character(LINE, column);
+                assert_eq!(encoding.to_column(LINE, character), column, "{encoding:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_offset_inside_a_character_rounds_down() {
+        // Byte 5 is the second byte of `é` at column 4
+        assert_eq!(PositionEncoding::Utf8.to_column(LINE, 5), 4);
+        // Unit 9 is the low surrogate of `𝔽` at column 8
+        assert_eq!(PositionEncoding::Utf16.to_column(LINE, 9), 8);
+    }
+
+    #[test]
+    fn test_source_lines_convert_ranges() {
+        let source = SourceLines::new("fn main() {}\n    // é\n    é();\n");
+        let range = Range::new(Position::new(2, 4), Position::new(2, 6));
+        assert_eq!(
+            source.range_to_columns(PositionEncoding::Utf8, range),
+            Range::new(Position::new(2, 4), Position::new(2, 5))
+        );
+        assert_eq!(
+            source.to_encoding(PositionEncoding::Utf8, Position::new(1, 8)),
+            Position::new(1, 9)
+        );
+        assert_eq!(
+            source.text(Range::new(Position::new(2, 4), Position::new(2, 5))),
+            "é"
+        );
+        // Lines past the end are left as they are
+        assert_eq!(
+            source.to_columns(PositionEncoding::Utf8, Position::new(7, 3)),
+            Position::new(7, 3)
+        );
+    }
+
+    #[test]
+    fn test_source_lines_find_whole_words() {
+        let source = SourceLines::new("@cached\ndef é_run(x):\n    run()\n\ndef run():\n");
+        let declaration = Range::new(Position::new(4, 0), Position::new(4, 10));
+        assert_eq!(
+            source.find_word(declaration, "run"),
+            Some(Position::new(4, 4))
+        );
+        // `run` inside `é_run` is not a word of its own
+        let whole = Range::new(Position::new(0, 0), Position::new(2, 9));
+        assert_eq!(source.find_word(whole, "run"), Some(Position::new(2, 4)));
+        assert_eq!(source.find_word(whole, "é_run"), Some(Position::new(1, 4)));
+        assert_eq!(source.find_word(declaration, "main"), None);
+    }
+}
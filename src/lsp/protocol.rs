@@ -0,0 +1,318 @@
+//! JSON-RPC message types and `Content-Length` framing used on the wire
+
+use super::position::PositionEncoding;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tower_lsp::jsonrpc::{Request, Response};
+use tower_lsp::lsp_types::{
+    CallHierarchyClientCapabilities, ClientCapabilities, ClientInfo,
+    DocumentSymbolClientCapabilities, GeneralClientCapabilities, GotoCapability, InitializeParams,
+    OneOf, ReferenceClientCapabilities, SemanticTokenModifier, SemanticTokenType,
+    SemanticTokensClientCapabilities, SemanticTokensClientCapabilitiesRequests,
+    SemanticTokensFullOptions, TextDocumentClientCapabilities, TokenFormat, Url,
+    WindowClientCapabilities, WorkspaceClientCapabilities, WorkspaceFolder,
+    WorkspaceSymbolClientCapabilities,
+};
+
+// ============================================================================
+// Messages
+// ============================================================================
+
+/// A single JSON-RPC message received from the language server
+///
+/// Requests without an id are notifications. Responses are tried first
+/// because `Request` tolerates a missing `method` field.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Message {
+    Response(Response),
+    Request(Request),
+}
+
+// ============================================================================
+// Framing
+// ============================================================================
+
+const CONTENT_LENGTH: &str = "content-length";
+
+/// Read one framed message from the stream
+///
+/// Returns `Ok(None)` when the stream is closed cleanly between messages.
+pub async fn read_message<R>(reader: &mut R) -> io::Result<Option<Message>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut content_length = None;
+    let mut in_headers = false;
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return if !in_headers {
+                Ok(None)
+            } else {
+                Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "stream closed inside message headers",
+                ))
+            };
+        }
+
+        let header = line.trim_end_matches(['\r', '\n']);
+        if header.is_empty() {
+            // An empty line before any header is tolerated as stray whitespace
+            if in_headers {
+                break;
+            }
+            continue;
+        }
+        in_headers = true;
+
+        let (name, value) = header.split_once(':').ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("malformed header line: {header:?}"),
+            )
+        })?;
+        if name.trim().eq_ignore_ascii_case(CONTENT_LENGTH) {
+            let length = value.trim().parse::<usize>().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid Content-Length: {:?}", value.trim()),
+                )
+            })?;
+            content_length = Some(length);
+        }
+    }
+
+    let content_length = content_length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Write one message to the stream with a `Content-Length` header
+pub async fn write_message<W, T>(writer: &mut W, message: &T) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+    T: Serialize,
+{
+    let body = serde_json::to_vec(message)?;
+    let header = format!("Content-Length: {}\r\n\r\n", body.len());
+
+    writer.write_all(header.as_bytes()).await?;
+    writer.write_all(&body).await?;
+    writer.flush().await
+}
+
+// ============================================================================
+// Initialization
+// ============================================================================
+
+/// Build the `initialize` parameters advertising what this client understands
+pub fn initialize_params(root_uri: Url, workspace_name: String) -> InitializeParams {
+    #[allow(deprecated)]
+    InitializeParams {
+        process_id: Some(std::process::id()),
+        root_path: None,
+        root_uri: Some(root_uri.clone()),
+        workspace_folders: Some(vec![WorkspaceFolder {
+            uri: root_uri,
+            name: workspace_name,
+        }]),
+        capabilities: client_capabilities(),
+        client_info: Some(ClientInfo {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        }),
+        ..Default::default()
+    }
+}
+
+/// Capabilities needed for call graph analysis
+fn client_capabilities() -> ClientCapabilities {
+    ClientCapabilities {
+        workspace: Some(WorkspaceClientCapabilities {
+            configuration: Some(true),
+            workspace_folders: Some(true),
+            symbol: Some(WorkspaceSymbolClientCapabilities::default()),
+            ..Default::default()
+        }),
+        text_document: Some(TextDocumentClientCapabilities {
+            call_hierarchy: Some(CallHierarchyClientCapabilities {
+                dynamic_registration: Some(false),
+            }),
+            document_symbol: Some(DocumentSymbolClientCapabilities {
+                hierarchical_document_symbol_support: Some(true),
+                ..Default::default()
+            }),
+            references: Some(ReferenceClientCapabilities::default()),
+            definition: Some(GotoCapability {
+                link_support: Some(true),
+                ..Default::default()
+            }),
+            // Only calls are of interest when scanning function bodies
+            semantic_tokens: Some(SemanticTokensClientCapabilities {
+                requests: SemanticTokensClientCapabilitiesRequests {
+                    range: Some(false),
+                    full: Some(SemanticTokensFullOptions::Bool(true)),
+                },
+                token_types: vec![SemanticTokenType::FUNCTION, SemanticTokenType::METHOD],
+                token_modifiers: vec![SemanticTokenModifier::DECLARATION],
+                formats: vec![TokenFormat::RELATIVE],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        // UTF-8 spares converting columns for servers that support it
+        general: Some(GeneralClientCapabilities {
+            position_encodings: Some(PositionEncoding::supported()),
+            ..Default::default()
+        }),
+        // Lets the server report indexing through `$/progress`
+        window: Some(WindowClientCapabilities {
+            work_done_progress: Some(true),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// LSP language identifier for a source file, based on its extension
+pub fn language_id(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default();
+    match extension {
+        "rs" => "rust",
+        "py" | "pyi" => "python",
+        "ts" | "mts" | "cts" => "typescript",
+        "tsx" => "typescriptreact",
+        "js" | "mjs" | "cjs" => "javascript",
+        "jsx" => "javascriptreact",
+        "go" => "go",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hh" | "hpp" | "hxx" => "cpp",
+        "java" => "java",
+        "cs" => "csharp",
+        _ => "plaintext",
+    }
+}
+
+/// Whether an optional `boolean | Options` server capability is enabled
+pub fn is_enabled<T>(capability: Option<&OneOf<bool, T>>) -> bool {
+    matches!(capability, Some(OneOf::Left(true)) | Some(OneOf::Right(_)))
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::io::BufReader;
+    use tower_lsp::jsonrpc::Id;
+    use tower_lsp::lsp_types::PositionEncodingKind;
+
+    #[tokio::test]
+    async fn test_write_then_read_roundtrip() {
+        let request = Request::build("textDocument/prepareCallHierarchy")
+            .id(7)
+            .params(json!({"textDocument": {"uri": "file:///a.rs"}}))
+            .finish();
+
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &request).await.unwrap();
+        assert!(buffer.starts_with(b"Content-Length: "));
+
+        let mut reader = BufReader::new(buffer.as_slice());
+        let message = read_message(&mut reader).await.unwrap();
+        assert_eq!(message, Some(Message::Request(request)));
+        assert_eq!(read_message(&mut reader).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_response_with_extra_headers() {
+        let body = r#"{"jsonrpc":"2.0","id":3,"result":null}"#;
+        let raw = format!(
+            "Content-Type: application/vscode-jsonrpc; charset=utf-8\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+
+        let mut reader = BufReader::new(raw.as_bytes());
+        let message = read_message(&mut reader).await.unwrap();
+        assert_eq!(
+            message,
+            Some(Message::Response(Response::from_ok(
+                Id::Number(3),
+                serde_json::Value::Null
+            )))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_truncated_body_fails() {
+        let raw = "Content-Length: 100\r\n\r\n{\"jsonrpc\":\"2.0\"}";
+        let mut reader = BufReader::new(raw.as_bytes());
+        assert!(read_message(&mut reader).await.is_err());
+    }
+
+    #[test]
+    fn test_language_id_from_extension() {
+        assert_eq!(language_id(Path::new("src/main.rs")), "rust");
+        assert_eq!(language_id(Path::new("app/view.tsx")), "typescriptreact");
+        assert_eq!(language_id(Path::new("include/util.hpp")), "cpp");
+        assert_eq!(language_id(Path::new("README")), "plaintext");
+    }
+
+    #[test]
+    fn test_is_enabled_capability() {
+        assert!(is_enabled::<()>(Some(&OneOf::Left(true))));
+        assert!(is_enabled(Some(&OneOf::<bool, ()>::Right(()))));
+        assert!(!is_enabled::<()>(Some(&OneOf::Left(false))));
+        assert!(!is_enabled::<()>(None));
+    }
+
+    #[test]
+    fn test_initialize_params_advertise_call_hierarchy() {
+        let root = Url::parse("file:///workspace/project/").unwrap();
+        let params = initialize_params(root.clone(), "project".to_string());
+
+        #[allow(deprecated)]
+        let root_uri = params.root_uri.clone();
+        assert_eq!(root_uri, Some(root));
+        let text_document = params.capabilities.text_document.unwrap();
+        assert!(text_document.call_hierarchy.is_some());
+        assert_eq!(
+            text_document
+                .document_symbol
+                .unwrap()
+                .hierarchical_document_symbol_support,
+            Some(true)
+        );
+        let window = params.capabilities.window.unwrap();
+        assert_eq!(window.work_done_progress, Some(true));
+        let encodings = params.capabilities.general.unwrap().position_encodings;
+        assert_eq!(encodings.unwrap()[0], PositionEncodingKind::UTF8);
+    }
+
+    #[tokio::test]
+    async fn test_read_invalid_content_length_fails() {
+        let raw = "Content-Length: abc\r\n\r\n{}";
+        let mut reader = BufReader::new(raw.as_bytes());
+        let err = read_message(&mut reader).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
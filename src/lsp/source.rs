@@ -0,0 +1,942 @@
+//! Language servers as a source of calls
+//!
+//! Calls come from the call hierarchy. For servers without one they are
+//! derived from `references`, or for outgoing calls from semantic tokens.
+
+use super::{LspClient, LspConnection, PositionEncoding, SourceLines};
+use crate::analyzer::analyzed_files;
+use crate::config::Config;
+use crate::source::{CallGraphSource, Calls, FunctionSymbol};
+use crate::{CallGraphError, Result};
+use futures::future::BoxFuture;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, OnceCell};
+use tower_lsp::lsp_types::request::{
+    CallHierarchyIncomingCalls, CallHierarchyOutgoingCalls, CallHierarchyPrepare,
+    DocumentSymbolRequest, GotoDefinition, References, SemanticTokensFullRequest,
+    WorkspaceSymbolRequest,
+};
+use tower_lsp::lsp_types::{
+    CallHierarchyIncomingCallsParams, CallHierarchyItem, CallHierarchyOutgoingCallsParams,
+    CallHierarchyPrepareParams, DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse,
+    GotoDefinitionParams, GotoDefinitionResponse, Location, OneOf, Position, Range,
+    ReferenceContext, ReferenceParams, SemanticTokenModifier, SemanticTokenType,
+    SemanticTokensParams, SemanticTokensResult, SymbolKind, TextDocumentIdentifier,
+    TextDocumentPositionParams, Url, WorkspaceSymbolParams, WorkspaceSymbolResponse,
+};
+use tracing::{debug, info, warn};
+
+// ============================================================================
+// Call Graph Source
+// ============================================================================
+
+/// Per-analysis state of the ways to find calls besides the call hierarchy
+#[derive(Default)]
+pub(crate) struct Fallbacks {
+    references: Mutex<ReferenceIndex>,
+    tokens: TokenIndex,
+    /// Cross-check call hierarchy callees against semantic tokens
+    verify_calls: bool,
+    /// Definitions resolved at once for the call tokens of one function
+    max_concurrent_requests: usize,
+}
+
+impl CallGraphSource for LspClient {
+    fn document_symbols<'a>(
+        &'a self,
+        file: &'a Path,
+    ) -> BoxFuture<'a, Result<Vec<FunctionSymbol>>> {
+        Box::pin(document_functions(self, file))
+    }
+
+    fn prepare_item<'a>(
+        &'a self,
+        function: &'a FunctionSymbol,
+    ) -> BoxFuture<'a, Result<Vec<CallHierarchyItem>>> {
+        Box::pin(function_items(self, function))
+    }
+
+    fn outgoing_calls<'a>(&'a self, item: &'a CallHierarchyItem) -> BoxFuture<'a, Result<Calls>> {
+        Box::pin(calls_of(self, item, true))
+    }
+
+    fn incoming_calls<'a>(&'a self, item: &'a CallHierarchyItem) -> BoxFuture<'a, Result<Calls>> {
+        Box::pin(calls_of(self, item, false))
+    }
+
+    fn workspace_symbols<'a>(
+        &'a self,
+        query: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Vec<FunctionSymbol>>>> {
+        Box::pin(async move {
+            if !self.supports_workspace_symbols() {
+                return Ok(None);
+            }
+            workspace_functions(self, query).await.map(Some)
+        })
+    }
+
+    /// Columns are converted to the code units the server negotiated
+    fn to_source_position(&self, file: &Path, position: Position) -> Position {
+        let encoding = Url::from_file_path(file)
+            .map(|uri| self.position_encoding(&uri))
+            .unwrap_or_default();
+        SourceLines::read(file).to_encoding(encoding, position)
+    }
+
+    fn prepare<'a>(&'a mut self, config: &'a Config) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            // Fail up front rather than midway through the traversal
+            if !self.supports_call_hierarchy()
+                && !self.connections().any(|connection| {
+                    uses_references(connection) || uses_semantic_tokens(connection)
+                })
+            {
+                return Err(CallGraphError::AnalysisError(
+                    "Language server provides neither callHierarchyProvider nor \
+                     documentSymbolProvider with referencesProvider or semanticTokensProvider"
+                        .to_string(),
+                )
+                .into());
+            }
+            // Semantic tokens only lead from a body to its callees
+            if config.direction.includes_callers() {
+                let finds_callers = |connection: &LspConnection| {
+                    connection.supports_call_hierarchy() || uses_references(connection)
+                };
+                if !self.connections().any(finds_callers) {
+                    return Err(CallGraphError::AnalysisError(
+                        "Finding callers requires callHierarchyProvider or documentSymbolProvider \
+                         with referencesProvider; use --direction callees"
+                            .to_string(),
+                    )
+                    .into());
+                }
+                if !self.connections().all(finds_callers) {
+                    warn!("Some language servers can only find callees; their callers are missing");
+                }
+            }
+            if config.root_function.is_none() && !self.supports_document_symbols() {
+                return Err(CallGraphError::AnalysisError(
+                    "Analyzing all functions requires documentSymbolProvider; pass --root instead"
+                        .to_string(),
+                )
+                .into());
+            }
+
+            wait_for_indexing(self, config).await;
+            self.fallbacks = Fallbacks {
+                references: Mutex::new(ReferenceIndex::new(analyzed_files(config)?)),
+                tokens: TokenIndex::default(),
+                verify_calls: config.analysis.verify_calls,
+                max_concurrent_requests: config.lsp.max_concurrent_requests.max(1),
+            };
+            Ok(())
+        })
+    }
+
+    fn restart<'a>(&'a mut self, config: &'a Config) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.restart_exited().await?;
+            wait_for_indexing(self, config).await;
+            Ok(())
+        })
+    }
+}
+
+/// Callees or callers of an item in the way its server supports
+async fn calls_of(client: &LspClient, item: &CallHierarchyItem, outgoing: bool) -> Result<Calls> {
+    let Some(connection) = client.connection_for(&item.uri) else {
+        debug!("No language server for {}", item.uri);
+        return Ok(Vec::new());
+    };
+    if connection.supports_call_hierarchy() {
+        let calls = if outgoing {
+            outgoing_calls(client, item.clone()).await?
+        } else {
+            incoming_calls(client, item.clone()).await?
+        };
+        if outgoing && client.fallbacks.verify_calls && uses_semantic_tokens(connection) {
+            verify_calls(client, connection, item, &calls).await;
+        }
+        Ok(calls)
+    } else if uses_references(connection) {
+        let mut references = client.fallbacks.references.lock().await;
+        references.calls_of(client, item, outgoing).await
+    } else if outgoing && uses_semantic_tokens(connection) {
+        token_calls(client, connection, item).await
+    } else {
+        debug!(
+            "No way to find {} calls for {}",
+            if outgoing { "outgoing" } else { "incoming" },
+            item.uri
+        );
+        Ok(Vec::new())
+    }
+}
+
+// ============================================================================
+// Symbols
+// ============================================================================
+
+/// Function-like symbols declared in a single file
+async fn document_functions(client: &LspClient, file: &Path) -> Result<Vec<FunctionSymbol>> {
+    let uri = client.open_document(file).await?;
+    let params = DocumentSymbolParams {
+        text_document: TextDocumentIdentifier { uri: uri.clone() },
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+    };
+
+    let mut candidates = Vec::new();
+    match client
+        .request_for::<DocumentSymbolRequest>(&uri, params)
+        .await?
+    {
+        Some(DocumentSymbolResponse::Nested(symbols)) => {
+            collect_nested_functions(&uri, &symbols, &mut Vec::new(), &mut candidates);
+        }
+        Some(DocumentSymbolResponse::Flat(symbols)) => {
+            let mut names = NameFinder::new(client.position_encoding(&uri));
+            candidates.extend(
+                symbols
+                    .into_iter()
+                    .filter(|symbol| is_function_kind(symbol.kind))
+                    .map(|symbol| FunctionSymbol {
+                        position: names.position(&symbol.location, &symbol.name),
+                        name: symbol.name,
+                        kind: symbol.kind,
+                        detail: None,
+                        container: container_segments(symbol.container_name.as_deref()),
+                        uri: symbol.location.uri,
+                        range: symbol.location.range,
+                    }),
+            );
+        }
+        None => {}
+    }
+    Ok(candidates)
+}
+
+fn collect_nested_functions(
+    uri: &Url,
+    symbols: &[DocumentSymbol],
+    parents: &mut Vec<String>,
+    out: &mut Vec<FunctionSymbol>,
+) {
+    for symbol in symbols {
+        if is_function_kind(symbol.kind) {
+            out.push(FunctionSymbol {
+                name: symbol.name.clone(),
+                kind: symbol.kind,
+                detail: symbol.detail.clone(),
+                container: parents.clone(),
+                uri: uri.clone(),
+                range: symbol.range,
+                position: symbol.selection_range.start,
+            });
+        }
+        if let Some(children) = &symbol.children {
+            parents.extend(container_segments(Some(&symbol.name)));
+            collect_nested_functions(uri, children, parents, out);
+            parents.truncate(parents.len() - container_segments(Some(&symbol.name)).len());
+        }
+    }
+}
+
+/// Normalize a container name such as `impl<T> Display for Foo<T>` or
+/// `pkg.Class` into path segments (`Foo`, or `pkg`, `Class`)
+fn container_segments(container: Option<&str>) -> Vec<String> {
+    let Some(container) = container.map(str::trim).filter(|c| !c.is_empty()) else {
+        return Vec::new();
+    };
+
+    let container = match container.strip_prefix("impl") {
+        Some(rest) if rest.starts_with([' ', '<']) => {
+            let rest = rest.rsplit(" for ").next().unwrap_or(rest);
+            // Drop the generic parameter list of `impl<T>` itself
+            match rest.trim_start().strip_prefix('<') {
+                Some(generic) if !rest.contains(" for ") => {
+                    generic.split_once('>').map_or(generic, |(_, ty)| ty)
+                }
+                _ => rest,
+            }
+        }
+        _ => container,
+    };
+
+    container
+        .split("::")
+        .flat_map(|segment| segment.split('.'))
+        .map(|segment| segment.split('<').next().unwrap_or(segment).trim())
+        .filter(|segment| !segment.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Function-like symbols matching a query across the whole workspace
+///
+/// Every server that supports `workspace/symbol` is asked; a failing server
+/// only contributes a warning.
+async fn workspace_functions(client: &LspClient, query: &str) -> Result<Vec<FunctionSymbol>> {
+    let mut candidates = Vec::new();
+    for connection in client
+        .connections()
+        .filter(|connection| connection.supports_workspace_symbols())
+    {
+        let params = WorkspaceSymbolParams {
+            query: query.to_string(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        match connection.request::<WorkspaceSymbolRequest>(params).await {
+            Ok(response) => {
+                let mut names = NameFinder::new(connection.position_encoding());
+                candidates.extend(workspace_candidates(response, &mut names));
+            }
+            Err(e) => warn!("workspace/symbol failed: {}", e),
+        }
+    }
+    Ok(candidates)
+}
+
+fn workspace_candidates(
+    response: Option<WorkspaceSymbolResponse>,
+    names: &mut NameFinder,
+) -> Vec<FunctionSymbol> {
+    match response {
+        Some(WorkspaceSymbolResponse::Flat(symbols)) => symbols
+            .into_iter()
+            .filter(|symbol| is_function_kind(symbol.kind))
+            .map(|symbol| FunctionSymbol {
+                position: names.position(&symbol.location, &symbol.name),
+                name: symbol.name,
+                kind: symbol.kind,
+                detail: None,
+                container: container_segments(symbol.container_name.as_deref()),
+                uri: symbol.location.uri,
+                range: symbol.location.range,
+            })
+            .collect(),
+        Some(WorkspaceSymbolResponse::Nested(symbols)) => symbols
+            .into_iter()
+            .filter(|symbol| is_function_kind(symbol.kind))
+            .filter_map(|symbol| match symbol.location {
+                OneOf::Left(location) => Some(FunctionSymbol {
+                    position: names.position(&location, &symbol.name),
+                    name: symbol.name,
+                    kind: symbol.kind,
+                    detail: None,
+                    container: container_segments(symbol.container_name.as_deref()),
+                    uri: location.uri,
+                    range: location.range,
+                }),
+                // Locations without a range would need workspaceSymbol/resolve
+                OneOf::Right(_) => None,
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Finds the names of flat symbols in their files
+///
+/// `SymbolInformation` has no selection range. Its range covers the whole
+/// declaration and starts at a keyword or attribute, where servers find no
+/// symbol for references or the call hierarchy.
+struct NameFinder {
+    encoding: PositionEncoding,
+    files: HashMap<Url, SourceLines>,
+}
+
+impl NameFinder {
+    fn new(encoding: PositionEncoding) -> Self {
+        Self {
+            encoding,
+            files: HashMap::new(),
+        }
+    }
+
+    /// Start of `name` inside a declaration, or of the declaration if the
+    /// name is not in the text
+    fn position(&mut self, location: &Location, name: &str) -> Position {
+        let lines = self.files.entry(location.uri.clone()).or_insert_with(|| {
+            match location.uri.to_file_path() {
+                Ok(path) => SourceLines::read(&path),
+                Err(_) => SourceLines::default(),
+            }
+        });
+        // Some servers append parameters or qualify names, e.g. `Foo.run(int)`
+        let name = name.split('(').next().unwrap_or(name);
+        let name = name.rsplit(['.', ':']).next().unwrap_or(name).trim();
+        let range = lines.range_to_columns(self.encoding, location.range);
+        lines
+            .find_word(range, name)
+            .map_or(location.range.start, |position| {
+                lines.to_encoding(self.encoding, position)
+            })
+    }
+}
+
+fn is_function_kind(kind: SymbolKind) -> bool {
+    matches!(
+        kind,
+        SymbolKind::FUNCTION | SymbolKind::METHOD | SymbolKind::CONSTRUCTOR
+    )
+}
+
+/// Items for a function, from the call hierarchy where the server has one
+async fn function_items(
+    client: &LspClient,
+    candidate: &FunctionSymbol,
+) -> Result<Vec<CallHierarchyItem>> {
+    match client.connection_for(&candidate.uri) {
+        Some(connection) if !connection.supports_call_hierarchy() => Ok(vec![candidate.to_item()]),
+        _ => prepare_call_hierarchy(client, &candidate.uri, candidate.position).await,
+    }
+}
+
+async fn prepare_call_hierarchy(
+    client: &LspClient,
+    uri: &Url,
+    position: Position,
+) -> Result<Vec<CallHierarchyItem>> {
+    let params = CallHierarchyPrepareParams {
+        text_document_position_params: TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            position,
+        },
+        work_done_progress_params: Default::default(),
+    };
+    Ok(client
+        .request_for::<CallHierarchyPrepare>(uri, params)
+        .await?
+        .unwrap_or_default())
+}
+
+/// Wait for the servers' initial indexing, since queries made before it
+/// finishes come back empty
+async fn wait_for_indexing(client: &LspClient, config: &Config) {
+    let readiness_timeout = config.lsp.readiness_timeout_seconds;
+    if readiness_timeout == 0 {
+        return;
+    }
+    info!("Waiting for the language server to finish indexing");
+    if !client
+        .wait_until_ready(Duration::from_secs(readiness_timeout))
+        .await
+    {
+        warn!(
+            "Language server still busy after {}s; the graph may be incomplete",
+            readiness_timeout
+        );
+    }
+}
+
+// ============================================================================
+// Call Hierarchy
+// ============================================================================
+
+/// Callees of an item with the call sites inside the item
+async fn outgoing_calls(client: &LspClient, item: CallHierarchyItem) -> Result<Calls> {
+    let uri = item.uri.clone();
+    let params = CallHierarchyOutgoingCallsParams {
+        item,
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+    };
+    let calls = client
+        .request_for::<CallHierarchyOutgoingCalls>(&uri, params)
+        .await?
+        .unwrap_or_default();
+    let source = call_site_source(client, &uri);
+    Ok(calls
+        .into_iter()
+        .map(|call| (call.to, source.convert(call.from_ranges)))
+        .collect())
+}
+
+/// Callers of an item with the call sites inside each caller
+async fn incoming_calls(client: &LspClient, item: CallHierarchyItem) -> Result<Calls> {
+    let uri = item.uri.clone();
+    let params = CallHierarchyIncomingCallsParams {
+        item,
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+    };
+    let calls = client
+        .request_for::<CallHierarchyIncomingCalls>(&uri, params)
+        .await?
+        .unwrap_or_default();
+    Ok(calls
+        .into_iter()
+        .map(|call| {
+            let call_sites = call_site_source(client, &call.from.uri).convert(call.from_ranges);
+            (call.from, call_sites)
+        })
+        .collect())
+}
+
+/// Text of a caller's file for converting call sites to character columns
+struct CallSiteSource {
+    encoding: PositionEncoding,
+    lines: SourceLines,
+}
+
+impl CallSiteSource {
+    fn convert(&self, ranges: Vec<Range>) -> Vec<Range> {
+        ranges
+            .into_iter()
+            .map(|range| self.lines.range_to_columns(self.encoding, range))
+            .collect()
+    }
+}
+
+/// The file is only read when the server does not count in characters
+fn call_site_source(client: &LspClient, caller: &Url) -> CallSiteSource {
+    let encoding = client.position_encoding(caller);
+    let lines = match caller.to_file_path() {
+        Ok(path) if encoding != PositionEncoding::Utf32 => SourceLines::read(&path),
+        _ => SourceLines::default(),
+    };
+    CallSiteSource { encoding, lines }
+}
+
+// ============================================================================
+// References Fallback
+// ============================================================================
+
+/// Whether calls are derived from references because the server has no
+/// call hierarchy
+fn uses_references(connection: &LspConnection) -> bool {
+    !connection.supports_call_hierarchy()
+        && connection.supports_references()
+        && connection.supports_document_symbols()
+}
+
+/// A reference to `callee` inside the declaration of `caller`
+struct ReferenceCall {
+    caller: FunctionSymbol,
+    callee: FunctionSymbol,
+    call_sites: Vec<Range>,
+}
+
+/// Calls derived from `textDocument/references` and document symbols
+///
+/// Every reference to a function B that lies inside the range of a function A
+/// counts as a call from A to B. Outgoing calls can only be found by asking
+/// for the references of every function, so the whole index is built on
+/// first use.
+#[derive(Default)]
+struct ReferenceIndex {
+    /// Files whose functions are looked up as callees
+    files: Vec<PathBuf>,
+    calls: Option<Vec<ReferenceCall>>,
+}
+
+impl ReferenceIndex {
+    fn new(files: Vec<PathBuf>) -> Self {
+        Self { files, calls: None }
+    }
+
+    /// Callees or callers of an item built by `FunctionSymbol::to_item`
+    async fn calls_of(
+        &mut self,
+        client: &LspClient,
+        item: &CallHierarchyItem,
+        outgoing: bool,
+    ) -> Result<Calls> {
+        if self.calls.is_none() {
+            self.calls = Some(self.build(client).await?);
+        }
+        let calls = self.calls.as_deref().unwrap_or_default();
+        Ok(calls
+            .iter()
+            .filter_map(|call| {
+                let (this, other) = if outgoing {
+                    (&call.caller, &call.callee)
+                } else {
+                    (&call.callee, &call.caller)
+                };
+                this.is(item)
+                    .then(|| (other.to_item(), call.call_sites.clone()))
+            })
+            .collect())
+    }
+
+    async fn build(&self, client: &LspClient) -> Result<Vec<ReferenceCall>> {
+        let mut functions: HashMap<Url, Vec<FunctionSymbol>> = HashMap::new();
+        // In file order, so the graph does not depend on hashing
+        let mut callees = Vec::new();
+        for file in &self.files {
+            // Files of servers with a call hierarchy are not needed
+            let Some(uri) = file
+                .canonicalize()
+                .ok()
+                .and_then(|path| Url::from_file_path(path).ok())
+                .filter(|uri| client.connection_for(uri).is_some_and(uses_references))
+            else {
+                continue;
+            };
+            match document_functions(client, file).await {
+                Ok(candidates) => {
+                    callees.extend(candidates.iter().cloned());
+                    functions.insert(uri, candidates);
+                }
+                Err(e) => warn!("Skipping {}: {}", file.display(), e),
+            }
+        }
+        info!(
+            "Finding references to {} functions to derive calls",
+            callees.len()
+        );
+
+        let mut sources: HashMap<Url, CallSiteSource> = HashMap::new();
+        let mut calls = Vec::new();
+        for callee in callees {
+            let locations = match references(client, &callee).await {
+                Ok(locations) => locations,
+                Err(e) if matches!(e.downcast_ref(), Some(CallGraphError::Timeout(_))) => {
+                    warn!("Skipping callers of {}: {}", callee.name, e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            for location in locations {
+                if !functions.contains_key(&location.uri) {
+                    // A reference from a file outside the analyzed ones
+                    let candidates = match location.uri.to_file_path() {
+                        Ok(path) => document_functions(client, &path).await.unwrap_or_default(),
+                        Err(_) => Vec::new(),
+                    };
+                    functions.insert(location.uri.clone(), candidates);
+                }
+                let Some(caller) = enclosing_function(&functions[&location.uri], location.range)
+                else {
+                    continue;
+                };
+                // Some servers list the declaration despite `includeDeclaration`
+                if location.uri == callee.uri && location.range.start == callee.position {
+                    continue;
+                }
+                let source = sources
+                    .entry(location.uri.clone())
+                    .or_insert_with(|| call_site_source(client, &location.uri));
+                calls.push(ReferenceCall {
+                    caller: caller.clone(),
+                    callee: callee.clone(),
+                    call_sites: source.convert(vec![location.range]),
+                });
+            }
+        }
+        Ok(calls)
+    }
+}
+
+/// The innermost function whose declaration contains `range`
+fn enclosing_function(candidates: &[FunctionSymbol], range: Range) -> Option<&FunctionSymbol> {
+    candidates
+        .iter()
+        .filter(|candidate| {
+            candidate.range.start <= range.start && range.end <= candidate.range.end
+        })
+        .min_by_key(|candidate| {
+            let range = candidate.range;
+            (
+                range.end.line - range.start.line,
+                range.end.character.saturating_sub(range.start.character),
+            )
+        })
+}
+
+/// Places referring to a function, without its declaration
+async fn references(client: &LspClient, function: &FunctionSymbol) -> Result<Vec<Location>> {
+    let params = ReferenceParams {
+        text_document_position: TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier {
+                uri: function.uri.clone(),
+            },
+            position: function.position,
+        },
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+        context: ReferenceContext {
+            include_declaration: false,
+        },
+    };
+    Ok(client
+        .request_for::<References>(&function.uri, params)
+        .await?
+        .unwrap_or_default())
+}
+
+// ============================================================================
+// Semantic Tokens Fallback
+// ============================================================================
+
+/// Whether outgoing calls can be found by resolving the function names used
+/// in a body
+fn uses_semantic_tokens(connection: &LspConnection) -> bool {
+    connection.semantic_tokens_legend().is_some()
+        && connection.supports_definition()
+        && connection.supports_document_symbols()
+}
+
+/// Callees of an item found through `textDocument/semanticTokens/full`
+///
+/// Every function or method token inside the item's range is resolved with
+/// `textDocument/definition`; the token itself is the call site.
+async fn token_calls(
+    client: &LspClient,
+    connection: &LspConnection,
+    item: &CallHierarchyItem,
+) -> Result<Calls> {
+    let index = &client.fallbacks.tokens;
+    let document = index.document(connection, &item.uri).await?;
+    let encoding = connection.position_encoding();
+
+    let tokens = document.calls.iter().copied().filter(|token| {
+        let inside = item.range.start <= token.start && token.end <= item.range.end;
+        inside && token.start != item.selection_range.start
+    });
+    // In token order, so the callees do not depend on response timing
+    let targets: Vec<(Range, Option<Location>)> = stream::iter(tokens)
+        .map(|token| async move {
+            let target = definition(connection, &item.uri, token.start).await?;
+            Result::<_>::Ok((token, target))
+        })
+        .buffered(client.fallbacks.max_concurrent_requests.max(1))
+        .try_collect()
+        .await?;
+
+    let mut calls: Vec<(CallHierarchyItem, Vec<Range>)> = Vec::new();
+    for (token, target) in targets {
+        let Some(target) = target else {
+            continue;
+        };
+        let candidates = index.functions(client, &target.uri).await;
+        let call_site = document.source.range_to_columns(encoding, token);
+        let callee = match candidates
+            .iter()
+            .find(|candidate| candidate.position == target.range.start)
+            .or_else(|| enclosing_function(&candidates, target.range))
+        {
+            Some(candidate) => candidate.to_item(),
+            // Declarations without document symbols, e.g. in dependencies
+            None => CallHierarchyItem {
+                name: document.source.text(call_site),
+                kind: SymbolKind::FUNCTION,
+                tags: None,
+                detail: None,
+                uri: target.uri,
+                range: target.range,
+                selection_range: target.range,
+                data: None,
+            },
+        };
+
+        let existing = calls.iter_mut().find(|(other, _)| {
+            other.uri == callee.uri && other.selection_range.start == callee.selection_range.start
+        });
+        match existing {
+            Some((_, call_sites)) => call_sites.push(call_site),
+            None => calls.push((callee, vec![call_site])),
+        }
+    }
+    Ok(calls)
+}
+
+/// Call tokens and text of a document
+struct TokenDocument {
+    calls: Vec<Range>,
+    source: SourceLines,
+}
+
+/// Per-file inputs of the semantic tokens fallback
+///
+/// Every function of a file is expanded on its own, so the tokens and text
+/// of a file and the functions declared in a callee's file are fetched on
+/// first use and shared for the rest of the analysis.
+#[derive(Default)]
+struct TokenIndex {
+    documents: FileCells<TokenDocument>,
+    functions: FileCells<Vec<FunctionSymbol>>,
+}
+
+/// A value per file, filled in once even if requested concurrently
+type FileCells<T> = std::sync::Mutex<HashMap<Url, Arc<OnceCell<Arc<T>>>>>;
+
+impl TokenIndex {
+    async fn document(&self, connection: &LspConnection, uri: &Url) -> Result<Arc<TokenDocument>> {
+        let cell = once_cell(&self.documents, uri);
+        cell.get_or_try_init(|| async {
+            let calls = call_tokens(connection, uri).await?;
+            let source = match uri.to_file_path() {
+                Ok(path) => SourceLines::read(&path),
+                Err(_) => SourceLines::default(),
+            };
+            Result::<_>::Ok(Arc::new(TokenDocument { calls, source }))
+        })
+        .await
+        .cloned()
+    }
+
+    /// Functions declared in a file; none if they cannot be listed
+    async fn functions(&self, client: &LspClient, uri: &Url) -> Arc<Vec<FunctionSymbol>> {
+        let cell = once_cell(&self.functions, uri);
+        cell.get_or_init(|| async {
+            Arc::new(match uri.to_file_path() {
+                Ok(path) => document_functions(client, &path).await.unwrap_or_default(),
+                Err(_) => Vec::new(),
+            })
+        })
+        .await
+        .clone()
+    }
+}
+
+/// The cell of a file, so concurrent expansions wait for one fetch
+fn once_cell<T>(cells: &FileCells<T>, uri: &Url) -> Arc<OnceCell<Arc<T>>> {
+    let mut cells = cells.lock().unwrap_or_else(|e| e.into_inner());
+    cells.entry(uri.clone()).or_default().clone()
+}
+
+/// Ranges of the function and method tokens of a document that are not
+/// declarations
+async fn call_tokens(connection: &LspConnection, uri: &Url) -> Result<Vec<Range>> {
+    let Some(legend) = connection.semantic_tokens_legend() else {
+        return Ok(Vec::new());
+    };
+    let is_call: Vec<bool> = legend
+        .token_types
+        .iter()
+        .map(|kind| *kind == SemanticTokenType::FUNCTION || *kind == SemanticTokenType::METHOD)
+        .collect();
+    let declaration = legend
+        .token_modifiers
+        .iter()
+        .position(|modifier| *modifier == SemanticTokenModifier::DECLARATION)
+        .map_or(0, |bit| 1 << bit);
+
+    let params = SemanticTokensParams {
+        text_document: TextDocumentIdentifier { uri: uri.clone() },
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+    };
+    let tokens = match connection
+        .request::<SemanticTokensFullRequest>(params)
+        .await?
+    {
+        Some(SemanticTokensResult::Tokens(tokens)) => tokens.data,
+        Some(SemanticTokensResult::Partial(partial)) => partial.data,
+        None => Vec::new(),
+    };
+
+    // Positions are relative to the previous token
+    let (mut line, mut start) = (0, 0);
+    let mut ranges = Vec::new();
+    for token in tokens {
+        if token.delta_line > 0 {
+            start = 0;
+        }
+        line += token.delta_line;
+        start += token.delta_start;
+        let is_call = is_call
+            .get(token.token_type as usize)
+            .copied()
+            .unwrap_or(false);
+        if is_call && token.token_modifiers_bitset & declaration == 0 {
+            ranges.push(Range::new(
+                Position::new(line, start),
+                Position::new(line, start + token.length),
+            ));
+        }
+    }
+    Ok(ranges)
+}
+
+/// Where the symbol at a position is declared; the first target if several
+async fn definition(
+    connection: &LspConnection,
+    uri: &Url,
+    position: Position,
+) -> Result<Option<Location>> {
+    let params = GotoDefinitionParams {
+        text_document_position_params: TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            position,
+        },
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+    };
+    Ok(match connection.request::<GotoDefinition>(params).await? {
+        Some(GotoDefinitionResponse::Scalar(location)) => Some(location),
+        Some(GotoDefinitionResponse::Array(locations)) => locations.into_iter().next(),
+        Some(GotoDefinitionResponse::Link(links)) => links
+            .into_iter()
+            .next()
+            .map(|link| Location::new(link.target_uri, link.target_selection_range)),
+        None => None,
+    })
+}
+
+/// Warn where semantic tokens find other callees than the call hierarchy
+async fn verify_calls(
+    client: &LspClient,
+    connection: &LspConnection,
+    item: &CallHierarchyItem,
+    calls: &[(CallHierarchyItem, Vec<Range>)],
+) {
+    let tokens = match token_calls(client, connection, item).await {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            warn!("Could not verify the calls of {}: {}", item.name, e);
+            return;
+        }
+    };
+    let names = |calls: &[(CallHierarchyItem, Vec<Range>)]| -> BTreeSet<String> {
+        calls
+            .iter()
+            .map(|(callee, _)| callee.name.clone())
+            .collect()
+    };
+    let (hierarchy, tokens) = (names(calls), names(&tokens));
+    let only_hierarchy: Vec<_> = hierarchy.difference(&tokens).cloned().collect();
+    let only_tokens: Vec<_> = tokens.difference(&hierarchy).cloned().collect();
+    if !only_hierarchy.is_empty() || !only_tokens.is_empty() {
+        warn!(
+            "Callees of {} differ: only in the call hierarchy [{}], only in semantic tokens [{}]",
+            item.name,
+            only_hierarchy.join(", "),
+            only_tokens.join(", ")
+        );
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_container_segments_normalizes_impl_blocks() {
+        assert_eq!(container_segments(Some("impl Foo")), vec!["Foo"]);
+        assert_eq!(
+            container_segments(Some("impl<T> Display for Wrapper<T>")),
+            vec!["Wrapper"]
+        );
+        assert_eq!(container_segments(Some("impl<T> Stack<T>")), vec!["Stack"]);
+        assert_eq!(
+            container_segments(Some("pkg.Handler")),
+            vec!["pkg", "Handler"]
+        );
+        assert!(container_segments(None).is_empty());
+    }
+}
@@ -0,0 +1,161 @@
+//! Scripted in-memory language server used by unit tests
+
+use super::protocol::{self, Message};
+use super::{Channel, LspClient, LspConnection, Transport};
+use crate::Result;
+use futures::future::BoxFuture;
+use serde_json::Value;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::io::{BufReader, DuplexStream, ReadHalf, WriteHalf};
+use tokio::task::JoinHandle;
+use tower_lsp::jsonrpc::{self, Request, Response};
+
+/// Server side of an in-memory connection to an `LspConnection`
+pub struct FakeServer {
+    reader: BufReader<ReadHalf<DuplexStream>>,
+    writer: WriteHalf<DuplexStream>,
+}
+
+impl FakeServer {
+    /// Next request or notification sent by the client
+    pub async fn next_request(&mut self) -> Option<Request> {
+        match protocol::read_message(&mut self.reader).await.unwrap() {
+            Some(Message::Request(request)) => Some(request),
+            Some(other) => panic!("unexpected message from client: {other:?}"),
+            None => None,
+        }
+    }
+
+    /// Next response sent by the client to a server-initiated request
+    pub async fn next_response(&mut self) -> Option<Response> {
+        match protocol::read_message(&mut self.reader).await.unwrap() {
+            Some(Message::Response(response)) => Some(response),
+            Some(other) => panic!("expected a response from client, got {other:?}"),
+            None => None,
+        }
+    }
+
+    pub async fn send<T: serde::Serialize>(&mut self, message: &T) {
+        protocol::write_message(&mut self.writer, message)
+            .await
+            .unwrap();
+    }
+
+    /// Answer every request with `handler` until the client disconnects
+    ///
+    /// Notifications are ignored.
+    pub fn serve<F>(self, mut handler: F) -> JoinHandle<()>
+    where
+        F: FnMut(&str, Value) -> jsonrpc::Result<Value> + Send + 'static,
+    {
+        self.serve_some(move |method, params| Some(handler(method, params)))
+    }
+
+    /// Like `serve`, but requests for which `handler` returns `None` are
+    /// never answered
+    pub fn serve_some<F>(self, handler: F) -> JoinHandle<()>
+    where
+        F: FnMut(&str, Value) -> Option<jsonrpc::Result<Value>> + Send + 'static,
+    {
+        self.serve_until(handler, |_, _| false)
+    }
+
+    /// Like `serve_some`, but the server disconnects as if it crashed at the
+    /// first request for which `crashes` returns true
+    pub fn serve_until<F, C>(mut self, mut handler: F, mut crashes: C) -> JoinHandle<()>
+    where
+        F: FnMut(&str, Value) -> Option<jsonrpc::Result<Value>> + Send + 'static,
+        C: FnMut(&str, &Value) -> bool + Send + 'static,
+    {
+        tokio::spawn(async move {
+            while let Ok(Some(message)) = protocol::read_message(&mut self.reader).await {
+                let Message::Request(request) = message else {
+                    continue;
+                };
+                let (method, id, params) = request.into_parts();
+                let Some(id) = id else {
+                    continue;
+                };
+                let params = params.unwrap_or(Value::Null);
+                if crashes(&method, &params) {
+                    break;
+                }
+                let Some(body) = handler(&method, params) else {
+                    continue;
+                };
+                if protocol::write_message(&mut self.writer, &Response::from_parts(id, body))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        })
+    }
+}
+
+/// Connect a client to a fresh fake server
+pub fn connect() -> (LspConnection, FakeServer) {
+    let (channel, server) = channel();
+    let client = LspConnection::from_streams(channel.reader, channel.writer);
+    (client, server)
+}
+
+/// A client whose only server is a fresh fake server handling every document
+pub fn connect_client() -> (LspClient, FakeServer) {
+    let (connection, server) = connect();
+    (LspClient::from_connection(connection), server)
+}
+
+/// A client whose only server is started again by `accept` after it exits
+///
+/// `accept` receives how many servers were started before this one.
+pub async fn restartable_client<F>(accept: F) -> LspClient
+where
+    F: Fn(usize, FakeServer) + Send + Sync + 'static,
+{
+    let transport = FakeTransport {
+        accept,
+        started: AtomicUsize::new(0),
+    };
+    let connection = LspConnection::connect(&transport).await.unwrap();
+    let mut client = LspClient::default();
+    client.add_restartable_server(None, connection, Box::new(transport));
+    client
+}
+
+fn channel() -> (Channel, FakeServer) {
+    let (client_side, server_side) = tokio::io::duplex(64 * 1024);
+    let (client_reader, client_writer) = tokio::io::split(client_side);
+    let (server_reader, server_writer) = tokio::io::split(server_side);
+
+    let server = FakeServer {
+        reader: BufReader::new(server_reader),
+        writer: server_writer,
+    };
+    (Channel::from_streams(client_reader, client_writer), server)
+}
+
+/// Transport handing the server side of every connection to a closure
+struct FakeTransport<F> {
+    accept: F,
+    started: AtomicUsize,
+}
+
+impl<F> fmt::Display for FakeTransport<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("fake")
+    }
+}
+
+impl<F> Transport for FakeTransport<F>
+where
+    F: Fn(usize, FakeServer) + Send + Sync,
+{
+    fn connect(&self) -> BoxFuture<'_, Result<Channel>> {
+        let (channel, server) = channel();
+        (self.accept)(self.started.fetch_add(1, Ordering::SeqCst), server);
+        Box::pin(async move { Ok(channel) })
+    }
+}
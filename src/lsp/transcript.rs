@@ -0,0 +1,467 @@
+//! Recording of LSP sessions and their replay without a server
+
+use super::protocol::{self, Message};
+use super::transport::{Channel, Transport};
+use crate::{CallGraphError, Result};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::io::DuplexStream;
+use tower_lsp::jsonrpc::{self, Response};
+use tower_lsp::lsp_types::Url;
+use tracing::{debug, warn};
+
+/// Methods whose parameters differ between runs, e.g. by process id
+const MATCHED_BY_METHOD: &[&str] = &["initialize", "shutdown"];
+
+/// Stands in for the workspace root URI, so a transcript replays in any checkout
+const WORKSPACE_PLACEHOLDER: &str = "$WORKSPACE";
+
+// ============================================================================
+// Transcript Format
+// ============================================================================
+
+/// One line of a transcript: a request with its response, or a notification
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Exchange {
+    /// Language of the server, or `None` for the catch-all server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub params: Value,
+    /// Answer of the server; `None` for notifications
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<Reply>,
+}
+
+/// Outcome of a recorded request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Reply {
+    Result(Value),
+    Error(jsonrpc::Error),
+}
+
+impl From<&jsonrpc::Result<Value>> for Reply {
+    fn from(response: &jsonrpc::Result<Value>) -> Self {
+        match response {
+            Ok(result) => Self::Result(result.clone()),
+            Err(error) => Self::Error(error.clone()),
+        }
+    }
+}
+
+/// Read every exchange of a JSONL transcript
+pub fn load(path: &Path) -> Result<Vec<Exchange>> {
+    let file = File::open(path).map_err(|e| {
+        CallGraphError::ConfigError(format!(
+            "Failed to open LSP transcript {}: {e}",
+            path.display()
+        ))
+    })?;
+    let mut exchanges = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let exchange = serde_json::from_str(&line).map_err(|e| {
+            CallGraphError::ConfigError(format!(
+                "Invalid LSP transcript {} line {}: {e}",
+                path.display(),
+                number + 1
+            ))
+        })?;
+        exchanges.push(exchange);
+    }
+    Ok(exchanges)
+}
+
+// ============================================================================
+// Recording
+// ============================================================================
+
+/// JSONL file that exchanges are appended to as they complete
+pub struct Transcript {
+    file: Mutex<File>,
+}
+
+impl Transcript {
+    /// Create or truncate the transcript file
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path).map_err(|e| {
+            CallGraphError::ConfigError(format!(
+                "Failed to create LSP transcript {}: {e}",
+                path.display()
+            ))
+        })?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Append one exchange; failures are logged rather than aborting the run
+    pub fn write(&self, exchange: &Exchange) {
+        let mut line = serde_json::to_string(exchange).expect("exchanges serialize");
+        line.push('\n');
+        if let Err(e) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            warn!("Failed to write LSP transcript: {}", e);
+        }
+    }
+}
+
+/// Records the traffic of one server into a shared transcript
+///
+/// URIs below the workspace root are written relative to a placeholder.
+#[derive(Clone)]
+pub struct Recorder {
+    transcript: Arc<Transcript>,
+    server: Option<String>,
+    workspace: Option<String>,
+}
+
+impl Recorder {
+    pub fn new(transcript: Arc<Transcript>, server: Option<&str>, root: &Path) -> Self {
+        Self {
+            transcript,
+            server: server.map(str::to_string),
+            workspace: workspace_uri(root),
+        }
+    }
+
+    pub fn record(&self, method: &str, mut params: Value, mut response: Option<Reply>) {
+        if let Some(workspace) = &self.workspace {
+            rebase(&mut params, workspace, WORKSPACE_PLACEHOLDER);
+            if let Some(Reply::Result(result)) = &mut response {
+                rebase(result, workspace, WORKSPACE_PLACEHOLDER);
+            }
+        }
+        self.transcript.write(&Exchange {
+            server: self.server.clone(),
+            method: method.to_string(),
+            params,
+            response,
+        });
+    }
+}
+
+/// Root URI of a workspace without its trailing slash
+fn workspace_uri(root: &Path) -> Option<String> {
+    let uri = Url::from_directory_path(root).ok()?;
+    Some(uri.as_str().trim_end_matches('/').to_string())
+}
+
+/// Replace `from` with `to` where it starts a string or object key, up to a
+/// path boundary
+fn rebase(value: &mut Value, from: &str, to: &str) {
+    let rebased = |text: &str| {
+        text.strip_prefix(from)
+            .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+            .map(|rest| format!("{to}{rest}"))
+    };
+    match value {
+        Value::String(text) => {
+            if let Some(moved) = rebased(text) {
+                *text = moved;
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                rebase(item, from, to);
+            }
+        }
+        Value::Object(fields) => {
+            *fields = std::mem::take(fields)
+                .into_iter()
+                .map(|(key, mut field)| {
+                    rebase(&mut field, from, to);
+                    (rebased(&key).unwrap_or(key), field)
+                })
+                .collect();
+        }
+        _ => {}
+    }
+}
+
+// ============================================================================
+// Replay
+// ============================================================================
+
+/// Stands in for a server by answering requests from a transcript
+///
+/// Requests are matched by method and parameters. Identical requests get the
+/// recorded responses in order, the last one repeating once they run out.
+/// Requests that were never recorded fail with an LSP error.
+pub struct ReplayTransport {
+    server: Option<String>,
+    replies: HashMap<(String, String), VecDeque<Reply>>,
+}
+
+impl ReplayTransport {
+    /// One transport per server found in a transcript, in order of appearance
+    ///
+    /// URIs recorded below the workspace root are moved below `root`.
+    pub fn from_transcript(exchanges: Vec<Exchange>, root: &Path) -> Vec<Self> {
+        let workspace = workspace_uri(root);
+        let mut transports: Vec<Self> = Vec::new();
+        for mut exchange in exchanges {
+            if let Some(workspace) = &workspace {
+                rebase(&mut exchange.params, WORKSPACE_PLACEHOLDER, workspace);
+                if let Some(Reply::Result(result)) = &mut exchange.response {
+                    rebase(result, WORKSPACE_PLACEHOLDER, workspace);
+                }
+            }
+            let index = match transports
+                .iter()
+                .position(|transport| transport.server == exchange.server)
+            {
+                Some(index) => index,
+                None => {
+                    transports.push(Self {
+                        server: exchange.server.clone(),
+                        replies: HashMap::new(),
+                    });
+                    transports.len() - 1
+                }
+            };
+            if let Some(reply) = exchange.response {
+                transports[index]
+                    .replies
+                    .entry(replay_key(&exchange.method, &exchange.params))
+                    .or_default()
+                    .push_back(reply);
+            }
+        }
+        transports
+    }
+
+    /// Language of the recorded server, or `None` for the catch-all server
+    pub fn server(&self) -> Option<&str> {
+        self.server.as_deref()
+    }
+}
+
+impl fmt::Display for ReplayTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "replay of the {} server",
+            self.server.as_deref().unwrap_or("catch-all")
+        )
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn connect(&self) -> BoxFuture<'_, Result<Channel>> {
+        let (client_side, server_side) = tokio::io::duplex(64 * 1024);
+        let (client_reader, client_writer) = tokio::io::split(client_side);
+        tokio::spawn(serve_replies(server_side, self.replies.clone()));
+        Box::pin(async move { Ok(Channel::from_streams(client_reader, client_writer)) })
+    }
+}
+
+fn replay_key(method: &str, params: &Value) -> (String, String) {
+    let params = if MATCHED_BY_METHOD.contains(&method) {
+        String::new()
+    } else {
+        params.to_string()
+    };
+    (method.to_string(), params)
+}
+
+/// Answer requests arriving on `stream` until the client disconnects
+async fn serve_replies(
+    stream: DuplexStream,
+    mut replies: HashMap<(String, String), VecDeque<Reply>>,
+) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = tokio::io::BufReader::new(reader);
+    while let Ok(Some(message)) = protocol::read_message(&mut reader).await {
+        let Message::Request(request) = message else {
+            continue;
+        };
+        let (method, id, params) = request.into_parts();
+        let Some(id) = id else {
+            continue;
+        };
+        let params = params.unwrap_or(Value::Null);
+        let body = match replies.get_mut(&replay_key(&method, &params)) {
+            Some(queue) if queue.len() > 1 => queue.pop_front().unwrap(),
+            Some(queue) => queue[0].clone(),
+            None if method == "shutdown" => Reply::Result(Value::Null),
+            None => {
+                debug!("No recorded response for {} {}", method, params);
+                let mut error = jsonrpc::Error::internal_error();
+                error.message = format!("No recorded response for {method}").into();
+                Reply::Error(error)
+            }
+        };
+        let body = match body {
+            Reply::Result(result) => Ok(result),
+            Reply::Error(error) => Err(error),
+        };
+        if protocol::write_message(&mut writer, &Response::from_parts(id, body))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp::LspConnection;
+    use serde_json::json;
+
+    fn exchange(
+        server: Option<&str>,
+        method: &str,
+        params: Value,
+        reply: Option<Reply>,
+    ) -> Exchange {
+        Exchange {
+            server: server.map(str::to_string),
+            method: method.to_string(),
+            params,
+            response: reply,
+        }
+    }
+
+    #[test]
+    fn test_transcript_round_trips_through_jsonl() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("session.jsonl");
+        let exchanges = vec![
+            exchange(
+                Some("rust"),
+                "initialize",
+                json!({"processId": 1}),
+                Some(Reply::Result(json!({"capabilities": {}}))),
+            ),
+            exchange(
+                Some("rust"),
+                "textDocument/didOpen",
+                json!({"uri": "file:///a.rs"}),
+                None,
+            ),
+            exchange(
+                None,
+                "textDocument/prepareCallHierarchy",
+                json!({}),
+                Some(Reply::Result(Value::Null)),
+            ),
+            exchange(
+                None,
+                "callHierarchy/outgoingCalls",
+                json!({}),
+                Some(Reply::Error(jsonrpc::Error::method_not_found())),
+            ),
+        ];
+
+        let transcript = Transcript::create(&path).unwrap();
+        for exchange in &exchanges {
+            transcript.write(exchange);
+        }
+        assert_eq!(load(&path).unwrap(), exchanges);
+    }
+
+    #[tokio::test]
+    async fn test_replay_answers_recorded_requests() {
+        let exchanges = vec![
+            exchange(
+                Some("rust"),
+                "initialize",
+                json!({"processId": 1}),
+                Some(Reply::Result(json!({"capabilities": {}}))),
+            ),
+            exchange(
+                Some("rust"),
+                "test/count",
+                json!({"n": 1}),
+                Some(Reply::Result(json!("first"))),
+            ),
+            exchange(
+                Some("rust"),
+                "test/count",
+                json!({"n": 1}),
+                Some(Reply::Result(json!("second"))),
+            ),
+            exchange(
+                Some("python"),
+                "test/count",
+                json!({"n": 1}),
+                Some(Reply::Result(json!("python"))),
+            ),
+        ];
+        let transports = ReplayTransport::from_transcript(exchanges, Path::new("/project"));
+        assert_eq!(transports.len(), 2);
+        assert_eq!(transports[0].server(), Some("rust"));
+
+        let connection = LspConnection::connect(&transports[0]).await.unwrap();
+        let ask = |params: Value| connection.send_request("test/count", params);
+        // Parameters that differ between runs are ignored for initialize
+        assert_eq!(
+            connection
+                .send_request("initialize", json!({"processId": 2}))
+                .await
+                .unwrap(),
+            json!({"capabilities": {}})
+        );
+        assert_eq!(ask(json!({"n": 1})).await.unwrap(), json!("first"));
+        assert_eq!(ask(json!({"n": 1})).await.unwrap(), json!("second"));
+        assert_eq!(ask(json!({"n": 1})).await.unwrap(), json!("second"));
+
+        let error = ask(json!({"n": 2})).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("No recorded response for test/count"));
+    }
+
+    #[tokio::test]
+    async fn test_replay_moves_workspace_uris_to_the_new_root() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("session.jsonl");
+        let transcript = Arc::new(Transcript::create(&path).unwrap());
+        let recorder = Recorder::new(transcript, None, Path::new("/home/a/proj"));
+        recorder.record(
+            "test/definition",
+            json!({"uri": "file:///home/a/proj/src/main.rs", "other": "file:///home/a/project"}),
+            Some(Reply::Result(json!({
+                "changes": {"file:///home/a/proj/lib.rs": []},
+                "uri": "file:///home/a/proj"
+            }))),
+        );
+
+        let exchanges = load(&path).unwrap();
+        assert_eq!(exchanges[0].params["uri"], json!("$WORKSPACE/src/main.rs"));
+        assert_eq!(
+            exchanges[0].params["other"],
+            json!("file:///home/a/project")
+        );
+
+        let transports = ReplayTransport::from_transcript(exchanges, Path::new("/tmp/checkout"));
+        let connection = LspConnection::connect(&transports[0]).await.unwrap();
+        let params =
+            json!({"uri": "file:///tmp/checkout/src/main.rs", "other": "file:///home/a/project"});
+        assert_eq!(
+            connection
+                .send_request("test/definition", params)
+                .await
+                .unwrap(),
+            json!({"changes": {"file:///tmp/checkout/lib.rs": []}, "uri": "file:///tmp/checkout"})
+        );
+    }
+}
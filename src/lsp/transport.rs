@@ -0,0 +1,455 @@
+//! Byte streams a language server can be reached over
+
+use crate::config::ServerOptions;
+use crate::{CallGraphError, Result};
+use futures::future::BoxFuture;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::process::{Child, Command};
+use tracing::{info, warn};
+
+// ============================================================================
+// Transport
+// ============================================================================
+
+/// A way of reaching a language server
+///
+/// `connect` may be called more than once, for example to reach a server
+/// again after it exited.
+pub trait Transport: fmt::Display + Send + Sync {
+    /// Open a fresh pair of streams to the server
+    fn connect(&self) -> BoxFuture<'_, Result<Channel>>;
+}
+
+/// Open streams to a language server
+pub struct Channel {
+    pub reader: Box<dyn AsyncRead + Send + Unpin>,
+    pub writer: Box<dyn AsyncWrite + Send + Unpin>,
+    /// Server process started for this channel, reaped on shutdown
+    pub process: Option<Child>,
+    /// Diagnostic output of the server, kept for crash reports
+    pub stderr: Option<Box<dyn AsyncRead + Send + Unpin>>,
+}
+
+impl Channel {
+    /// A channel over an already connected byte stream pair
+    pub fn from_streams<R, W>(reader: R, writer: W) -> Self
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        Self {
+            reader: Box::new(reader),
+            writer: Box::new(writer),
+            process: None,
+            stderr: None,
+        }
+    }
+}
+
+/// Choose the transport for a `--lsp-server` value
+///
+/// `tcp://host:port` and `unix:///path/to.sock` connect to a running server;
+/// anything else is a command line started in `working_dir` with the `args`
+/// and `env` of `options`.
+pub fn parse(
+    server: &str,
+    working_dir: &Path,
+    options: &ServerOptions,
+) -> Result<Box<dyn Transport>> {
+    let is_socket = server.starts_with("tcp://") || server.starts_with("unix://");
+    if is_socket && !(options.args.is_empty() && options.env.is_empty()) {
+        warn!(
+            "Ignoring args and env for {}: the server is already running",
+            server
+        );
+    }
+
+    if let Some(address) = server.strip_prefix("tcp://") {
+        if address.is_empty() {
+            return Err(invalid_address(server));
+        }
+        return Ok(Box::new(TcpTransport::new(address.trim_end_matches('/'))));
+    }
+    if let Some(path) = server.strip_prefix("unix://") {
+        if path.is_empty() {
+            return Err(invalid_address(server));
+        }
+        return unix_transport(Path::new(path));
+    }
+    Ok(Box::new(
+        StdioTransport::new(server, working_dir)
+            .args(&options.args)
+            .envs(&options.env),
+    ))
+}
+
+fn invalid_address(server: &str) -> Box<dyn std::error::Error + Send + Sync> {
+    CallGraphError::ConfigError(format!("Invalid LSP server address '{server}'")).into()
+}
+
+#[cfg(unix)]
+fn unix_transport(path: &Path) -> Result<Box<dyn Transport>> {
+    Ok(Box::new(UnixTransport::new(path)))
+}
+
+#[cfg(not(unix))]
+fn unix_transport(path: &Path) -> Result<Box<dyn Transport>> {
+    Err(CallGraphError::ConfigError(format!(
+        "Unix sockets are not supported on this platform: {}",
+        path.display()
+    ))
+    .into())
+}
+
+// ============================================================================
+// Stdio
+// ============================================================================
+
+/// A server process spoken to over its stdin and stdout
+pub struct StdioTransport {
+    command_line: String,
+    working_dir: PathBuf,
+    /// Passed after the arguments of the command line, without splitting
+    args: Vec<String>,
+    env: BTreeMap<String, String>,
+}
+
+impl StdioTransport {
+    /// The command line is split on whitespace into program and arguments
+    pub fn new(command_line: &str, working_dir: &Path) -> Self {
+        Self {
+            command_line: command_line.to_string(),
+            working_dir: working_dir.to_path_buf(),
+            args: Vec::new(),
+            env: BTreeMap::new(),
+        }
+    }
+
+    /// Append arguments, which may contain whitespace
+    pub fn args(mut self, args: &[String]) -> Self {
+        self.args.extend_from_slice(args);
+        self
+    }
+
+    /// Set environment variables for the server process
+    pub fn envs(mut self, env: &BTreeMap<String, String>) -> Self {
+        self.env
+            .extend(env.iter().map(|(key, value)| (key.clone(), value.clone())));
+        self
+    }
+}
+
+impl fmt::Display for StdioTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.command_line)?;
+        for arg in &self.args {
+            write!(f, " {arg}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Transport for StdioTransport {
+    fn connect(&self) -> BoxFuture<'_, Result<Channel>> {
+        Box::pin(async move {
+            let mut parts = self.command_line.split_whitespace();
+            let program = parts.next().ok_or_else(|| {
+                CallGraphError::ConfigError("LSP server command is empty".to_string())
+            })?;
+
+            info!("Starting language server: {}", self);
+            let mut child = Command::new(program)
+                .args(parts)
+                .args(&self.args)
+                .envs(&self.env)
+                .current_dir(&self.working_dir)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| {
+                    CallGraphError::ConfigError(format!(
+                        "Failed to start LSP server '{program}': {e}"
+                    ))
+                })?;
+
+            let stdin = child.stdin.take().expect("child stdin is piped");
+            let stdout = child.stdout.take().expect("child stdout is piped");
+            let stderr = child.stderr.take().expect("child stderr is piped");
+            Ok(Channel {
+                reader: Box::new(stdout),
+                writer: Box::new(stdin),
+                process: Some(child),
+                stderr: Some(Box::new(stderr)),
+            })
+        })
+    }
+}
+
+// ============================================================================
+// Sockets
+// ============================================================================
+
+/// A running server listening on a TCP port
+pub struct TcpTransport {
+    address: String,
+}
+
+impl TcpTransport {
+    /// Connect to `host:port`
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for TcpTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "tcp://{}", self.address)
+    }
+}
+
+impl Transport for TcpTransport {
+    fn connect(&self) -> BoxFuture<'_, Result<Channel>> {
+        Box::pin(async move {
+            info!("Connecting to language server at {}", self);
+            let stream = tokio::net::TcpStream::connect(&self.address)
+                .await
+                .map_err(|e| connect_failed(self, e))?;
+            let (reader, writer) = stream.into_split();
+            Ok(Channel::from_streams(reader, writer))
+        })
+    }
+}
+
+/// A running server listening on a Unix domain socket
+#[cfg(unix)]
+pub struct UnixTransport {
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixTransport {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl fmt::Display for UnixTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unix://{}", self.path.display())
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixTransport {
+    fn connect(&self) -> BoxFuture<'_, Result<Channel>> {
+        Box::pin(async move {
+            info!("Connecting to language server at {}", self);
+            let stream = tokio::net::UnixStream::connect(&self.path)
+                .await
+                .map_err(|e| connect_failed(self, e))?;
+            let (reader, writer) = stream.into_split();
+            Ok(Channel::from_streams(reader, writer))
+        })
+    }
+}
+
+fn connect_failed(
+    transport: &dyn Transport,
+    error: std::io::Error,
+) -> Box<dyn std::error::Error + Send + Sync> {
+    CallGraphError::ConfigError(format!(
+        "Failed to connect to LSP server at {transport}: {error}"
+    ))
+    .into()
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp::protocol::{self, Message};
+    use crate::lsp::LspConnection;
+    use serde_json::{json, Value};
+    use tokio::io::BufReader;
+    use tower_lsp::jsonrpc::Response;
+
+    /// Answer one request on a socket with its method name
+    async fn echo_one<S>(stream: S)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut reader = BufReader::new(reader);
+        let Some(Message::Request(request)) = protocol::read_message(&mut reader).await.unwrap()
+        else {
+            panic!("expected a request");
+        };
+        let id = request.id().cloned().unwrap();
+        let response = Response::from_ok(id, json!(request.method()));
+        protocol::write_message(&mut writer, &response)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_parse_selects_transport_by_scheme() {
+        let dir = Path::new("/project");
+        let options = ServerOptions::default();
+        assert_eq!(
+            parse("tcp://127.0.0.1:9257", dir, &options)
+                .unwrap()
+                .to_string(),
+            "tcp://127.0.0.1:9257"
+        );
+        assert_eq!(
+            parse("pyright-langserver --stdio", dir, &options)
+                .unwrap()
+                .to_string(),
+            "pyright-langserver --stdio"
+        );
+        assert!(parse("tcp://", dir, &options).is_err());
+        #[cfg(unix)]
+        assert_eq!(
+            parse("unix:///run/ra.sock", dir, &options)
+                .unwrap()
+                .to_string(),
+            "unix:///run/ra.sock"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stdio_transport_passes_args_and_env() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let server = temp_dir.path().join("server");
+        std::fs::write(&server, "#!/bin/sh\necho \"$GREETING|$1|$2\" >&2\nexit 1\n").unwrap();
+        std::fs::set_permissions(&server, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let options = ServerOptions {
+            args: vec!["two words".to_string()],
+            env: BTreeMap::from([("GREETING".to_string(), "hello".to_string())]),
+            ..Default::default()
+        };
+        let command = format!("{} --first", server.display());
+        let transport = parse(&command, temp_dir.path(), &options).unwrap();
+        assert_eq!(transport.to_string(), format!("{command} two words"));
+
+        // The script reports what it was started with through stderr
+        let connection = LspConnection::connect(transport.as_ref()).await.unwrap();
+        let error = connection
+            .send_request("test/args", Value::Null)
+            .await
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("hello|--first|two words"),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tcp_transport_reaches_listening_server() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            echo_one(stream).await;
+        });
+
+        let transport = parse(
+            &format!("tcp://{address}"),
+            Path::new("."),
+            &ServerOptions::default(),
+        )
+        .unwrap();
+        let connection = LspConnection::connect(transport.as_ref()).await.unwrap();
+        let result = connection
+            .send_request("test/tcp", Value::Null)
+            .await
+            .unwrap();
+        assert_eq!(result, json!("test/tcp"));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_leaves_a_socket_server_running() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut methods = Vec::new();
+            while let Some(message) = protocol::read_message(&mut reader).await.unwrap() {
+                if let Message::Request(request) = message {
+                    methods.push(request.method().to_string());
+                }
+            }
+            methods
+        });
+
+        let transport = TcpTransport::new(&address.to_string());
+        let mut connection = LspConnection::connect(&transport).await.unwrap();
+        connection.shutdown().await.unwrap();
+
+        // The stream is closed without `shutdown` or `exit`
+        assert!(server.await.unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_transport_reaches_listening_server() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let socket = temp_dir.path().join("server.sock");
+        let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            echo_one(stream).await;
+        });
+
+        let transport = parse(
+            &format!("unix://{}", socket.display()),
+            Path::new("."),
+            &ServerOptions::default(),
+        )
+        .unwrap();
+        let connection = LspConnection::connect(transport.as_ref()).await.unwrap();
+        let result = connection
+            .send_request("test/unix", Value::Null)
+            .await
+            .unwrap();
+        assert_eq!(result, json!("test/unix"));
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_server_is_a_config_error() {
+        // Grab a free port and release it again so nothing listens there
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+
+        let error = TcpTransport::new(&address.to_string())
+            .connect()
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(CallGraphError::ConfigError(_))
+        ));
+        assert!(error.to_string().contains(&address.to_string()));
+    }
+}
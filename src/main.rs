@@ -1,18 +1,26 @@
-use call_graph_generator::{CallGraph, Config, DotGenerator, LspClient, Result};
-use tracing::{error, info};
+use call_graph_generator::{CallGraph, Config, DotGenerator, Index, LspClient, Result};
+use tracing::{debug, error, info, warn};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
-
     // Parse command line arguments
     let args = Config::parse();
 
+    // Initialize tracing; progress is shown by default, details with --verbose
+    let default_filter = if args.verbose {
+        "debug"
+    } else {
+        "warn,call_graph_generator=info"
+    };
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(default_filter));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+
     info!("Starting CallGraphGenerator");
-    info!("Configuration: {:?}", args);
+    debug!("Configuration: {:?}", args);
 
     // Run the main analysis
     match run_analysis(args).await {
@@ -29,17 +37,19 @@ async fn main() -> Result<()> {
 
 /// Main analysis workflow
 async fn run_analysis(config: Config) -> Result<()> {
-    // Step 1: Initialize LSP client
-    info!("Initializing LSP client");
-    let mut lsp_client = LspClient::new(&config).await?;
-
-    // Step 2: Perform analysis
-    info!("Performing call graph analysis");
-    let call_graph = CallGraph::analyze(&mut lsp_client, &config).await?;
+    // Steps 1 and 2: Build the call graph from an index or a language server
+    let call_graph = match &config.index {
+        Some(path) => {
+            info!("Building call graph from index {}", path.display());
+            let mut index = Index::load(path, &config.workspace_root()?)?;
+            CallGraph::analyze(&mut index, &config).await?
+        }
+        None => analyze_with_server(&config).await?,
+    };
 
     // Step 3: Generate output
     info!("Generating output");
-    let dot_generator = DotGenerator::new(&config);
+    let dot_generator = DotGenerator::new(&config)?;
     dot_generator.generate(&call_graph, &config.output_path)?;
 
     info!(
@@ -49,3 +59,22 @@ async fn run_analysis(config: Config) -> Result<()> {
 
     Ok(())
 }
+
+/// Query a language server for the call graph
+async fn analyze_with_server(config: &Config) -> Result<CallGraph> {
+    // Step 1: Initialize LSP client
+    info!("Initializing LSP client");
+    let mut lsp_client = LspClient::new(config).await?;
+
+    // Step 2: Perform analysis
+    info!("Performing call graph analysis");
+    let analysis = CallGraph::analyze(&mut lsp_client, config).await;
+
+    // Always let the server exit cleanly, even when the analysis failed; a
+    // server that does not should not cost the graph or hide the real error
+    info!("Shutting down LSP client");
+    if let Err(e) = lsp_client.shutdown().await {
+        warn!("Language server did not shut down cleanly: {}", e);
+    }
+    analysis
+}
@@ -1,8 +1,10 @@
 //! Output generation module
 
-use crate::analyzer::CallGraph;
+use crate::analyzer::{CallGraph, FunctionNode, NodeIndex};
 use crate::config::Config;
+use crate::filter::ExcludeFilter;
 use crate::Result;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
 
 // ============================================================================
@@ -11,38 +13,245 @@ use std::fs;
 
 /// DOT format generator for call graphs
 pub struct DotGenerator {
-    // TODO: Add configuration fields
+    show_types: bool,
+    show_parameters: bool,
+    cluster_by_module: bool,
+    exclude: ExcludeFilter,
 }
 
 impl DotGenerator {
     /// Create a new DOT generator
-    pub fn new(_config: &Config) -> Self {
-        Self {}
+    ///
+    /// Fails if one of the exclude patterns is not a valid glob.
+    pub fn new(config: &Config) -> Result<Self> {
+        Ok(Self {
+            show_types: config.output.show_types,
+            show_parameters: config.output.show_parameters,
+            cluster_by_module: config.output.cluster_by_module,
+            exclude: ExcludeFilter::new(&config.exclude_patterns)?,
+        })
     }
 
     /// Generate DOT file from call graph
-    pub fn generate(&self, _call_graph: &CallGraph, output_path: &str) -> Result<()> {
-        // TODO: Implement actual DOT generation
-        let dot_content = r#"digraph CallGraph {
-    rankdir=TB;
-    
-    // Placeholder call graph
-    "main::main" [label="main", shape=box, style=filled, fillcolor=lightblue];
-    
-    // TODO: Generate actual nodes and edges from call graph
-}
-"#;
-
-        fs::write(output_path, dot_content)?;
+    pub fn generate(&self, call_graph: &CallGraph, output_path: &str) -> Result<()> {
+        fs::write(output_path, self.render(call_graph))?;
         Ok(())
     }
+
+    /// Render the call graph as DOT source
+    pub fn render(&self, call_graph: &CallGraph) -> String {
+        let ids = node_ids(call_graph);
+        // Mixed-language graphs colour each function by its language
+        let languages: BTreeSet<_> = call_graph
+            .nodes()
+            .filter_map(|(_, node)| node.language())
+            .collect();
+        let coloured = languages.len() > 1;
+        let mut dot = String::from("digraph CallGraph {\n    rankdir=TB;\n");
+
+        // Group nodes by module; nodes without one stay at the top level
+        let mut clusters: BTreeMap<&str, Vec<NodeIndex>> = BTreeMap::new();
+        let mut loose = Vec::new();
+        for (id, node) in call_graph.nodes() {
+            if !self.is_visible(call_graph, id) {
+                continue;
+            }
+            match node.container.as_deref() {
+                Some(module) if self.cluster_by_module => {
+                    clusters.entry(module).or_default().push(id)
+                }
+                _ => loose.push(id),
+            }
+        }
+
+        dot.push_str("\n    // Node definitions\n");
+        for id in loose {
+            self.write_node(&mut dot, call_graph, &ids, id, "    ", coloured);
+        }
+        for (module, members) in clusters {
+            dot.push_str(&format!(
+                "    subgraph \"cluster_{}\" {{\n        label=\"{}\";\n        style=rounded;\n",
+                escape(module),
+                escape(module)
+            ));
+            for id in members {
+                self.write_node(&mut dot, call_graph, &ids, id, "        ", coloured);
+            }
+            dot.push_str("    }\n");
+        }
+
+        dot.push_str("\n    // Edge definitions\n");
+        for (caller, callee, edge) in call_graph.edges() {
+            if !self.is_visible(call_graph, caller) || !self.is_visible(call_graph, callee) {
+                continue;
+            }
+            dot.push_str(&format!("    \"{}\" -> \"{}\"", ids[&caller], ids[&callee]));
+            if edge.count() > 1 {
+                dot.push_str(&format!(" [label=\"{}\"]", edge.count()));
+            }
+            dot.push_str(";\n");
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Roots are always drawn; other functions are subject to the exclude patterns
+    fn is_visible(&self, call_graph: &CallGraph, id: NodeIndex) -> bool {
+        call_graph.is_root(id)
+            || !self
+                .exclude
+                .is_excluded(&call_graph.graph()[id], call_graph.workspace_root())
+    }
+
+    fn write_node(
+        &self,
+        dot: &mut String,
+        call_graph: &CallGraph,
+        ids: &HashMap<NodeIndex, String>,
+        id: NodeIndex,
+        indent: &str,
+        coloured: bool,
+    ) {
+        let node = &call_graph.graph()[id];
+        let fill = node.language().filter(|_| coloured).map(language_color);
+        let style = match (call_graph.is_root(id), fill) {
+            (true, Some(color)) => format!("shape=box, style=\"filled,bold\", fillcolor={color}"),
+            (true, None) => "shape=box, style=filled, fillcolor=lightblue".to_string(),
+            (false, _) if node.external => "shape=component, style=dashed".to_string(),
+            (false, Some(color)) => format!("shape=ellipse, style=filled, fillcolor={color}"),
+            (false, None) => "shape=ellipse".to_string(),
+        };
+        dot.push_str(&format!(
+            "{}\"{}\" [label=\"{}\", {}];\n",
+            indent,
+            ids[&id],
+            escape(&self.label(node)),
+            style
+        ));
+    }
+
+    /// Node label: the function name, optionally followed by parameters and return type
+    fn label(&self, node: &FunctionNode) -> String {
+        let mut label = node.name.clone();
+        if node.external {
+            return label;
+        }
+        let (parameters, return_type) = node
+            .detail
+            .as_deref()
+            .map(split_signature)
+            .unwrap_or_default();
+
+        if self.show_parameters {
+            label.push_str(parameters.unwrap_or("()"));
+        }
+        if self.show_types {
+            if let Some(return_type) = return_type {
+                label.push_str(" -> ");
+                label.push_str(return_type);
+            }
+        }
+        label
+    }
 }
 
 // ============================================================================
 // Output Formatters
 // ============================================================================
 
-// TODO: Implement output formatters
+/// Stable, escaped DOT identifiers for every node
+///
+/// Ids are qualified names; functions sharing one are told apart by file and line.
+fn node_ids(call_graph: &CallGraph) -> HashMap<NodeIndex, String> {
+    let mut name_counts: HashMap<String, usize> = HashMap::new();
+    for (_, node) in call_graph.nodes() {
+        *name_counts.entry(node.qualified_name()).or_default() += 1;
+    }
+
+    call_graph
+        .nodes()
+        .map(|(id, node)| {
+            let name = node.qualified_name();
+            let unique = if name_counts[&name] > 1 {
+                let file = node
+                    .uri
+                    .path_segments()
+                    .and_then(|mut segments| segments.next_back())
+                    .unwrap_or_default();
+                format!("{}@{}:{}", name, file, node.selection_range.start.line + 1)
+            } else {
+                name
+            };
+            (id, escape(&unique))
+        })
+        .collect()
+}
+
+/// Fill colour for the nodes of a language in mixed-language graphs
+fn language_color(language: &str) -> &'static str {
+    match language {
+        "rust" => "sandybrown",
+        "typescript" => "lightskyblue",
+        "python" => "khaki",
+        "go" => "paleturquoise",
+        "c" => "thistle",
+        "java" => "peachpuff",
+        _ => "lightgray",
+    }
+}
+
+/// Escape text for use inside a double-quoted DOT string
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Split a signature such as `fn f(a: i32) -> u32` into `(a: i32)` and `u32`
+///
+/// Handles both `-> T` and `: T` return type notations.
+fn split_signature(detail: &str) -> (Option<&str>, Option<&str>) {
+    let Some(open) = detail.find('(') else {
+        return (None, None);
+    };
+
+    let mut depth = 0;
+    let mut close = None;
+    for (offset, c) in detail[open..].char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(open + offset);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let Some(close) = close else {
+        return (None, None);
+    };
+
+    let rest = detail[close + 1..].trim();
+    let return_type = rest
+        .strip_prefix("->")
+        .or_else(|| rest.strip_prefix(':'))
+        .map(|ty| ty.split(" where ").next().unwrap_or(ty).trim())
+        .filter(|ty| !ty.is_empty());
+
+    (Some(&detail[open..=close]), return_type)
+}
 
 // ============================================================================
 // Tests
@@ -51,7 +260,23 @@ impl DotGenerator {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::OutputConfig;
     use tempfile::NamedTempFile;
+    use tower_lsp::lsp_types::{CallHierarchyItem, Position, Range, SymbolKind, Url};
+
+    fn test_item(name: &str, line: u32) -> CallHierarchyItem {
+        let range = Range::new(Position::new(line, 3), Position::new(line, 10));
+        CallHierarchyItem {
+            name: name.to_string(),
+            kind: SymbolKind::FUNCTION,
+            tags: None,
+            detail: None,
+            uri: Url::parse("file:///project/src/main.rs").unwrap(),
+            range,
+            selection_range: range,
+            data: None,
+        }
+    }
     #[test]
     fn test_dot_generator_creation() {
         let config = Config {
@@ -60,17 +285,20 @@ mod tests {
             output_path: "test.dot".to_string(),
             root_function: None,
             max_depth: None,
+            direction: crate::config::CallDirection::Callees,
             exclude_patterns: vec![],
             lsp_server_command: None,
+            record_lsp: None,
+            replay_lsp: None,
+            index: None,
             verbose: false,
             analysis: crate::config::AnalysisConfig::default(),
             output: crate::config::OutputConfig::default(),
             lsp: crate::config::LspConfig::default(),
         };
 
-        let _generator = DotGenerator::new(&config);
+        let _generator = DotGenerator::new(&config).unwrap();
         // Just verify it can be created without panicking
-        assert!(true);
     }
 
     #[test]
@@ -84,16 +312,24 @@ mod tests {
             output_path: output_path.to_string(),
             root_function: None,
             max_depth: None,
+            direction: crate::config::CallDirection::Callees,
             exclude_patterns: vec![],
             lsp_server_command: None,
+            record_lsp: None,
+            replay_lsp: None,
+            index: None,
             verbose: false,
             analysis: crate::config::AnalysisConfig::default(),
             output: crate::config::OutputConfig::default(),
             lsp: crate::config::LspConfig::default(),
         };
 
-        let generator = DotGenerator::new(&config);
-        let call_graph = CallGraph {};
+        let generator = DotGenerator::new(&config).unwrap();
+        let mut call_graph = CallGraph::new();
+        let main = call_graph.add_item(test_item("main", 0));
+        call_graph.mark_root(main);
+        let helper = call_graph.add_item(test_item("helper", 4));
+        call_graph.add_call(main, helper, vec![]);
 
         let result = generator.generate(&call_graph, output_path);
         assert!(result.is_ok());
@@ -102,7 +338,172 @@ mod tests {
         let content = std::fs::read_to_string(output_path).unwrap();
         assert!(content.contains("digraph CallGraph"));
         assert!(content.contains("rankdir=TB"));
-        assert!(content.contains("main::main"));
+        assert!(content.contains("\"main::main\" [label=\"main\", shape=box"));
+        assert!(content.contains("\"main::main\" -> \"main::helper\";"));
+    }
+
+    fn generator(output: OutputConfig) -> DotGenerator {
+        DotGenerator {
+            show_types: output.show_types,
+            show_parameters: output.show_parameters,
+            cluster_by_module: output.cluster_by_module,
+            exclude: ExcludeFilter::default(),
+        }
+    }
+
+    fn flat_output() -> OutputConfig {
+        OutputConfig {
+            cluster_by_module: false,
+            ..OutputConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_render_escapes_names() {
+        let mut call_graph = CallGraph::new();
+        call_graph.add_item(test_item("say \"hi\"\\now", 0));
+
+        let dot = generator(flat_output()).render(&call_graph);
+        assert!(dot.contains(r#""main::say \"hi\"\\now" [label="say \"hi\"\\now""#));
+    }
+
+    #[test]
+    fn test_render_disambiguates_duplicate_names() {
+        let mut call_graph = CallGraph::new();
+        call_graph.add_item(test_item("new", 3));
+        call_graph.add_item(test_item("new", 20));
+
+        let dot = generator(flat_output()).render(&call_graph);
+        assert!(dot.contains("\"main::new@main.rs:4\""));
+        assert!(dot.contains("\"main::new@main.rs:21\""));
+    }
+
+    #[test]
+    fn test_render_clusters_by_module() {
+        let mut call_graph = CallGraph::new();
+        let main = call_graph.add_item(test_item("main", 0));
+        let mut other = test_item("helper", 0);
+        other.uri = Url::parse("file:///project/src/utils.rs").unwrap();
+        let helper = call_graph.add_item(other);
+        call_graph.add_call(main, helper, vec![]);
+
+        let dot = generator(OutputConfig::default()).render(&call_graph);
+        assert!(dot.contains("subgraph \"cluster_main\" {"));
+        assert!(dot.contains("subgraph \"cluster_utils\" {"));
+        assert!(dot.contains("\"main::main\" -> \"utils::helper\";"));
+
+        let dot = generator(flat_output()).render(&call_graph);
+        assert!(!dot.contains("subgraph"));
+    }
+
+    #[test]
+    fn test_render_signature_options() {
+        let mut call_graph = CallGraph::new();
+        let mut item = test_item("parse", 0);
+        item.detail = Some("pub fn parse(input: &str, strict: bool) -> Result<Ast>".to_string());
+        call_graph.add_item(item);
+
+        let label = |show_types, show_parameters| {
+            let output = OutputConfig {
+                show_types,
+                show_parameters,
+                cluster_by_module: false,
+                ..OutputConfig::default()
+            };
+            generator(output).render(&call_graph)
+        };
+
+        assert!(label(false, false).contains("[label=\"parse\","));
+        assert!(label(true, false).contains("[label=\"parse -> Result<Ast>\","));
+        assert!(label(false, true).contains("[label=\"parse(input: &str, strict: bool)\","));
+        assert!(label(true, true)
+            .contains("[label=\"parse(input: &str, strict: bool) -> Result<Ast>\","));
+    }
+
+    #[test]
+    fn test_render_marks_repeated_calls() {
+        let mut call_graph = CallGraph::new();
+        let main = call_graph.add_item(test_item("main", 0));
+        let helper = call_graph.add_item(test_item("helper", 4));
+        let site = |line| Range::new(Position::new(line, 4), Position::new(line, 10));
+        call_graph.add_call(main, helper, vec![site(1), site(2)]);
+
+        let dot = generator(flat_output()).render(&call_graph);
+        assert!(dot.contains("\"main::main\" -> \"main::helper\" [label=\"2\"];"));
+    }
+
+    #[test]
+    fn test_render_hides_excluded_functions() {
+        let mut call_graph = CallGraph::new();
+        let main = call_graph.add_item(test_item("main", 0));
+        let check = call_graph.add_item(test_item("test_helper", 4));
+        let helper = call_graph.add_item(test_item("helper", 8));
+        call_graph.mark_root(main);
+        call_graph.add_call(main, check, vec![]);
+        call_graph.add_call(main, helper, vec![]);
+
+        let mut generator = generator(flat_output());
+        generator.exclude = ExcludeFilter::new(&["test_*", "main"]).unwrap();
+        let dot = generator.render(&call_graph);
+        assert!(!dot.contains("test_helper"));
+        assert!(dot.contains("\"main::main\" [label=\"main\", shape=box"));
+        assert!(dot.contains("\"main::main\" -> \"main::helper\";"));
+    }
+
+    #[test]
+    fn test_render_external_packages() {
+        let mut call_graph = CallGraph::new();
+        let main = call_graph.add_item(test_item("main", 0));
+        let uri = Url::parse("file:///registry/src/index/serde-1.0.0/src/de.rs").unwrap();
+        let serde = call_graph.add_function(FunctionNode::external_package(&uri));
+        call_graph.add_call(main, serde, vec![]);
+
+        let output = OutputConfig {
+            show_parameters: true,
+            ..flat_output()
+        };
+        let dot = generator(output).render(&call_graph);
+        assert!(dot.contains("\"serde\" [label=\"serde\", shape=component, style=dashed];"));
+        assert!(dot.contains("\"main::main\" -> \"serde\";"));
+    }
+
+    #[test]
+    fn test_render_colours_mixed_language_graphs() {
+        let mut call_graph = CallGraph::new();
+        let main = call_graph.add_item(test_item("main", 0));
+        call_graph.mark_root(main);
+        call_graph.add_item(test_item("helper", 4));
+
+        // A single language keeps the plain styling
+        let dot = generator(flat_output()).render(&call_graph);
+        assert!(!dot.contains("sandybrown"));
+
+        let mut script = test_item("generate", 0);
+        script.uri = Url::parse("file:///project/tools/gen.py").unwrap();
+        call_graph.add_item(script);
+
+        let dot = generator(flat_output()).render(&call_graph);
+        assert!(dot.contains(
+            "\"main::main\" [label=\"main\", shape=box, style=\"filled,bold\", fillcolor=sandybrown];"
+        ));
+        assert!(dot.contains(
+            "\"main::helper\" [label=\"helper\", shape=ellipse, style=filled, fillcolor=sandybrown];"
+        ));
+        assert!(dot.contains("[label=\"generate\", shape=ellipse, style=filled, fillcolor=khaki];"));
+    }
+
+    #[test]
+    fn test_split_signature() {
+        assert_eq!(
+            split_signature("fn run(cb: fn(u8) -> u8) -> bool"),
+            (Some("(cb: fn(u8) -> u8)"), Some("bool"))
+        );
+        assert_eq!(
+            split_signature("function greet(name: string): void"),
+            (Some("(name: string)"), Some("void"))
+        );
+        assert_eq!(split_signature("def run(self)"), (Some("(self)"), None));
+        assert_eq!(split_signature("struct Foo"), (None, None));
     }
 
     #[test]
@@ -113,16 +514,20 @@ mod tests {
             output_path: "test.dot".to_string(),
             root_function: None,
             max_depth: None,
+            direction: crate::config::CallDirection::Callees,
             exclude_patterns: vec![],
             lsp_server_command: None,
+            record_lsp: None,
+            replay_lsp: None,
+            index: None,
             verbose: false,
             analysis: crate::config::AnalysisConfig::default(),
             output: crate::config::OutputConfig::default(),
             lsp: crate::config::LspConfig::default(),
         };
 
-        let generator = DotGenerator::new(&config);
-        let call_graph = CallGraph {};
+        let generator = DotGenerator::new(&config).unwrap();
+        let call_graph = CallGraph::new();
 
         // Try to write to an invalid path (directory that doesn't exist)
         let result = generator.generate(&call_graph, "/nonexistent/directory/output.dot");
@@ -0,0 +1,182 @@
+//! Sources of functions and calls for the call graph
+//!
+//! The traversal in `analyzer` only asks a `CallGraphSource` for the
+//! functions declared in a file and the calls from or to a function. The
+//! live `LspClient`, which also replays recorded sessions, and a
+//! precomputed `Index` implement it; library users can plug in their own.
+
+use crate::analyzer::module_path;
+use crate::config::Config;
+use crate::{CallGraphError, Result};
+use futures::future::BoxFuture;
+use std::path::Path;
+use tower_lsp::lsp_types::{CallHierarchyItem, Position, Range, SymbolKind, Url};
+
+/// Callers or callees of a function with the call sites inside each caller
+///
+/// Call site columns count Unicode characters.
+pub type Calls = Vec<(CallHierarchyItem, Vec<Range>)>;
+
+/// Answers the questions a call graph traversal asks
+///
+/// Items handed to `outgoing_calls` and `incoming_calls` are ones the source
+/// returned before, rebuilt from the graph with the same uri, ranges and
+/// `data`.
+pub trait CallGraphSource: Send + Sync {
+    /// Function-like symbols declared in a file
+    fn document_symbols<'a>(&'a self, file: &'a Path)
+        -> BoxFuture<'a, Result<Vec<FunctionSymbol>>>;
+
+    /// Items that stand for a declared function in call queries
+    fn prepare_item<'a>(
+        &'a self,
+        function: &'a FunctionSymbol,
+    ) -> BoxFuture<'a, Result<Vec<CallHierarchyItem>>>;
+
+    /// Functions called by `item`
+    fn outgoing_calls<'a>(&'a self, item: &'a CallHierarchyItem) -> BoxFuture<'a, Result<Calls>>;
+
+    /// Functions calling `item`
+    fn incoming_calls<'a>(&'a self, item: &'a CallHierarchyItem) -> BoxFuture<'a, Result<Calls>>;
+
+    /// Functions across the workspace whose name contains `query`
+    ///
+    /// `None` makes the analysis look through the symbols of every file.
+    fn workspace_symbols<'a>(
+        &'a self,
+        _query: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Vec<FunctionSymbol>>>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    /// Position in the source's own units of a character column in `file`
+    fn to_source_position(&self, _file: &Path, position: Position) -> Position {
+        position
+    }
+
+    /// Get ready for an analysis, failing early if it cannot be answered
+    fn prepare<'a>(&'a mut self, _config: &'a Config) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Recover after a query failed with `CallGraphError::ServerExited`
+    fn restart<'a>(&'a mut self, _config: &'a Config) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async {
+            Err(
+                CallGraphError::AnalysisError("Call graph source cannot restart".to_string())
+                    .into(),
+            )
+        })
+    }
+}
+
+/// A function-like symbol declared in a document
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub detail: Option<String>,
+    /// Enclosing symbols such as `Foo` for a method in `impl Foo`
+    pub container: Vec<String>,
+    pub uri: Url,
+    /// Range of the whole declaration
+    pub range: Range,
+    /// Start of the symbol name, used for call hierarchy requests
+    pub position: Position,
+}
+
+impl FunctionSymbol {
+    /// Name without a parameter list some servers append, e.g. `run(int)`
+    pub fn plain_name(&self) -> &str {
+        self.name.split('(').next().unwrap_or(&self.name).trim()
+    }
+
+    /// Item standing in for a call hierarchy item where there is none
+    pub fn to_item(&self) -> CallHierarchyItem {
+        CallHierarchyItem {
+            name: self.plain_name().to_string(),
+            kind: self.kind,
+            tags: None,
+            detail: self.detail.clone(),
+            uri: self.uri.clone(),
+            range: self.range,
+            selection_range: Range::new(self.position, self.position),
+            data: None,
+        }
+    }
+
+    /// Whether `item` was built from this symbol by `to_item`
+    pub fn is(&self, item: &CallHierarchyItem) -> bool {
+        self.uri == item.uri && self.position == item.selection_range.start
+    }
+
+    /// Module path of the file followed by the enclosing symbols
+    pub(crate) fn path_segments(&self, workspace_root: &Path) -> Vec<String> {
+        let mut segments: Vec<String> = module_path(&self.uri, Some(workspace_root))
+            .map(|module| module.split("::").map(str::to_string).collect())
+            .unwrap_or_default();
+        segments.extend(self.container.iter().cloned());
+        segments
+    }
+
+    /// Whether the qualifier is a trailing run of this symbol's path
+    pub(crate) fn matches(&self, qualifier: &[String], name: &str, workspace_root: &Path) -> bool {
+        self.plain_name() == name && self.path_segments(workspace_root).ends_with(qualifier)
+    }
+
+    /// `src/file.rs:LINE  module::Type::name` for error messages
+    pub(crate) fn describe(&self, workspace_root: &Path) -> String {
+        let file = self
+            .uri
+            .to_file_path()
+            .map(|path| {
+                path.strip_prefix(workspace_root)
+                    .unwrap_or(&path)
+                    .display()
+                    .to_string()
+            })
+            .unwrap_or_else(|_| self.uri.to_string());
+        let mut qualified = self.path_segments(workspace_root);
+        qualified.push(self.plain_name().to_string());
+        format!(
+            "{}:{}  {}",
+            file,
+            self.position.line + 1,
+            qualified.join("::")
+        )
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_function_symbol_matches_qualifier_suffix() {
+        let root = Path::new("/project");
+        let function = FunctionSymbol {
+            name: "new".to_string(),
+            kind: SymbolKind::METHOD,
+            detail: None,
+            container: vec!["Parser".to_string()],
+            uri: Url::parse("file:///project/src/parser.rs").unwrap(),
+            range: Range::default(),
+            position: Position::new(11, 7),
+        };
+        let qualifier = |path: &[&str]| path.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert!(function.matches(&[], "new", root));
+        assert!(function.matches(&qualifier(&["Parser"]), "new", root));
+        assert!(function.matches(&qualifier(&["parser", "Parser"]), "new", root));
+        assert!(!function.matches(&qualifier(&["Lexer"]), "new", root));
+        assert!(!function.matches(&[], "parse", root));
+        assert_eq!(
+            function.describe(root),
+            "src/parser.rs:12  parser::Parser::new"
+        );
+    }
+}
@@ -0,0 +1,472 @@
+//! In-process language server serving a declarative fixture call graph
+//!
+//! The server is built on `tower-lsp` and listens on a local TCP port, so the
+//! CLI and the library can reach it with `--lsp-server tcp://...`.
+
+#![allow(dead_code)]
+
+use call_graph_generator::source::Calls;
+use call_graph_generator::{CallGraphSource, FunctionSymbol};
+use futures::future::BoxFuture;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tower_lsp::jsonrpc::Result;
+use tower_lsp::lsp_types::*;
+use tower_lsp::{LanguageServer, LspService, Server};
+
+// ============================================================================
+// Fixture
+// ============================================================================
+
+/// Functions of a project and the calls between them
+#[derive(Debug, Clone, Default)]
+pub struct Fixture {
+    functions: Vec<Function>,
+    calls: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+struct Function {
+    name: String,
+    /// Path relative to the project root
+    file: String,
+    /// Zero-based line of the declaration
+    line: u32,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declare a function on a zero-based line of a project file
+    pub fn function(mut self, name: &str, file: &str, line: u32) -> Self {
+        self.functions.push(Function {
+            name: name.to_string(),
+            file: file.to_string(),
+            line,
+        });
+        self
+    }
+
+    /// Declare that `caller` calls `callee`
+    pub fn call(mut self, caller: &str, callee: &str) -> Self {
+        assert!(self.find(caller).is_some(), "unknown caller {caller}");
+        assert!(self.find(callee).is_some(), "unknown callee {callee}");
+        self.calls.push((caller.to_string(), callee.to_string()));
+        self
+    }
+
+    /// Write every declared file with each function on its line
+    pub fn write_sources(&self, root: &Path) {
+        let mut files: Vec<&str> = self.functions.iter().map(|f| f.file.as_str()).collect();
+        files.sort();
+        files.dedup();
+        for file in files {
+            let mut lines = Vec::new();
+            for function in self.functions.iter().filter(|f| f.file == file) {
+                let line = function.line as usize;
+                if lines.len() <= line {
+                    lines.resize(line + 1, String::new());
+                }
+                lines[line] = format!("fn {}() {{}}", function.name);
+            }
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, lines.join("\n") + "\n").unwrap();
+        }
+    }
+
+    /// Write an LSIF dump of the fixture as an indexer would for `root`
+    ///
+    /// Every function is a tagged definition spanning its line; each call is
+    /// a reference at the start of the caller's line, like the mock server's
+    /// call sites.
+    pub fn write_lsif(&self, root: &Path, dump: &Path) {
+        let root = Url::from_directory_path(root.canonicalize().unwrap()).unwrap();
+        let mut elements = vec![serde_json::json!({
+            "id": 0, "type": "vertex", "label": "metaData", "version": "0.5.0",
+            "projectRoot": root.as_str(), "positionEncoding": "utf-16",
+        })];
+        let mut id = 0;
+        let mut next_id = || {
+            id += 1;
+            id
+        };
+
+        let mut files: Vec<&str> = self.functions.iter().map(|f| f.file.as_str()).collect();
+        files.sort();
+        files.dedup();
+        let mut documents = std::collections::HashMap::new();
+        for file in files {
+            let document = next_id();
+            documents.insert(file, (document, Vec::new()));
+            elements.push(serde_json::json!({
+                "id": document, "type": "vertex", "label": "document",
+                "uri": root.join(file).unwrap().as_str(), "languageId": "rust",
+            }));
+        }
+
+        let range = |line: u32, start: usize, end: usize| {
+            serde_json::json!({
+                "start": {"line": line, "character": start},
+                "end": {"line": line, "character": end},
+            })
+        };
+        let mut results = std::collections::HashMap::new();
+        for function in &self.functions {
+            let (definition, result_set, result) = (next_id(), next_id(), next_id());
+            let name = &function.name;
+            let mut vertex = range(function.line, 3, 3 + name.len());
+            vertex["id"] = definition.into();
+            vertex["type"] = "vertex".into();
+            vertex["label"] = "range".into();
+            vertex["tag"] = serde_json::json!({
+                "type": "definition", "text": name, "kind": 12,
+                "fullRange": range(function.line, 0, name.len() + 8),
+            });
+            elements.push(vertex);
+            elements.push(
+                serde_json::json!({"id": result_set, "type": "vertex", "label": "resultSet"}),
+            );
+            elements.push(
+                serde_json::json!({"id": result, "type": "vertex", "label": "definitionResult"}),
+            );
+            for (label, out, target) in [
+                ("next", definition, result_set),
+                ("textDocument/definition", result_set, result),
+            ] {
+                elements.push(serde_json::json!({
+                    "id": next_id(), "type": "edge", "label": label, "outV": out, "inV": target,
+                }));
+            }
+            elements.push(serde_json::json!({
+                "id": next_id(), "type": "edge", "label": "item", "outV": result, "inVs": [definition],
+            }));
+            documents
+                .get_mut(function.file.as_str())
+                .unwrap()
+                .1
+                .push(definition);
+            results.insert(name.as_str(), result_set);
+        }
+
+        for (caller, callee) in &self.calls {
+            let caller = self.find(caller).unwrap();
+            let reference = next_id();
+            let mut vertex = range(caller.line, 0, 1);
+            vertex["id"] = reference.into();
+            vertex["type"] = "vertex".into();
+            vertex["label"] = "range".into();
+            elements.push(vertex);
+            elements.push(serde_json::json!({
+                "id": next_id(), "type": "edge", "label": "next",
+                "outV": reference, "inV": results[callee.as_str()],
+            }));
+            documents
+                .get_mut(caller.file.as_str())
+                .unwrap()
+                .1
+                .push(reference);
+        }
+
+        for (document, ranges) in documents.into_values() {
+            elements.push(serde_json::json!({
+                "id": next_id(), "type": "edge", "label": "contains",
+                "outV": document, "inVs": ranges,
+            }));
+        }
+
+        let lines: Vec<String> = elements.iter().map(|element| element.to_string()).collect();
+        std::fs::write(dump, lines.join("\n") + "\n").unwrap();
+    }
+
+    fn find(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+}
+
+// ============================================================================
+// Mock Server
+// ============================================================================
+
+/// A running mock server; it stops when dropped
+pub struct MockServer {
+    address: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Serve `fixture` for the project at `root`, accepting any number of clients
+    pub async fn start(fixture: Fixture, root: &Path) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let project = Arc::new(Project::new(fixture, root));
+
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let project = project.clone();
+                tokio::spawn(async move {
+                    let (reader, writer) = stream.into_split();
+                    let (service, socket) = LspService::new(|_| Backend { project });
+                    Server::new(reader, writer, socket).serve(service).await;
+                });
+            }
+        });
+        Self { address, task }
+    }
+
+    /// Value for `--lsp-server`
+    pub fn url(&self) -> String {
+        format!("tcp://{}", self.address)
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// A fixture placed in a project directory
+///
+/// Besides backing the mock server, it is a `CallGraphSource` of its own.
+pub struct Project {
+    fixture: Fixture,
+    root: PathBuf,
+}
+
+impl Project {
+    pub fn new(fixture: Fixture, root: &Path) -> Self {
+        Self {
+            fixture,
+            root: root.canonicalize().unwrap(),
+        }
+    }
+
+    fn uri(&self, function: &Function) -> Url {
+        Url::from_file_path(self.root.join(&function.file)).unwrap()
+    }
+
+    fn item(&self, function: &Function) -> CallHierarchyItem {
+        let range = Range::new(
+            Position::new(function.line, 3),
+            Position::new(function.line, 3 + function.name.len() as u32),
+        );
+        CallHierarchyItem {
+            name: function.name.clone(),
+            kind: SymbolKind::FUNCTION,
+            tags: None,
+            detail: None,
+            uri: self.uri(function),
+            range,
+            selection_range: range,
+            data: None,
+        }
+    }
+
+    #[allow(deprecated)]
+    fn symbol(&self, function: &Function) -> SymbolInformation {
+        let item = self.item(function);
+        SymbolInformation {
+            name: item.name,
+            kind: item.kind,
+            tags: None,
+            deprecated: None,
+            location: Location::new(item.uri, item.range),
+            container_name: None,
+        }
+    }
+
+    fn function_at(&self, uri: &Url, line: u32) -> Option<&Function> {
+        self.fixture
+            .functions
+            .iter()
+            .find(|function| &self.uri(function) == uri && function.line == line)
+    }
+
+    fn function_for(&self, item: &CallHierarchyItem) -> Option<&Function> {
+        self.function_at(&item.uri, item.selection_range.start.line)
+    }
+
+    /// The other end of every call from or to `function`
+    fn calls(&self, function: &Function, outgoing: bool) -> Vec<(CallHierarchyItem, Vec<Range>)> {
+        self.fixture
+            .calls
+            .iter()
+            .filter_map(|(caller, callee)| {
+                let (this, other) = if outgoing {
+                    (caller, callee)
+                } else {
+                    (callee, caller)
+                };
+                (this == &function.name).then(|| self.fixture.find(other).unwrap())
+            })
+            .map(|other| {
+                // The call site is the caller's own line
+                let caller = if outgoing { function } else { other };
+                let site = Range::new(Position::new(caller.line, 0), Position::new(caller.line, 1));
+                (self.item(other), vec![site])
+            })
+            .collect()
+    }
+}
+
+impl CallGraphSource for Project {
+    fn document_symbols<'a>(
+        &'a self,
+        file: &'a Path,
+    ) -> BoxFuture<'a, call_graph_generator::Result<Vec<FunctionSymbol>>> {
+        let uri = Url::from_file_path(file.canonicalize().unwrap()).unwrap();
+        let symbols = self
+            .fixture
+            .functions
+            .iter()
+            .filter(|function| self.uri(function) == uri)
+            .map(|function| {
+                let item = self.item(function);
+                FunctionSymbol {
+                    name: item.name,
+                    kind: item.kind,
+                    detail: None,
+                    container: Vec::new(),
+                    uri: item.uri,
+                    range: item.range,
+                    position: item.selection_range.start,
+                }
+            })
+            .collect();
+        Box::pin(async { Ok(symbols) })
+    }
+
+    fn prepare_item<'a>(
+        &'a self,
+        function: &'a FunctionSymbol,
+    ) -> BoxFuture<'a, call_graph_generator::Result<Vec<CallHierarchyItem>>> {
+        let items = self
+            .function_at(&function.uri, function.position.line)
+            .map(|function| self.item(function))
+            .into_iter()
+            .collect();
+        Box::pin(async { Ok(items) })
+    }
+
+    fn outgoing_calls<'a>(
+        &'a self,
+        item: &'a CallHierarchyItem,
+    ) -> BoxFuture<'a, call_graph_generator::Result<Calls>> {
+        let calls = self
+            .function_for(item)
+            .map(|function| self.calls(function, true))
+            .unwrap_or_default();
+        Box::pin(async { Ok(calls) })
+    }
+
+    fn incoming_calls<'a>(
+        &'a self,
+        item: &'a CallHierarchyItem,
+    ) -> BoxFuture<'a, call_graph_generator::Result<Calls>> {
+        let calls = self
+            .function_for(item)
+            .map(|function| self.calls(function, false))
+            .unwrap_or_default();
+        Box::pin(async { Ok(calls) })
+    }
+}
+
+struct Backend {
+    project: Arc<Project>,
+}
+
+#[tower_lsp::async_trait]
+impl LanguageServer for Backend {
+    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+        Ok(InitializeResult {
+            capabilities: ServerCapabilities {
+                call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                ..Default::default()
+            },
+            server_info: Some(ServerInfo {
+                name: "mock-lsp".to_string(),
+                version: None,
+            }),
+        })
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Result<Option<DocumentSymbolResponse>> {
+        let uri = params.text_document.uri;
+        let symbols = self
+            .project
+            .fixture
+            .functions
+            .iter()
+            .filter(|function| self.project.uri(function) == uri)
+            .map(|function| self.project.symbol(function))
+            .collect();
+        Ok(Some(DocumentSymbolResponse::Flat(symbols)))
+    }
+
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Option<Vec<SymbolInformation>>> {
+        let symbols = self
+            .project
+            .fixture
+            .functions
+            .iter()
+            .filter(|function| function.name.contains(&params.query))
+            .map(|function| self.project.symbol(function))
+            .collect();
+        Ok(Some(symbols))
+    }
+
+    async fn prepare_call_hierarchy(
+        &self,
+        params: CallHierarchyPrepareParams,
+    ) -> Result<Option<Vec<CallHierarchyItem>>> {
+        let position = params.text_document_position_params;
+        Ok(self
+            .project
+            .function_at(&position.text_document.uri, position.position.line)
+            .map(|function| vec![self.project.item(function)]))
+    }
+
+    async fn incoming_calls(
+        &self,
+        params: CallHierarchyIncomingCallsParams,
+    ) -> Result<Option<Vec<CallHierarchyIncomingCall>>> {
+        Ok(self.project.function_for(&params.item).map(|function| {
+            self.project
+                .calls(function, false)
+                .into_iter()
+                .map(|(from, from_ranges)| CallHierarchyIncomingCall { from, from_ranges })
+                .collect()
+        }))
+    }
+
+    async fn outgoing_calls(
+        &self,
+        params: CallHierarchyOutgoingCallsParams,
+    ) -> Result<Option<Vec<CallHierarchyOutgoingCall>>> {
+        Ok(self.project.function_for(&params.item).map(|function| {
+            self.project
+                .calls(function, true)
+                .into_iter()
+                .map(|(to, from_ranges)| CallHierarchyOutgoingCall { to, from_ranges })
+                .collect()
+        }))
+    }
+}
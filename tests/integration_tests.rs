@@ -1,5 +1,10 @@
+mod common;
+
 use assert_cmd::Command;
+use call_graph_generator::{CallGraph, Config, DotGenerator, LspClient};
+use common::{Fixture, MockServer, Project};
 use predicates::prelude::*;
+use std::path::Path;
 
 #[test]
 fn test_cli_help() {
@@ -19,3 +24,238 @@ fn test_cli_version() {
         .success()
         .stdout(predicate::str::contains("call-graph-generator"));
 }
+
+// ============================================================================
+// End-to-end Tests Against the Mock Server
+// ============================================================================
+
+/// A small project: `main` drives a parser that logs through a utility
+fn project_fixture() -> Fixture {
+    Fixture::new()
+        .function("main", "src/main.rs", 0)
+        .function("run", "src/main.rs", 2)
+        .function("parse", "src/parser.rs", 0)
+        .function("tokenize", "src/parser.rs", 4)
+        .function("log", "src/util.rs", 0)
+        .call("main", "run")
+        .call("run", "parse")
+        .call("run", "log")
+        .call("parse", "tokenize")
+        .call("tokenize", "log")
+}
+
+/// Run the binary to completion off the async runtime serving the mock
+async fn run_cli(project: &Path, args: Vec<String>) -> assert_cmd::assert::Assert {
+    let project = project.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        Command::cargo_bin("call-graph-generator")
+            .unwrap()
+            .current_dir(&project)
+            .arg("--project")
+            .arg(&project)
+            .args(args)
+            .assert()
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_cli_generates_graph_from_mock_server() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let fixture = project_fixture();
+    fixture.write_sources(temp_dir.path());
+    let server = MockServer::start(fixture, temp_dir.path()).await;
+
+    let output = temp_dir.path().join("graph.dot");
+    run_cli(
+        temp_dir.path(),
+        vec![
+            "--root".into(),
+            "main".into(),
+            "--lsp-server".into(),
+            server.url(),
+            "--output".into(),
+            output.display().to_string(),
+        ],
+    )
+    .await
+    .success();
+
+    let dot = std::fs::read_to_string(&output).unwrap();
+    assert!(dot.starts_with("digraph CallGraph {"));
+    for edge in [
+        "\"main::main\" -> \"main::run\"",
+        "\"main::run\" -> \"parser::parse\"",
+        "\"main::run\" -> \"util::log\"",
+        "\"parser::parse\" -> \"parser::tokenize\"",
+        "\"parser::tokenize\" -> \"util::log\"",
+    ] {
+        assert!(dot.contains(edge), "missing {edge} in\n{dot}");
+    }
+    assert_eq!(dot.matches(" -> ").count(), 5);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_library_pipeline_follows_callers_to_depth() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let fixture = project_fixture();
+    fixture.write_sources(temp_dir.path());
+    let server = MockServer::start(fixture, temp_dir.path()).await;
+
+    let config = Config::parse_from([
+        "call-graph-generator",
+        "--project",
+        temp_dir.path().to_str().unwrap(),
+        "--root",
+        "log",
+        "--direction",
+        "callers",
+        "--depth",
+        "1",
+        "--lsp-server",
+        &server.url(),
+    ]);
+    let mut client = LspClient::new(&config).await.unwrap();
+    let graph = CallGraph::analyze(&mut client, &config).await.unwrap();
+    client.shutdown().await.unwrap();
+
+    let mut names: Vec<_> = graph.nodes().map(|(_, node)| node.name.clone()).collect();
+    names.sort();
+    assert_eq!(names, ["log", "run", "tokenize"]);
+
+    let dot = DotGenerator::new(&config).unwrap().render(&graph);
+    assert!(dot.contains("\"main::run\" -> \"util::log\""));
+    assert!(dot.contains("\"parser::tokenize\" -> \"util::log\""));
+    assert!(!dot.contains("parser::parse"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_recorded_session_regenerates_graph_without_server() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let fixture = project_fixture();
+    fixture.write_sources(temp_dir.path());
+    let transcript = temp_dir.path().join("session.jsonl");
+    let recorded = temp_dir.path().join("recorded.dot");
+    // Replay happens in another checkout of the same sources
+    let checkout = tempfile::tempdir().unwrap();
+    fixture.write_sources(checkout.path());
+    let replayed = checkout.path().join("replayed.dot");
+
+    let server = MockServer::start(fixture, temp_dir.path()).await;
+    run_cli(
+        temp_dir.path(),
+        vec![
+            "--root".into(),
+            "main".into(),
+            "--lsp-server".into(),
+            server.url(),
+            "--record-lsp".into(),
+            transcript.display().to_string(),
+            "--output".into(),
+            recorded.display().to_string(),
+        ],
+    )
+    .await
+    .success();
+    drop(server);
+
+    run_cli(
+        checkout.path(),
+        vec![
+            "--root".into(),
+            "main".into(),
+            "--replay-lsp".into(),
+            transcript.display().to_string(),
+            "--output".into(),
+            replayed.display().to_string(),
+        ],
+    )
+    .await
+    .success();
+
+    let transcript = std::fs::read_to_string(&transcript).unwrap();
+    let root = temp_dir.path().canonicalize().unwrap();
+    assert!(!transcript.contains(&root.display().to_string()));
+    assert_eq!(
+        std::fs::read_to_string(&recorded).unwrap(),
+        std::fs::read_to_string(&replayed).unwrap()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_lsif_index_gives_the_same_graph_as_the_server() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let fixture = project_fixture();
+    fixture.write_sources(temp_dir.path());
+    let dump = temp_dir.path().join("dump.lsif");
+    fixture.write_lsif(temp_dir.path(), &dump);
+    let from_server = temp_dir.path().join("server.dot");
+    let from_index = temp_dir.path().join("index.dot");
+
+    let server = MockServer::start(fixture, temp_dir.path()).await;
+    run_cli(
+        temp_dir.path(),
+        vec![
+            "--root".into(),
+            "main".into(),
+            "--lsp-server".into(),
+            server.url(),
+            "--output".into(),
+            from_server.display().to_string(),
+        ],
+    )
+    .await
+    .success();
+    drop(server);
+
+    run_cli(
+        temp_dir.path(),
+        vec![
+            "--root".into(),
+            "main".into(),
+            "--index".into(),
+            dump.display().to_string(),
+            "--output".into(),
+            from_index.display().to_string(),
+        ],
+    )
+    .await
+    .success();
+
+    assert_eq!(
+        std::fs::read_to_string(&from_server).unwrap(),
+        std::fs::read_to_string(&from_index).unwrap()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_custom_source_gives_the_same_graph_as_the_server() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let fixture = project_fixture();
+    fixture.write_sources(temp_dir.path());
+    let server = MockServer::start(fixture.clone(), temp_dir.path()).await;
+
+    let config = Config::parse_from([
+        "call-graph-generator",
+        "--project",
+        temp_dir.path().to_str().unwrap(),
+        "--direction",
+        "both",
+        "--lsp-server",
+        &server.url(),
+    ]);
+    let mut client = LspClient::new(&config).await.unwrap();
+    let from_server = CallGraph::analyze(&mut client, &config).await.unwrap();
+    client.shutdown().await.unwrap();
+
+    let mut project = Project::new(fixture, temp_dir.path());
+    let from_source = CallGraph::analyze(&mut project, &config).await.unwrap();
+
+    let generator = DotGenerator::new(&config).unwrap();
+    assert!(from_source.edge_count() > 0);
+    assert_eq!(
+        generator.render(&from_server),
+        generator.render(&from_source)
+    );
+}